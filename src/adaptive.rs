@@ -0,0 +1,94 @@
+//! Content-adaptive encoding for `--adaptive`.
+//!
+//! Pages are classified by pixels of a small copy: pure black-and-white line-art is stored
+//! as lossless grayscale WebP, which is both smaller and sharper than lossy formats for it,
+//! grayscale pages (e.g. screentone) lose the chroma, color pages are encoded as configured.
+//! Color and grayscale pages get their own quality with `--quality-color` and `--quality-gray`.
+
+use image::DynamicImage;
+use image::ImageOutputFormat;
+
+use crate::cli::Config;
+
+
+/// Size of the copy to classify.
+const SAMPLE: u32 = 256;
+/// Pixel is colored when its channels differ more than this.
+const CHROMA_THRESHOLD: u8 = 24;
+/// Page is colored when this fraction of pixels is colored.
+const COLOR_FRACTION: f64 = 0.01;
+/// Gray pixel is black or white when it is this close to either end.
+const BILEVEL_MARGIN: u8 = 32;
+/// Page is black-and-white when this fraction of pixels is black or white.
+const BILEVEL_FRACTION: f64 = 0.98;
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PageKind {
+	/// Black-and-white line-art.
+	Bilevel,
+	Grayscale,
+	Color,
+}
+
+
+pub fn classify(image: &DynamicImage) -> PageKind {
+	let small = image.thumbnail(SAMPLE, SAMPLE).to_rgb8();
+	let total = (small.width() * small.height()).max(1) as f64;
+	let (mut colored, mut extreme) = (0, 0);
+	for pixel in small.pixels() {
+		let [r, g, b] = pixel.0;
+		if r.max(g).max(b) - r.min(g).min(b) > CHROMA_THRESHOLD {
+			colored += 1;
+		}
+		let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+		if luma < BILEVEL_MARGIN as u32 || luma > (u8::MAX - BILEVEL_MARGIN) as u32 {
+			extreme += 1;
+		}
+	}
+
+	if colored as f64 / total > COLOR_FRACTION {
+		PageKind::Color
+	} else if extreme as f64 / total > BILEVEL_FRACTION {
+		PageKind::Bilevel
+	} else {
+		PageKind::Grayscale
+	}
+}
+
+
+/// Image and encoding settings for the page.
+pub fn tune(image: DynamicImage, cfg: &Config) -> (DynamicImage, Config) {
+	let kind = classify(&image);
+	trace!("page kind: {kind:?}");
+	let gray = |image: DynamicImage| {
+		if image.color().has_alpha() {
+			DynamicImage::ImageLumaA8(image.to_luma_alpha8())
+		} else {
+			DynamicImage::ImageLuma8(image.to_luma8())
+		}
+	};
+	// quality of the class wins over the one of the format:
+	let with_quality = |quality: Option<u8>| match quality {
+		Some(quality) => {
+			Config { quality,
+			         quality_avif: None,
+			         quality_webp: None,
+			         quality_jpeg: None,
+			         ..cfg.clone() }
+		},
+		None => cfg.clone(),
+	};
+	match kind {
+		PageKind::Color => (image, with_quality(cfg.quality_color)),
+		PageKind::Grayscale => (gray(image), with_quality(cfg.quality_gray)),
+		// the command encodes all pages, and `page_ext` names them after it:
+		PageKind::Bilevel if cfg.encoder_cmd.is_some() => (gray(image), cfg.clone()),
+		PageKind::Bilevel => {
+			let cfg = Config { format: ImageOutputFormat::WebP,
+			                   lossless: true,
+			                   ..cfg.clone() };
+			(gray(image), cfg)
+		},
+	}
+}
@@ -0,0 +1,154 @@
+//! Image adjustments: tone with `--autolevel`, `--brightness`, `--contrast` and `--gamma`,
+//! and `--sharpen`.
+//!
+//! Tone adjustments map every color channel value independently,
+//! so they are combined into a single lookup table applied in one pass.
+
+use image::DynamicImage;
+
+use crate::cli::Config;
+
+
+/// Fraction of darkest and brightest values ignored by auto-levels, for dust and specks.
+const AUTOLEVEL_CLIP: f64 = 0.005;
+/// Blur radius of unsharp mask, small enough for details of downscaled pages.
+const SHARPEN_SIGMA: f32 = 1.0;
+
+
+pub fn parse_positive(s: &str) -> Result<f32, String> {
+	s.parse::<f32>()
+	 .ok()
+	 .filter(|value| value.is_finite() && *value > 0.0)
+	 .ok_or_else(|| format!("Invalid value '{s}', expected a positive number"))
+}
+
+
+/// Whether any adjustment is configured.
+fn enabled(cfg: &Config) -> bool {
+	cfg.autolevel || cfg.gamma.is_some() || cfg.brightness != 0 || cfg.contrast != 0
+}
+
+
+pub fn apply(image: DynamicImage, cfg: &Config) -> DynamicImage {
+	if !enabled(cfg) {
+		return image;
+	}
+	let mut image = to_8bit(image);
+	let (channels, colors) = layout(&image);
+	let data = bytes_mut(&mut image);
+
+	let (low, high) = if cfg.autolevel {
+		levels(data, channels, colors)
+	} else {
+		(0, 255)
+	};
+	trace!("adjusting: levels {low}..{high}, brightness {}, contrast {}, gamma {:?}",
+	       cfg.brightness,
+	       cfg.contrast,
+	       cfg.gamma);
+	let lut = lut(low, high, cfg);
+	for pixel in data.chunks_exact_mut(channels) {
+		for value in &mut pixel[..colors] {
+			*value = lut[*value as usize];
+		}
+	}
+	image
+}
+
+
+/// Unsharp mask with the given amount: the difference from the blurred image is added to the image.
+pub fn sharpen(image: DynamicImage, amount: f32) -> DynamicImage {
+	let mut image = to_8bit(image);
+	let blurred = to_8bit(image.blur(SHARPEN_SIGMA));
+	let (channels, colors) = layout(&image);
+	trace!("sharpening {}x{} by {amount}", image.width(), image.height());
+
+	let data = bytes_mut(&mut image);
+	for (pixel, blurred) in data.chunks_exact_mut(channels).zip(blurred.as_bytes().chunks_exact(channels)) {
+		for (value, blurred) in pixel[..colors].iter_mut().zip(blurred) {
+			let diff = *value as f32 - *blurred as f32;
+			*value = (*value as f32 + diff * amount).round().clamp(0.0, 255.0) as u8;
+		}
+	}
+	image
+}
+
+
+/// Image with 8 bits per channel, as is if it already is.
+fn to_8bit(image: DynamicImage) -> DynamicImage {
+	match image {
+		DynamicImage::ImageLuma8(_) |
+		DynamicImage::ImageLumaA8(_) |
+		DynamicImage::ImageRgb8(_) |
+		DynamicImage::ImageRgba8(_) => image,
+		image if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8()),
+		image => DynamicImage::ImageRgb8(image.to_rgb8()),
+	}
+}
+
+/// Channels per pixel and how many of them are color, not alpha.
+fn layout(image: &DynamicImage) -> (usize, usize) {
+	match image {
+		DynamicImage::ImageLuma8(_) => (1, 1),
+		DynamicImage::ImageLumaA8(_) => (2, 1),
+		DynamicImage::ImageRgb8(_) => (3, 3),
+		_ => (4, 3),
+	}
+}
+
+fn bytes_mut(image: &mut DynamicImage) -> &mut [u8] {
+	match image {
+		DynamicImage::ImageLuma8(buf) => buf,
+		DynamicImage::ImageLumaA8(buf) => buf,
+		DynamicImage::ImageRgb8(buf) => buf,
+		DynamicImage::ImageRgba8(buf) => buf,
+		_ => unreachable!("converted with `to_8bit`"),
+	}
+}
+
+
+/// Darkest and brightest values of color channels, ignoring `AUTOLEVEL_CLIP` of outliers.
+fn levels(data: &[u8], channels: usize, colors: usize) -> (u8, u8) {
+	let mut histogram = [0u64; 256];
+	for pixel in data.chunks_exact(channels) {
+		for value in &pixel[..colors] {
+			histogram[*value as usize] += 1;
+		}
+	}
+	let total: u64 = histogram.iter().sum();
+	let clip = (total as f64 * AUTOLEVEL_CLIP) as u64;
+
+	let low = percentile(&histogram, clip, 0..256);
+	let high = percentile(&histogram, clip, (0..256).rev());
+	if high > low {
+		(low, high)
+	} else {
+		(0, 255)
+	}
+}
+
+
+/// First value in the given order after `clip` values are skipped.
+fn percentile(histogram: &[u64; 256], clip: u64, mut values: impl Iterator<Item = usize>) -> u8 {
+	let mut sum = 0;
+	values.find(|i| {
+		      sum += histogram[*i];
+		      sum > clip
+	      })
+	      .unwrap_or_default() as u8
+}
+
+
+fn lut(low: u8, high: u8, cfg: &Config) -> [u8; 256] {
+	let contrast = 1.0 + cfg.contrast as f32 / 100.0;
+	let gamma = cfg.gamma.unwrap_or(1.0);
+	let mut lut = [0; 256];
+	for (i, value) in lut.iter_mut().enumerate() {
+		let mut x = (i as f32 - low as f32) / (high - low) as f32;
+		x += cfg.brightness as f32 / 255.0;
+		x = (x - 0.5) * contrast + 0.5;
+		x = x.clamp(0.0, 1.0).powf(1.0 / gamma);
+		*value = (x * 255.0).round() as u8;
+	}
+	lut
+}
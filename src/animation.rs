@@ -0,0 +1,61 @@
+//! Animated pages (GIF, APNG, animated WebP) for `--animation`.
+
+use std::io::Cursor;
+use image::{AnimationDecoder, Frame, ImageError, ImageFormat, ImageResult};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::EncodingError;
+
+use crate::cli::Config;
+
+
+/// All frames, `None` if the image is not animated.
+pub fn frames(data: &[u8], format: ImageFormat) -> ImageResult<Option<Vec<Frame>>> {
+	let frames = match format {
+		ImageFormat::Gif => GifDecoder::new(Cursor::new(data))?.into_frames().collect_frames()?,
+		ImageFormat::Png => {
+			let decoder = PngDecoder::new(Cursor::new(data))?;
+			if !decoder.is_apng() {
+				return Ok(None);
+			}
+			decoder.apng().into_frames().collect_frames()?
+		},
+		ImageFormat::WebP => {
+			let decoder = WebPDecoder::new(Cursor::new(data))?;
+			if !decoder.has_animation() {
+				return Ok(None);
+			}
+			decoder.into_frames().collect_frames()?
+		},
+		_ => return Ok(None),
+	};
+	Ok((frames.len() > 1).then_some(frames))
+}
+
+
+/// Encode frames as animated WebP.
+pub fn encode_webp(frames: &[Frame], cfg: &Config) -> ImageResult<Vec<u8>> {
+	let error = |message: String| ImageError::Encoding(EncodingError::new(ImageFormat::WebP.into(), message));
+
+	let mut config = webp::WebPConfig::new().map_err(|_| error("Invalid WebP config".to_owned()))?;
+	config.lossless = cfg.lossless as _;
+	config.quality = if cfg.lossless { 75.0 } else { cfg.quality as _ };
+	if let Some(method) = cfg.webp.method {
+		config.method = method as _;
+	}
+
+	let (width, height) = frames[0].buffer().dimensions();
+	let mut encoder = webp::AnimEncoder::new(width, height, &config);
+	let mut timestamp = 0;
+	for frame in frames {
+		if frame.buffer().dimensions() != (width, height) {
+			return Err(error("Frames of different size".to_owned()));
+		}
+		encoder.add_frame(webp::AnimFrame::from_rgba(frame.buffer(), width, height, timestamp));
+		let (numer, denom) = frame.delay().numer_denom_ms();
+		timestamp += (numer / denom.max(1)) as i32;
+	}
+	let encoded = encoder.try_encode().map_err(|err| error(format!("{err:?}")))?;
+	Ok(encoded.to_vec())
+}
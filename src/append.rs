@@ -0,0 +1,70 @@
+//! Incremental update of previously converted zip archives.
+//!
+//! With `--append` every written zip entry carries the hash of its source entry and the settings
+//! in the entry comment, so unchanged pages converted with the same settings can be copied
+//! from the previous output instead of being converted again. Pages of source entries
+//! which are gone or changed are dropped.
+
+use std::collections::HashMap;
+use std::path::Path;
+use async_zip::tokio::read::fs::ZipFileReader;
+
+use crate::error::Error;
+
+
+/// Previous output archive.
+pub struct Previous {
+	reader: ZipFileReader,
+	/// Source hash => entry indices, more than one for split spreads
+	by_source: HashMap<String, Vec<usize>>,
+}
+
+
+impl Previous {
+	/// Open existing output, `None` if there is nothing to update.
+	pub async fn open(path: impl AsRef<Path>) -> Result<Option<Self>, Error> {
+		let path = path.as_ref();
+		if !tokio::fs::try_exists(path).await? {
+			return Ok(None);
+		}
+		debug!("opening previous output: '{}'", path.display());
+
+		let reader = ZipFileReader::new(path).await?;
+		let mut by_source: HashMap<_, Vec<_>> = HashMap::new();
+		for (i, entry) in reader.file().entries().iter().enumerate() {
+			match entry.entry().comment().as_str() {
+				Ok(hash) if !hash.is_empty() => by_source.entry(hash.to_owned()).or_default().push(i),
+				_ => {},
+			}
+		}
+		Ok(Some(Self { reader, by_source }))
+	}
+
+
+	/// Entries converted from the source entry with the given hash.
+	pub fn find(&self, source_hash: &str) -> Option<&[usize]> { self.by_source.get(source_hash).map(Vec::as_slice) }
+
+
+	/// Names of entries not converted from source entries, e.g. of generated ComicInfo.xml,
+	/// with their indices.
+	pub fn generated(&self) -> impl Iterator<Item = (usize, String)> + '_ {
+		self.reader
+		    .file()
+		    .entries()
+		    .iter()
+		    .enumerate()
+		    .filter(|(_, entry)| entry.entry().comment().as_str().map_or(true, str::is_empty))
+		    .filter_map(|(i, entry)| Some((i, entry.entry().filename().as_str().ok()?.to_owned())))
+	}
+
+
+	/// Read name and data of the entry.
+	pub async fn read(&self, index: usize) -> Result<(String, Vec<u8>), Error> {
+		let mut reader = self.reader.reader_with_entry(index).await?;
+		let entry = reader.entry();
+		let name = entry.filename().clone().into_string()?;
+		let mut data = Vec::with_capacity(entry.uncompressed_size() as _);
+		reader.read_to_end_checked(&mut data).await?;
+		Ok((name, data))
+	}
+}
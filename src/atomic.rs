@@ -0,0 +1,48 @@
+//! Atomic output: archives are written to `name.ext.part` next to the destination
+//! and renamed into place only when complete, so interrupted runs never leave
+//! half-written archives looking valid.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+
+/// Partially written file, removed on drop unless committed.
+pub struct PartFile {
+	path: PathBuf,
+	part: PathBuf,
+	committed: bool,
+}
+
+
+impl PartFile {
+	pub fn new(path: impl AsRef<Path>) -> Self {
+		let path = path.as_ref().to_owned();
+		let mut part = path.clone().into_os_string();
+		part.push(".part");
+		Self { path,
+		       part: part.into(),
+		       committed: false }
+	}
+
+	/// Where the data is written until commit.
+	pub fn part(&self) -> &Path { &self.part }
+
+	/// Move complete file into place.
+	pub async fn commit(mut self) -> Result<(), Error> {
+		trace!("renaming '{}' to '{}'", self.part.display(), self.path.display());
+		tokio::fs::rename(&self.part, &self.path).await?;
+		self.committed = true;
+		Ok(())
+	}
+}
+
+
+impl Drop for PartFile {
+	fn drop(&mut self) {
+		if !self.committed {
+			debug!("removing incomplete '{}'", self.part.display());
+			std::fs::remove_file(&self.part).ok();
+		}
+	}
+}
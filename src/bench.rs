@@ -0,0 +1,194 @@
+//! `bench` command: convert a sample of pages with each combination of formats and qualities,
+//! and compare sizes, encode times and optionally SSIM.
+
+use std::time::{Duration, Instant};
+use futures::{stream, StreamExt};
+use indicatif::HumanBytes;
+
+use crate::cli::{BenchArgs, Config, FormatFileExt};
+use crate::error::Error;
+use crate::{paths, pool, similar};
+
+
+struct Sample {
+	name: String,
+	data: Vec<u8>,
+}
+
+
+#[derive(Default)]
+struct Outcome {
+	size: u64,
+	/// Encode time of all sample pages, summed over threads.
+	time: Duration,
+	/// SSIM of pages which could be decoded back.
+	ssim: Vec<f64>,
+	failed: usize,
+}
+
+
+pub async fn run(args: &BenchArgs) -> Result<(), Error> {
+	let cfg = &args.config;
+	let (reader, entries, _) = crate::archive_reader(&args.input, cfg.password.as_deref(), &cfg.junk_filter()).await?;
+	let numbers = paths::page_numbers(&entries);
+	let mut samples = Vec::new();
+	for entry in entries {
+		if numbers.get(&entry.index).is_some_and(|n| (n - 1) % args.sample_every as usize == 0) {
+			let mut data = Vec::new();
+			reader.read_file(&entry.uri, &mut data)?;
+			samples.push(Sample { name: entry.uri, data });
+		}
+	}
+	if samples.is_empty() {
+		return Err(format!("No pages in '{}'", args.input.display()).into());
+	}
+	let source_size: u64 = samples.iter().map(|sample| sample.data.len() as u64).sum();
+	info!(
+	      "Sample: {} of {} pages, {}",
+	      samples.len(),
+	      numbers.len(),
+	      HumanBytes(source_size)
+	);
+
+	println!(
+	         "{:<8}{:>8}{:>12}{:>8}{:>10}{:>8}",
+	         "format", "quality", "size", "ratio", "time", "ssim"
+	);
+	for format in &args.formats {
+		for quality in &args.qualities {
+			// `--quality-*` overrides would make every quality the same:
+			let cfg = Config { format: format.clone(),
+			                   quality: *quality,
+			                   quality_avif: None,
+			                   quality_webp: None,
+			                   quality_jpeg: None,
+			                   quality_color: None,
+			                   quality_gray: None,
+			                   reuse_similar: false,
+			                   ..cfg.clone() };
+			let res = bench(&args.input, &samples, &cfg, args.ssim).await;
+			let ssim = match res.ssim.len() {
+				0 => "-".to_owned(),
+				n => format!("{:.4}", res.ssim.iter().sum::<f64>() / n as f64),
+			};
+			println!(
+			         "{:<8}{:>8}{:>12}{:>7.1}%{:>9.2}s{:>8}",
+			         cfg.format.ext(),
+			         quality,
+			         HumanBytes(res.size).to_string(),
+			         res.size as f64 / source_size as f64 * 100.0,
+			         res.time.as_secs_f64(),
+			         ssim
+			);
+			if res.failed > 0 {
+				warn!("{} of {} pages failed with {} {quality}", res.failed, samples.len(), cfg.format.ext());
+			}
+		}
+	}
+	Ok(())
+}
+
+
+async fn bench(archive: &std::path::Path, samples: &[Sample], cfg: &Config, ssim: bool) -> Outcome {
+	let encode = |sample: &Sample| {
+		let cfg = cfg.clone();
+		let data = sample.data.clone();
+		let name = sample.name.clone();
+		let origin = similar::Origin { archive: archive.to_owned(),
+		                               page: sample.name.clone() };
+		async move {
+			pool::run(move || {
+				let started = Instant::now();
+				let transcoded = crate::transcode(cfg, data.clone(), name, None, origin, &Default::default());
+				let time = started.elapsed();
+				let ssim = match transcoded.as_ref() {
+					Ok(pages) if ssim && pages.len() == 1 => page_ssim(&data, &pages[0].data),
+					_ => None,
+				};
+				transcoded.map(|pages| (pages.iter().map(|page| page.data.len() as u64).sum::<u64>(), time, ssim))
+			}).await
+		}
+	};
+	let results: Vec<_> = stream::iter(samples).map(encode)
+	                                           .buffer_unordered(cfg.jobs.max(1))
+	                                           .collect()
+	                                           .await;
+
+	let mut total = Outcome::default();
+	for res in results {
+		match res {
+			Ok(Ok((size, time, ssim))) => {
+				total.size += size;
+				total.time += time;
+				total.ssim.extend(ssim);
+			},
+			Ok(Err(err)) => {
+				debug!("{err}");
+				total.failed += 1;
+			},
+			Err(err) => {
+				debug!("{err}");
+				total.failed += 1;
+			},
+		}
+	}
+	total
+}
+
+
+/// SSIM of the output against the source scaled to the same size, `None` if the output can't be decoded.
+fn page_ssim(source: &[u8], output: &[u8]) -> Option<f64> {
+	let output = image::load_from_memory(output).ok()?.to_luma8();
+	let source = image::load_from_memory(source).ok()?;
+	let source = match source.width() == output.width() && source.height() == output.height() {
+		true => source.to_luma8(),
+		false => {
+			source.resize_exact(output.width(), output.height(), image::imageops::FilterType::Triangle)
+			      .to_luma8()
+		},
+	};
+	Some(ssim(&source, &output))
+}
+
+
+/// Mean SSIM of luma over 8x8 windows.
+pub(crate) fn ssim(a: &image::GrayImage, b: &image::GrayImage) -> f64 {
+	const WINDOW: u32 = 8;
+	const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+	const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+	let (width, height) = a.dimensions();
+	let mut sum = 0.0;
+	let mut windows = 0;
+	for y in (0..height.saturating_sub(WINDOW - 1)).step_by(WINDOW as usize) {
+		for x in (0..width.saturating_sub(WINDOW - 1)).step_by(WINDOW as usize) {
+			let pixels = || (y..y + WINDOW).flat_map(move |y| (x..x + WINDOW).map(move |x| (x, y)));
+			let n = (WINDOW * WINDOW) as f64;
+			let (mut mean_a, mut mean_b) = (0.0, 0.0);
+			for (x, y) in pixels() {
+				mean_a += a.get_pixel(x, y).0[0] as f64;
+				mean_b += b.get_pixel(x, y).0[0] as f64;
+			}
+			mean_a /= n;
+			mean_b /= n;
+			let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+			for (x, y) in pixels() {
+				let da = a.get_pixel(x, y).0[0] as f64 - mean_a;
+				let db = b.get_pixel(x, y).0[0] as f64 - mean_b;
+				var_a += da * da;
+				var_b += db * db;
+				cov += da * db;
+			}
+			var_a /= n - 1.0;
+			var_b /= n - 1.0;
+			cov /= n - 1.0;
+			sum += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2)) /
+			       ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+			windows += 1;
+		}
+	}
+	match windows {
+		0 => 1.0,
+		n => sum / n as f64,
+	}
+}
@@ -0,0 +1,61 @@
+//! Total output size cap of `--max-total-output`.
+//!
+//! Bytes are counted as they are written, so archives in progress can't overshoot the cap:
+//! the write which would cross it fails with [`Error::CapReached`] and its output is removed.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::error::Error;
+
+
+static CAP: OnceLock<u64> = OnceLock::new();
+/// Bytes written by all outputs, of removed ones not counted.
+static WRITTEN: AtomicU64 = AtomicU64::new(0);
+/// Some write didn't fit, no archive should be started anymore.
+static REACHED: AtomicBool = AtomicBool::new(false);
+
+
+/// Bytes written by one output, not counted anymore when dropped before [`Charge::keep`].
+#[derive(Default)]
+pub struct Charge(u64);
+
+
+/// Limit total size of outputs to `cap` bytes.
+pub fn init(cap: u64) { CAP.set(cap).ok(); }
+
+
+/// Whether a write didn't fit under the cap.
+pub fn reached() -> bool { REACHED.load(Ordering::SeqCst) }
+
+
+impl Charge {
+	/// Count `len` bytes about to be written, or fail if they would cross the cap.
+	pub fn add(&mut self, len: u64) -> Result<(), Error> {
+		let Some(&cap) = CAP.get() else {
+			return Ok(());
+		};
+		let fits = WRITTEN.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |written| {
+			                  Some(written + len).filter(|&total| total <= cap)
+		                  });
+		if fits.is_err() {
+			REACHED.store(true, Ordering::SeqCst);
+			return Err(Error::CapReached);
+		}
+		self.0 += len;
+		Ok(())
+	}
+
+
+	/// Output is complete, its bytes stay counted.
+	pub fn keep(mut self) { self.0 = 0; }
+}
+
+
+impl Drop for Charge {
+	fn drop(&mut self) {
+		if self.0 > 0 {
+			WRITTEN.fetch_sub(self.0, Ordering::SeqCst);
+		}
+	}
+}
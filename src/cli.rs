@@ -1,26 +1,100 @@
 extern crate clap;
-use clap::{Parser, ValueEnum};
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use indicatif::{MultiProgress, ProgressStyle, ProgressBar};
 use std::{path::PathBuf, borrow::Cow, sync::Arc};
 
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, args_override_self = true)]
 pub struct Args {
-	#[arg(short, long, action = clap::ArgAction::Count, default_value_t = 0)]
+	#[command(subcommand)]
+	pub command: Option<Command>,
+
+	#[arg(short, long, action = clap::ArgAction::Count, default_value_t = 0, global = true)]
 	pub verbose: u8,
 
+	/// Also write all log records, regardless of `--verbose`, to the file. It is appended to.
+	#[arg(long, value_name = "PATH", global = true)]
+	#[arg(env = "COMIC_REPACK_LOG_FILE")]
+	pub log_file: Option<PathBuf>,
+
+	/// Where to store failed archives for `retry-failed`.
+	#[arg(long, value_name = "PATH", default_value = "comic-repack.failed.json", global = true)]
+	#[arg(env = "COMIC_REPACK_FAILURES_FILE")]
+	pub failures_file: PathBuf,
+
+	/// Number of threads decoding and encoding pages. Defaults to num of physical CPUs.
+	/// Pages in flight are limited by `--jobs` separately.
+	#[arg(long, value_name = "THREADS", global = true, default_value_t = num_cpus::get_physical() as u32)]
+	#[arg(env = "COMIC_REPACK_ENCODE_THREADS")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub encode_threads: u32,
+
+	/// Directory for temporary files: large pages waiting to be written, 7z chunks waiting for compression
+	/// and uploads of `serve --http`. Large buffers stay in memory unless this is set.
+	#[arg(long, value_name = "DIR", global = true)]
+	#[arg(env = "COMIC_REPACK_TEMP_DIR")]
+	pub temp_dir: Option<PathBuf>,
+
+	/// Buffers larger than this are written to `--temp-dir` instead of kept in memory, e.g. `512K`.
+	#[arg(long, value_name = "SIZE", global = true, default_value = "1M")]
+	#[arg(env = "COMIC_REPACK_SPILL_ABOVE")]
+	#[arg(value_parser = parse_size)]
+	pub spill_above: u64,
+
+	/// Arguments of `convert`, which is the default command.
+	#[clap(flatten)]
+	pub convert: ConvertArgs,
+}
+
+impl Args {
+	/// Arguments of conversion, given either to `convert` or without a command.
+	pub fn into_convert(self) -> ConvertArgs {
+		match self.command {
+			Some(Command::Convert(args)) | Some(Command::Library { convert: args, .. }) => args,
+			_ => self.convert,
+		}
+	}
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
 	#[clap(flatten)]
 	pub config: Config,
 
-	/// Input files.
+	/// Input files. Use `input.cbz=Output Name.cbz` to set the output file name.
 	/// .
 	#[arg(last = false, value_name = "FILES")]
 	pub input: Vec<PathBuf>,
 
-	/// How many pairs or input-output files will in parallel processing.
-	#[arg(short = 'p', long, value_name = "JOBS", default_value_t = 1)]
-	pub jobs_fs: usize,
+	/// Read more inputs from the file, one per line, or from stdin with `-`.
+	/// For lists too long for the command line, e.g. produced by `find`.
+	#[arg(long, value_name = "PATH")]
+	#[arg(env = "COMIC_REPACK_FILES_FROM")]
+	pub files_from: Option<PathBuf>,
+
+	/// Inputs of `--files-from` are separated by NUL instead of newline, as of `find -print0`.
+	#[arg(short = '0', long = "null", requires = "files_from")]
+	pub null: bool,
+
+	/// Scan directories given as inputs for archives, including subdirectories.
+	#[arg(short = 'r', long)]
+	#[arg(env = "COMIC_REPACK_RECURSIVE")]
+	pub recursive: bool,
+
+	/// Extensions of archives found in directories with `--recursive`, case-insensitive.
+	#[arg(long, value_name = "EXT", value_delimiter = ',')]
+	#[arg(default_values_t = ["cbz", "cbr", "cb7", "cbt", "zip", "rar", "7z", "tar"].map(String::from))]
+	#[arg(env = "COMIC_REPACK_INPUT_EXT")]
+	pub input_ext: Vec<String>,
+
+	/// Most archives converted in parallel, defaults to `--max-cpu`. Conversion starts with one archive
+	/// and adds more while encode threads are idle waiting for reading and writing, fewer when they are saturated.
+	#[arg(short = 'p', long, value_name = "JOBS")]
+	#[arg(env = "COMIC_REPACK_JOBS_FS")]
+	pub jobs_fs: Option<usize>,
 
 	/// Output directory. Defaults to the current working directory,
 	/// so changing input files inplace can be possible and cause a problem. TODO: fix it!
@@ -28,9 +102,414 @@ pub struct Args {
 	/// .
 	#[arg(last = true, value_name = "OUT DIR")]
 	pub output: Option<PathBuf>,
+
+	/// Output file name for the input, relative to the output directory.
+	/// Extension defaults to the output one. Can be repeated.
+	#[arg(long, num_args = 2, value_names = ["INPUT", "NAME"])]
+	pub rename: Vec<String>,
+
+	/// Skip input archives smaller than this, e.g. `30M`, which are usually optimized already.
+	#[arg(long, value_name = "SIZE")]
+	#[arg(env = "COMIC_REPACK_MIN_INPUT_SIZE")]
+	#[arg(value_parser = parse_size)]
+	pub min_input_size: Option<u64>,
+
+	/// Skip input archives larger than this, e.g. `2G`, to convert them separately with other settings.
+	#[arg(long, value_name = "SIZE")]
+	#[arg(env = "COMIC_REPACK_MAX_INPUT_SIZE")]
+	#[arg(value_parser = parse_size)]
+	pub max_input_size: Option<u64>,
+
+	/// Order of converting archives, e.g. `size` to finish small ones first for quick feedback.
+	#[arg(long, value_name = "ORDER", default_value = "name")]
+	#[arg(env = "COMIC_REPACK_ORDER_BY")]
+	#[arg(value_enum)]
+	pub order_by: BatchOrder,
+
+	/// Reverse `--order-by`, e.g. largest or newest archives first.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_REVERSE")]
+	pub reverse: bool,
+
+	/// Write JSON report with per-archive page stats, histograms and outliers.
+	#[arg(long, value_name = "PATH")]
+	#[arg(env = "COMIC_REPACK_REPORT")]
+	pub report: Option<PathBuf>,
+
+	/// Run the command after each converted archive, e.g. `curl -X POST http://komga:25600/api/v1/libraries/1/scan`.
+	/// Source, output, sizes and status are in `COMIC_*` environment variables, see the README.
+	#[arg(long, value_name = "COMMAND")]
+	#[arg(env = "COMIC_REPACK_ON_SUCCESS")]
+	#[arg(value_parser = crate::hooks::parse_command)]
+	pub on_success: Option<crate::hooks::HookCommand>,
+
+	/// Run the command after each failed archive, with the error in `COMIC_ERROR`.
+	#[arg(long, value_name = "COMMAND")]
+	#[arg(env = "COMIC_REPACK_ON_FAILURE")]
+	#[arg(value_parser = crate::hooks::parse_command)]
+	pub on_failure: Option<crate::hooks::HookCommand>,
+
+	/// POST JSON with source, output, sizes and status to this `http://` URL after each archive.
+	#[arg(long, value_name = "URL")]
+	#[arg(env = "COMIC_REPACK_WEBHOOK")]
+	#[arg(value_parser = crate::hooks::parse_webhook)]
+	pub webhook: Option<crate::hooks::Webhook>,
+
+	/// Show a desktop notification with counts and saved size when the batch finishes,
+	/// or after each archive with `--notify archive`.
+	#[arg(long, value_name = "WHEN", num_args = 0..=1, default_missing_value = "batch")]
+	#[arg(env = "COMIC_REPACK_NOTIFY")]
+	#[arg(value_enum)]
+	pub notify: Option<Notify>,
+
+	/// Limit total size of outputs, e.g. `500G`. Archives in progress whose next page wouldn't fit
+	/// are cancelled and removed, and no new ones are started. Archives not converted
+	/// are stored to the failures file, so `retry-failed` resumes the batch.
+	#[arg(long, value_name = "SIZE")]
+	#[arg(env = "COMIC_REPACK_MAX_TOTAL_OUTPUT")]
+	#[arg(value_parser = parse_size)]
+	pub max_total_output: Option<u64>,
+
+	/// What to do when the output directory has less free space than the sources take,
+	/// which is the most the outputs can take, limited by `--max-total-output`.
+	#[arg(long, value_name = "MODE", default_value = "refuse")]
+	#[arg(env = "COMIC_REPACK_DISK_CHECK")]
+	#[arg(value_enum)]
+	pub disk_check: DiskCheck,
+
+	/// Limit memory of pages in flight across all jobs, e.g. `4G`.
+	/// Pages wait for memory before conversion, so a large scan can't run out of it with many jobs.
+	#[arg(long, value_name = "SIZE")]
+	#[arg(env = "COMIC_REPACK_MAX_MEMORY")]
+	#[arg(value_parser = parse_size)]
+	pub max_memory: Option<u64>,
+
+	/// Write progress events as newline-delimited JSON to the file, e.g. `/dev/fd/3`, or `-` for stdout.
+	#[arg(long, value_name = "PATH")]
+	#[arg(env = "COMIC_REPACK_PROGRESS_JSON")]
+	pub progress_json: Option<PathBuf>,
+
+	/// How to show progress: `bars` in a terminal, `json` lines like `{"archive": "...", "done": 42, "total": 200}`
+	/// printed every few seconds, or `none`. `auto` shows bars if stdout is a terminal, otherwise JSON lines.
+	#[arg(long, value_name = "MODE", default_value = "auto")]
+	#[arg(env = "COMIC_REPACK_PROGRESS")]
+	#[arg(value_enum)]
+	pub progress: Progress,
+
+	/// Skip archives finished by an interrupted run according to the journal, restart the rest.
+	#[arg(long)]
+	#[arg(env = "COMIC_REPACK_RESUME")]
+	pub resume: bool,
+
+	/// Read back every entry of outputs after writing, failing the archive if something is unreadable.
+	#[arg(long)]
+	#[arg(env = "COMIC_REPACK_VERIFY")]
+	pub verify: bool,
+
+	/// Write a manifest with hashes and sizes of the input and outputs, and the settings,
+	/// next to each output: `name.cbz.sha256.json`.
+	#[arg(long, value_name = "ALGORITHM")]
+	#[arg(env = "COMIC_REPACK_CHECKSUMS")]
+	#[arg(value_enum)]
+	pub checksums: Option<Checksum>,
+
+	/// Delete the input after its output is written and synced, and verified with `--verify`.
+	#[arg(long, conflicts_with = "trash_source")]
+	#[arg(env = "COMIC_REPACK_DELETE_SOURCE")]
+	pub delete_source: bool,
+
+	/// Move the input to the trash after its output is written, as `--delete-source`.
+	#[arg(long)]
+	#[arg(env = "COMIC_REPACK_TRASH_SOURCE")]
+	pub trash_source: bool,
+
+	/// Only estimate output sizes, converting every Nth page of each archive, default 10.
+	/// Prints the projection for the current settings and writes nothing.
+	#[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10")]
+	#[arg(env = "COMIC_REPACK_ESTIMATE")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub estimate: Option<u32>,
+
+	/// Journal of started and finished archives for `--resume`, removed when the batch is complete.
+	#[arg(long, value_name = "PATH", default_value = "comic-repack.journal")]
+	#[arg(env = "COMIC_REPACK_JOURNAL")]
+	pub journal: PathBuf,
+}
+
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+	/// Convert archives, the default command.
+	Convert(ConvertArgs),
+
+	/// Re-run only archives failed last time, with the same settings.
+	RetryFailed {
+		/// Settings to override, e.g. `-q 80 --force`.
+		#[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "ARGS")]
+		overrides: Vec<String>,
+	},
+
+	/// Copy a page range or pages matching a name into a new archive, without transcoding.
+	/// Pages are renumbered, ComicInfo.xml is updated.
+	Slice(SliceArgs),
+
+	/// Concatenate several archives into one, converting pages as usual.
+	/// Page names are prefixed with the source number,
+	/// ComicInfo.xml of the first source which has it is kept at the root.
+	Merge(MergeArgs),
+
+	/// Split archive into several, by top-level folders or at page numbers, converting pages as usual.
+	Split(SplitArgs),
+
+	/// Watch folders and convert archives appearing in them, once they stop changing,
+	/// until interrupted. Archives are converted again when they are replaced.
+	Watch(WatchArgs),
+
+	/// Convert a sample of pages with each combination of formats and qualities,
+	/// and print a table of sizes, encode times and optionally SSIM.
+	Bench(BenchArgs),
+
+	/// Compare an original archive with the converted one: entry lists, page formats and dimensions,
+	/// and pixel differences of pages as the largest channel delta and SSIM.
+	/// AVIF pages can't be decoded, so their pixels are not compared.
+	Diff(DiffArgs),
+
+	/// Scan library folders given as inputs, show which archives would be converted
+	/// with the estimated savings, and convert them on confirmation.
+	/// Outputs mirror the folder structure in the output directory.
+	Library {
+		#[clap(flatten)]
+		library: LibraryArgs,
+
+		#[clap(flatten)]
+		convert: ConvertArgs,
+	},
+
+	/// Accept conversion jobs over a Unix socket, one JSON request per line
+	/// with `submit`, `status` and `cancel` methods.
+	Serve(ServeArgs),
+}
+
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LibraryArgs {
+	/// Convert every Nth page of each archive to estimate its output size.
+	#[arg(long, value_name = "N", default_value_t = 10)]
+	#[arg(env = "COMIC_REPACK_SAMPLE_EVERY")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub sample_every: u32,
+
+	/// Convert without asking for confirmation.
+	#[arg(short = 'y', long)]
+	#[arg(env = "COMIC_REPACK_YES")]
+	pub yes: bool,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+	/// Input archive.
+	#[arg(value_name = "FILE")]
+	pub input: PathBuf,
+
+	/// Output formats to compare, e.g. `avif,webp`.
+	/// Other formats can be compared by running with different `--encoder-cmd`.
+	#[arg(long, value_name = "FORMATS", value_delimiter = ',', default_value = "avif,webp")]
+	#[arg(env = "COMIC_REPACK_FORMATS")]
+	#[arg(value_parser = parse_image_output_format)]
+	pub formats: Vec<image::ImageOutputFormat>,
+
+	/// Qualities to compare.
+	#[arg(long, value_name = "QUALITIES", value_delimiter = ',', default_value = "60,75,90")]
+	#[arg(env = "COMIC_REPACK_QUALITIES")]
+	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub qualities: Vec<u8>,
+
+	/// Convert every Nth page of the archive as the sample.
+	#[arg(long, value_name = "N", default_value_t = 10)]
+	#[arg(env = "COMIC_REPACK_SAMPLE_EVERY")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub sample_every: u32,
+
+	/// Also compare decoded outputs with the source pages by SSIM of luma, which is slower.
+	/// Not available for AVIF, which can't be decoded.
+	#[arg(long)]
+	#[arg(env = "COMIC_REPACK_SSIM")]
+	pub ssim: bool,
+
+	#[clap(flatten)]
+	pub config: Config,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+	/// Path of the Unix socket, replaced if left by a stopped server.
+	#[arg(long, value_name = "PATH", required_unless_present = "http")]
+	#[arg(env = "COMIC_REPACK_SOCKET")]
+	pub socket: Option<PathBuf>,
+
+	/// Also serve HTTP on this address, e.g. `127.0.0.1:8080`, with uploads, downloads
+	/// and server-sent progress events. There is no authentication, so don't expose it publicly.
+	#[arg(long, value_name = "ADDR")]
+	#[arg(env = "COMIC_REPACK_HTTP")]
+	pub http: Option<std::net::SocketAddr>,
+
+	/// Output directory, defaults to the current one.
+	#[arg(short, long, value_name = "DIR")]
+	#[arg(env = "COMIC_REPACK_OUTPUT")]
+	pub output: Option<PathBuf>,
+
+	/// How many archives are converted in parallel, others wait in the queue.
+	#[arg(short = 'p', long, value_name = "JOBS", default_value_t = 1)]
+	#[arg(env = "COMIC_REPACK_JOBS_FS")]
+	pub jobs_fs: usize,
+
+	#[clap(flatten)]
+	pub config: Config,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+	/// Folders to watch, including subfolders.
+	#[arg(value_name = "DIR", required = true)]
+	pub dirs: Vec<PathBuf>,
+
+	/// Output directory, defaults to the current one. It's never scanned, even inside a watched folder.
+	#[arg(short, long, value_name = "DIR")]
+	#[arg(env = "COMIC_REPACK_OUTPUT")]
+	pub output: Option<PathBuf>,
+
+	/// Seconds between scans. An archive is converted when its size and modification time
+	/// are the same in two scans in a row, so it isn't read while still being copied.
+	#[arg(long, value_name = "SECS", default_value_t = 5)]
+	#[arg(env = "COMIC_REPACK_WATCH_INTERVAL")]
+	#[arg(value_parser = clap::value_parser!(u64).range(1..))]
+	pub interval: u64,
+
+	/// Extensions of archives to convert, case-insensitive.
+	#[arg(long, value_name = "EXT", value_delimiter = ',')]
+	#[arg(default_values_t = ["cbz", "cbr", "cb7", "cbt", "zip", "rar", "7z", "tar"].map(String::from))]
+	#[arg(env = "COMIC_REPACK_INPUT_EXT")]
+	pub input_ext: Vec<String>,
+
+	#[clap(flatten)]
+	pub config: Config,
 }
 
 
+#[derive(clap::Args, Debug)]
+pub struct SplitArgs {
+	/// Input archive.
+	#[arg(value_name = "FILE")]
+	pub input: PathBuf,
+
+	/// Split after these page numbers, e.g. `24,48,72`. By default splits by folders.
+	#[arg(long, value_name = "PAGES", value_delimiter = ',')]
+	#[arg(env = "COMIC_REPACK_AT")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub at: Vec<u32>,
+
+	/// Output directory, defaults to the current one.
+	/// Parts are named `{input name} - {folder or number}`.
+	#[arg(short, long, value_name = "DIR")]
+	#[arg(env = "COMIC_REPACK_OUTPUT")]
+	pub output: Option<PathBuf>,
+
+	#[clap(flatten)]
+	pub config: Config,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+	/// Input archives, in order.
+	#[arg(value_name = "FILES", required = true)]
+	pub input: Vec<PathBuf>,
+
+	/// Output archive.
+	#[arg(short, long, value_name = "FILE")]
+	#[arg(env = "COMIC_REPACK_OUTPUT")]
+	pub output: PathBuf,
+
+	#[clap(flatten)]
+	pub config: Config,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+	/// Original archive.
+	#[arg(value_name = "ORIGINAL")]
+	pub original: PathBuf,
+
+	/// Converted archive. Pixels of AVIF pages are not compared, the decoder isn't built in,
+	/// so fails if all pages are AVIF.
+	#[arg(value_name = "CONVERTED")]
+	pub converted: PathBuf,
+
+	/// Password for encrypted zip and 7z inputs.
+	#[arg(long, value_name = "PASSWORD")]
+	#[arg(env = "COMIC_REPACK_PASSWORD", hide_env_values = true)]
+	pub password: Option<String>,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct SliceArgs {
+	/// Input archive.
+	#[arg(value_name = "FILE")]
+	pub input: PathBuf,
+
+	/// Output archive, its type is determined by the extension.
+	#[arg(short, long, value_name = "FILE")]
+	#[arg(env = "COMIC_REPACK_OUTPUT")]
+	pub output: PathBuf,
+
+	/// Page numbers in natural order starting from 1, e.g. `5-30` or `1,3,10-`.
+	#[arg(long, value_name = "RANGES")]
+	#[arg(env = "COMIC_REPACK_PAGES")]
+	#[arg(value_parser = crate::slice::parse_page_ranges)]
+	pub pages: Option<crate::slice::PageRanges>,
+
+	/// Keep only pages with path matching the glob pattern, e.g. `*/chapter-2/*`.
+	#[arg(long, value_name = "GLOB")]
+	#[arg(env = "COMIC_REPACK_NAME")]
+	pub name: Option<glob::Pattern>,
+
+	/// Page name template, see `--rename-pages` of the conversion.
+	#[arg(long, value_name = "TEMPLATE", default_value = "{index:03}")]
+	#[arg(env = "COMIC_REPACK_RENAME_PAGES")]
+	#[arg(value_parser = crate::paths::parse_page_template)]
+	pub rename_pages: String,
+
+	/// Don't renumber pages.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_KEEP_NAMES")]
+	pub keep_names: bool,
+
+	/// Allow overwrite of existing files.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_FORCE")]
+	pub force: bool,
+
+	#[clap(flatten)]
+	pub zip: ZipOptions,
+
+	#[clap(flatten)]
+	pub sevenz: SevenZipOptions,
+}
+
+
+/// AVIF encoder threads with `--deterministic`, unless `--avif-threads` is given.
+const DETERMINISTIC_AVIF_THREADS: usize = 4;
+
+/// Fake format of the `null-codec` feature: raw pixels with a small header, no compression.
+#[cfg(feature = "null-codec")]
+pub const NULL_CODEC: &str = "null";
+
+
 fn parse_image_output_format(s: &str) -> Result<image::ImageOutputFormat, String> {
 	use image::ImageOutputFormat::{self, *};
 	match s.to_lowercase().as_str() {
@@ -43,6 +522,8 @@ fn parse_image_output_format(s: &str) -> Result<image::ImageOutputFormat, String
 		"tga" => Ok(Tga),
 		"qoi" => Ok(Qoi),
 		"tiff" => Ok(Tiff),
+		#[cfg(feature = "null-codec")]
+		NULL_CODEC => Ok(Unsupported(NULL_CODEC.to_owned())),
 		other => {
 			if let Some(format) =
 				image::ImageFormat::from_extension(other).map(ImageOutputFormat::from)
@@ -61,34 +542,669 @@ fn parse_image_output_format(s: &str) -> Result<image::ImageOutputFormat, String
 pub struct Config {
 	/// Output image format.
 	/// Supported formats: https://docs.rs/image/0.24.6/image/codecs/index.html#supported-formats
-	#[arg(short, long, default_value = "avif")]
+	/// With the `null-codec` feature `null` writes raw pixels, for tests and benchmarks.
+	#[arg(short, long, alias = "codec", default_value = "avif")]
+	#[arg(env = "COMIC_REPACK_FORMAT")]
 	#[arg(value_parser = parse_image_output_format)]
 	pub format: image::ImageOutputFormat,
 
+	/// Format of the cover page, e.g. `jpeg` for readers which can't make thumbnails of AVIF.
+	/// Cover is the page marked as `FrontCover` in ComicInfo.xml or the first one.
+	#[arg(long, value_name = "FORMAT")]
+	#[arg(env = "COMIC_REPACK_COVER_FORMAT")]
+	#[arg(value_parser = parse_image_output_format)]
+	pub cover_format: Option<image::ImageOutputFormat>,
+
+	/// Quality of lossy formats, 1...100. The scales differ between encoders,
+	/// so it can be overridden per format with `--quality-avif`, `--quality-webp` and `--quality-jpeg`.
 	#[arg(short, long, default_value_t = 100)]
+	#[arg(env = "COMIC_REPACK_QUALITY")]
 	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
 	pub quality: u8,
 
+	/// AVIF quality, 1...100. Visually about as good as JPEG of 5-10 points higher quality.
+	#[arg(long, value_name = "QUALITY")]
+	#[arg(env = "COMIC_REPACK_QUALITY_AVIF")]
+	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub quality_avif: Option<u8>,
+
+	/// Lossy WebP quality, 1...100. For lossless WebP the quality is not used.
+	#[arg(long, value_name = "QUALITY")]
+	#[arg(env = "COMIC_REPACK_QUALITY_WEBP")]
+	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub quality_webp: Option<u8>,
+
+	/// JPEG quality, 1...100, the usual libjpeg scale.
+	#[arg(long, value_name = "QUALITY")]
+	#[arg(env = "COMIC_REPACK_QUALITY_JPEG")]
+	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub quality_jpeg: Option<u8>,
+
 	/// Only for webp.
 	#[arg(short, long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_LOSSLESS")]
 	pub lossless: bool,
 
 	/// Used for AVIF encoding, in range 1...10.
 	#[arg(short, long, default_value_t = 3)]
+	#[arg(env = "COMIC_REPACK_SPEED")]
 	#[arg(value_parser = clap::value_parser!(u8).range(1..=10))]
 	pub speed: u8,
 
-	/// Number of parallel threads to use. Defaults to num of physical CPUs - 1.
-	#[arg(short, long, default_value_t = (num_cpus::get_physical() - 1).max(1))]
+	/// Most pages decoded and encoded at once across all archives. Defaults to num of physical CPUs - 1.
+	#[arg(short, long, visible_alias = "max-cpu", default_value_t = (num_cpus::get_physical() - 1).max(1))]
+	#[arg(env = "COMIC_REPACK_JOBS")]
 	pub jobs: usize,
 
+	/// Preset of resolution, grayscale, gamma, format and quality for the reader device.
+	/// Pages already in the output format or in a modern one are re-encoded to fit the device too.
+	/// Options given explicitly override the preset.
+	#[arg(long, value_name = "DEVICE")]
+	#[arg(env = "COMIC_REPACK_DEVICE")]
+	#[arg(value_enum)]
+	pub device: Option<crate::device::Device>,
+
+	/// Downscale pages wider than this, keeping aspect ratio.
+	#[arg(long, value_name = "PX")]
+	#[arg(env = "COMIC_REPACK_MAX_WIDTH")]
+	pub max_width: Option<u32>,
+
+	/// Downscale pages taller than this, keeping aspect ratio.
+	#[arg(long, value_name = "PX")]
+	#[arg(env = "COMIC_REPACK_MAX_HEIGHT")]
+	pub max_height: Option<u32>,
+
+	/// Convert pages with embedded ICC profiles (Adobe RGB, Display P3, gray, CMYK) to sRGB,
+	/// which readers assume for untagged images, and tag PNG and JPEG outputs as sRGB.
+	#[arg(long, value_name = "SWITCH", default_value_t = ColorManagement::On)]
+	#[arg(env = "COMIC_REPACK_COLOR_MANAGEMENT")]
+	#[arg(value_enum)]
+	pub color_management: ColorManagement,
+
+	/// Remove EXIF, XMP, comments and thumbnails from pages copied as-is,
+	/// e.g. scanner serial numbers and GPS tags. Re-encoded pages carry no metadata anyway.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_STRIP_METADATA")]
+	pub strip_metadata: bool,
+
+	/// Keep 16 bits per channel of 16-bit and HDR sources for PNG and AVIF (as 10-bit) output,
+	/// unless other adjustments are applied. Otherwise such pages are reduced to 8 bits.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_KEEP_16BIT")]
+	pub keep_16bit: bool,
+
+	/// Convert pages to grayscale.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_GRAYSCALE")]
+	pub grayscale: bool,
+
+	/// Filters applied to pages in order, separated by commas, e.g. `autocrop,resize:1600,grayscale`.
+	/// `autocrop[:TOLERANCE]` trims uniform borders, `resize:[W]x[H]` or `resize:W` downscales,
+	/// `crop:PX` or `crop:TOP/RIGHT/BOTTOM/LEFT` cuts margins, `grayscale`, `rotate:90|180|270`.
+	#[arg(long, value_name = "FILTERS")]
+	#[arg(env = "COMIC_REPACK_FILTERS")]
+	#[arg(value_parser = crate::filter::parse)]
+	pub filters: Option<crate::filter::Chain>,
+
+	/// WASM module run on every page after `--filters`, which may change its pixels,
+	/// skip the page or copy it as-is. See the `plugin` module for the interface.
+	#[arg(long, value_name = "WASM")]
+	#[arg(env = "COMIC_REPACK_PAGE_PLUGIN")]
+	#[arg(value_parser = crate::plugin::load)]
+	pub page_plugin: Option<crate::plugin::Plugin>,
+
+	/// Stretch levels so the darkest values become black and the brightest white, for faded scans.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_AUTOLEVEL")]
+	pub autolevel: bool,
+
+	/// Add to brightness, -255...255.
+	#[arg(long, value_name = "VALUE", default_value_t = 0, allow_negative_numbers = true)]
+	#[arg(env = "COMIC_REPACK_BRIGHTNESS")]
+	#[arg(value_parser = clap::value_parser!(i16).range(-255..=255))]
+	pub brightness: i16,
+
+	/// Change contrast by percent, -100...100.
+	#[arg(long, value_name = "PERCENT", default_value_t = 0, allow_negative_numbers = true)]
+	#[arg(env = "COMIC_REPACK_CONTRAST")]
+	#[arg(value_parser = clap::value_parser!(i16).range(-100..=100))]
+	pub contrast: i16,
+
+	/// Gamma correction: values below 1 darken midtones, above 1 brighten them.
+	/// E-ink panels usually look better slightly darkened.
+	#[arg(long, value_name = "GAMMA")]
+	#[arg(env = "COMIC_REPACK_GAMMA")]
+	#[arg(value_parser = crate::adjust::parse_positive)]
+	pub gamma: Option<f32>,
+
+	/// Sharpen pages after downscaling with unsharp mask of this amount, e.g. `0.5`.
+	/// Downscaled pages look soft on e-readers without it.
+	#[arg(long, value_name = "AMOUNT")]
+	#[arg(env = "COMIC_REPACK_SHARPEN")]
+	#[arg(value_parser = crate::adjust::parse_positive)]
+	pub sharpen: Option<f32>,
+
+	/// Quantize pages to grayscale of this bit depth: 16 gray levels of 4 bits is all e-ink readers show.
+	/// PNG is written with the real bit depth, lossless WebP just compresses better.
+	#[arg(long, value_name = "BITS")]
+	#[arg(env = "COMIC_REPACK_BIT_DEPTH")]
+	#[arg(value_parser = ["1", "2", "4", "8"])]
+	pub bit_depth: Option<String>,
+
+	/// Dither quantized pages, which keeps gradients at low bit depths.
+	#[arg(long, default_value_t = false, requires = "bit_depth")]
+	#[arg(env = "COMIC_REPACK_DITHER")]
+	pub dither: bool,
+
+	/// Pages bigger than this (in megapixels) are scaled while decoding
+	/// without materializing the full bitmap. Only JPEG and PNG.
+	/// The default catches webtoon strips, e.g. 800x20000 is 16 megapixels.
+	#[arg(long, value_name = "MPX", default_value_t = 8)]
+	#[arg(env = "COMIC_REPACK_STREAM_THRESHOLD")]
+	pub stream_threshold: u32,
+
+	/// What to do with images already in the output format: keep as-is,
+	/// copy with the extension of the output format, or re-encode with scaling and quality applied.
+	#[arg(long, value_name = "POLICY", default_value_t = SameFormat::Skip)]
+	#[arg(env = "COMIC_REPACK_SAME_FORMAT")]
+	#[arg(value_enum)]
+	pub same_format: SameFormat,
+
+	/// What to do with animated GIF, APNG and WebP pages: `keep` encodes animated WebP
+	/// if it is the output format and copies them otherwise, `first-frame` encodes only the first frame.
+	#[arg(long, value_name = "POLICY", default_value_t = Animation::Keep)]
+	#[arg(env = "COMIC_REPACK_ANIMATION")]
+	#[arg(value_enum)]
+	pub animation: Animation,
+
+	/// Re-encode WebP and AVIF sources, which are kept as-is by default, e.g. to convert WebP to AVIF.
+	/// AVIF sources can't be decoded yet and are still copied.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_REENCODE_MODERN")]
+	pub reencode_modern: bool,
+
+	/// Re-encode all images, even those already in the output format or in a modern one.
+	/// Same as `--same-format reencode --reencode-modern`.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_FORCE_REENCODE")]
+	pub force_reencode: bool,
+
+	/// Pick encoding per page by its content: black-and-white line-art is stored as lossless WebP,
+	/// grayscale pages are encoded without color, color pages as configured.
+	/// With `--encoder-cmd` line-art is encoded by the command too, as grayscale.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_ADAPTIVE")]
+	pub adaptive: bool,
+
+	/// Quality of color pages with `--adaptive`, 1...100, e.g. 75 with `-f avif`.
+	/// Overrides `--quality` and `--quality-*` of the format for them.
+	#[arg(long, value_name = "QUALITY", requires = "adaptive")]
+	#[arg(env = "COMIC_REPACK_QUALITY_COLOR")]
+	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub quality_color: Option<u8>,
+
+	/// Quality of grayscale pages with `--adaptive`, 1...100, usually lower than of color ones,
+	/// as screentone hides artifacts. Overrides `--quality` and `--quality-*` of the format for them.
+	#[arg(long, value_name = "QUALITY", requires = "adaptive")]
+	#[arg(env = "COMIC_REPACK_QUALITY_GRAY")]
+	#[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub quality_gray: Option<u8>,
+
+	/// Lower quality of pages encoded bigger than this, e.g. `350K`, searching for the best quality which fits.
+	/// Costs a few extra encodes per such page. Lossless formats are not affected.
+	#[arg(long, value_name = "SIZE")]
+	#[arg(env = "COMIC_REPACK_TARGET_PAGE_SIZE")]
+	#[arg(value_parser = parse_size)]
+	pub target_page_size: Option<u64>,
+
+	/// Like `--target-page-size`, with the budget split evenly between pages of the archive, e.g. `200M`.
+	#[arg(long, value_name = "SIZE", conflicts_with = "target_page_size")]
+	#[arg(env = "COMIC_REPACK_TARGET_ARCHIVE_SIZE")]
+	#[arg(value_parser = parse_size)]
+	pub target_archive_size: Option<u64>,
+
+	/// Give up encoding a page after this many seconds and fall back to `--timeout-fallback`.
+	/// Encoders can't be interrupted, so the abandoned encode keeps its thread until it finishes.
+	#[arg(long, value_name = "SECS")]
+	#[arg(env = "COMIC_REPACK_PAGE_TIMEOUT")]
+	#[arg(value_parser = clap::value_parser!(u64).range(1..))]
+	pub page_timeout: Option<u64>,
+
+	/// What to do with pages over `--page-timeout`.
+	#[arg(long, value_name = "POLICY", default_value_t = TimeoutFallback::Fast)]
+	#[arg(env = "COMIC_REPACK_TIMEOUT_FALLBACK")]
+	#[arg(value_enum)]
+	pub timeout_fallback: TimeoutFallback,
+
+	/// Rename pages to a clean numbering in natural order, e.g. "{index:03}".
+	/// Placeholders: `{index}` (page number from 1, `{index:03}` zero-padded), `{stem}` (original name).
+	/// Non-image entries keep their names.
+	#[arg(long, value_name = "TEMPLATE")]
+	#[arg(env = "COMIC_REPACK_RENAME_PAGES")]
+	#[arg(value_parser = crate::paths::parse_page_template)]
+	pub rename_pages: Option<String>,
+
+	/// Skip entries matching the glob pattern, either the whole path in the archive or the file name,
+	/// e.g. `*.url` or `scans/credits-*`. Can be repeated.
+	#[arg(long, value_name = "GLOB")]
+	#[arg(env = "COMIC_REPACK_JUNK_PATTERN", value_delimiter = ',')]
+	pub junk_pattern: Vec<glob::Pattern>,
+
+	/// Keep entries skipped by the default rules: `Thumbs.db`, `desktop.ini`,
+	/// `__MACOSX` folders and hidden files such as `.DS_Store`.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_NO_DEFAULT_FILTERS")]
+	pub no_default_filters: bool,
+
+	/// Keep inner folders of pages, e.g. `Chapter 01/`, instead of putting all pages to the archive root.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_KEEP_STRUCTURE")]
+	pub keep_structure: bool,
+
+	/// What to do when entries get the same output name, e.g. `01/001.jpg` and `02/001.jpg`
+	/// flattened to `001.avif`. The first entry in order keeps the name.
+	#[arg(long, value_name = "POLICY", default_value_t = OnCollision::Rename)]
+	#[arg(env = "COMIC_REPACK_ON_COLLISION")]
+	#[arg(value_enum)]
+	pub on_collision: OnCollision,
+
+	/// Keep modification times of entries, read from zip and 7z inputs.
+	/// Otherwise entries get the time of conversion.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_PRESERVE_TIMES")]
+	pub preserve_times: bool,
+
+	/// Copy the zip comment of the input, which may hold ComicBookInfo metadata, to zip output.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_PRESERVE_COMMENT")]
+	pub preserve_comment: bool,
+
+	/// Give output files the modification time of the input, and its permissions without execute bits.
+	/// Otherwise outputs are modified at the time of conversion and have default permissions.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_PRESERVE_FILE_TIMES")]
+	pub preserve_file_times: bool,
+
+	/// Convert metadata between formats: `cbi-to-comicinfo` writes ComicBookInfo from the zip comment
+	/// as ComicInfo.xml, `filename-to-comicinfo` writes what the input file name tells,
+	/// unless the archive already has ComicInfo.xml.
+	#[arg(long, value_name = "CONVERSION")]
+	#[arg(env = "COMIC_REPACK_META_CONVERT")]
+	#[arg(value_enum)]
+	pub meta_convert: Option<MetaConvert>,
+
+	/// Byte-identical output for the same input and options, regardless of the machine:
+	/// entries have no timestamps and AVIF tiling doesn't depend on the number of threads.
+	/// Entries are written in `--order` anyway, whichever page is encoded first.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_DETERMINISTIC")]
+	#[arg(conflicts_with_all = ["preserve_times", "reuse_similar"])]
+	pub deterministic: bool,
+
+	/// Order of entries in the output archive.
+	#[arg(long, value_name = "ORDER", default_value_t = EntryOrder::Natural)]
+	#[arg(env = "COMIC_REPACK_ORDER")]
+	#[arg(value_enum)]
+	pub order: EntryOrder,
+
+	/// Detect identical pages by content.
+	/// `skip` writes duplicates only once, `report` keeps them but warns.
+	#[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "skip")]
+	#[arg(env = "COMIC_REPACK_DEDUPE")]
+	#[arg(value_enum)]
+	pub dedupe: Option<Dedupe>,
+
+	/// Reuse encoded pages for perceptually identical pages of other archives in the run,
+	/// e.g. the same chapter from different rips. Encodes are kept in memory, up to 1 GiB.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_REUSE_SIMILAR")]
+	pub reuse_similar: bool,
+
+	/// Keep going past CRC and decompression errors of damaged sources: pages read partially
+	/// are kept if they still decode, unrecoverable ones are listed in the report.
+	#[arg(long, default_value_t = false, conflicts_with = "strict")]
+	#[arg(env = "COMIC_REPACK_SALVAGE")]
+	pub salvage: bool,
+
+	/// Fail the whole archive and remove its output if any page fails,
+	/// including images which can't be decoded and would be copied as-is otherwise.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_STRICT")]
+	pub strict: bool,
+
+	/// What to do with layered working files (PSD, XCF) inside archives.
+	#[arg(long, value_name = "POLICY", default_value_t = WorkingFiles::Copy)]
+	#[arg(env = "COMIC_REPACK_WORKING_FILES")]
+	#[arg(value_enum)]
+	pub working_files: WorkingFiles,
+
+	/// Rotate pages with orientation different from the most of pages (e.g. a few sideways scans).
+	/// Needs an extra pass over the archive to read page sizes.
+	#[arg(long, value_name = "DIRECTION", num_args = 0..=1, default_missing_value = "cw")]
+	#[arg(env = "COMIC_REPACK_FIX_ORIENTATION")]
+	#[arg(value_enum)]
+	pub fix_orientation: Option<Rotation>,
+
+	/// Split landscape pages (two-page spreads) into two pages.
+	/// `gutter` cuts at the spine shadow of photographed books and trims it, `center` cuts in the middle.
+	#[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "gutter")]
+	#[arg(env = "COMIC_REPACK_SPLIT_SPREADS")]
+	#[arg(value_enum)]
+	pub split_spreads: Option<SplitSpreads>,
+
+	/// Right-to-left reading order (manga): the right half of a split spread goes first.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_RTL")]
+	pub rtl: bool,
+
+	/// Split pages taller than this (webtoon strips) into several pages of equal height.
+	#[arg(long, value_name = "PX")]
+	#[arg(env = "COMIC_REPACK_SPLIT_TALL")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub split_tall: Option<u32>,
+
+	/// Rows shared by neighbor pieces of `--split-tall`, at most half of the max height.
+	#[arg(long, value_name = "PX", default_value_t = 0, requires = "split_tall")]
+	#[arg(env = "COMIC_REPACK_SPLIT_TALL_OVERLAP")]
+	pub split_tall_overlap: u32,
+
+	/// Join consecutive pages of the same width into strips not taller than this,
+	/// for readers that smooth-scroll long images.
+	#[arg(long, value_name = "PX", conflicts_with = "split_tall")]
+	#[arg(env = "COMIC_REPACK_STITCH")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub stitch: Option<u32>,
+
+	/// Encode pages with an external command instead, e.g. `cjxl - - -q {quality}`.
+	/// Decoded page is piped to stdin, the encoded one is read from stdout.
+	/// Placeholders: `{quality}`, `{speed}`, `{width}`, `{height}`.
+	#[arg(long, value_name = "COMMAND")]
+	#[arg(env = "COMIC_REPACK_ENCODER_CMD")]
+	#[arg(value_parser = crate::external::parse_encoder_command)]
+	pub encoder_cmd: Option<crate::external::EncoderCommand>,
+
+	/// Format of decoded pages piped to `--encoder-cmd`.
+	#[arg(long, value_name = "FORMAT", default_value_t = crate::external::EncoderInput::Png)]
+	#[arg(env = "COMIC_REPACK_ENCODER_INPUT")]
+	#[arg(value_enum)]
+	pub encoder_input: crate::external::EncoderInput,
+
+	/// Extension of pages produced by `--encoder-cmd`, e.g. `jxl`. Defaults to the `--format` one.
+	#[arg(long, value_name = "EXT")]
+	#[arg(env = "COMIC_REPACK_ENCODER_EXT")]
+	#[arg(value_parser = parse_output_ext)]
+	pub encoder_ext: Option<String>,
+
+	#[clap(flatten)]
+	pub avif: AvifOptions,
+
+	#[clap(flatten)]
+	pub webp: WebpOptions,
+
+	#[clap(flatten)]
+	pub png: PngOptions,
+
+	#[clap(flatten)]
+	pub jpeg: JpegOptions,
+
+	#[clap(flatten)]
+	pub zip: ZipOptions,
+
+	#[clap(flatten)]
+	pub sevenz: SevenZipOptions,
+
+	/// Password for encrypted zip and 7z inputs.
+	#[arg(long, value_name = "PASSWORD", conflicts_with = "password_file")]
+	#[arg(env = "COMIC_REPACK_PASSWORD", hide_env_values = true)]
+	pub password: Option<String>,
+
+	/// Read password for encrypted inputs from the file (first line).
+	#[arg(long, value_name = "PATH")]
+	#[arg(env = "COMIC_REPACK_PASSWORD_FILE")]
+	pub password_file: Option<PathBuf>,
+
 	#[arg(short, long, value_name = "TYPE", default_value_t = ArchiveType::Cbz)]
+	#[arg(env = "COMIC_REPACK_ARCHIVE")]
 	pub archive: ArchiveType,
 
-	/// Allow overwrite of existing files.
+	/// Extension of output files, e.g. `CBZ` or `zip`. Defaults to the archive type.
+	/// Doesn't change the container format.
+	#[arg(long, value_name = "EXT")]
+	#[arg(env = "COMIC_REPACK_OUTPUT_EXT")]
+	#[arg(value_parser = parse_output_ext)]
+	pub output_ext: Option<String>,
+
+	/// Arrange outputs as `Series/Series Vol. N.cbz` for a media server, using ComicInfo.xml
+	/// or the input file name. `--rename` takes precedence.
+	#[arg(long, value_name = "SERVER")]
+	#[arg(env = "COMIC_REPACK_LAYOUT")]
+	#[arg(value_enum)]
+	pub layout: Option<Layout>,
+
+	/// Output name template relative to the output directory, e.g. `{series}/{series} v{volume:02}`.
+	/// Placeholders: `{series}`, `{volume}`, `{chapter}`, `{year}` from ComicInfo.xml or the input file name,
+	/// and `{stem}` (input file name). Extension is appended. `--rename` takes precedence.
+	#[arg(long, value_name = "TEMPLATE", conflicts_with = "layout")]
+	#[arg(env = "COMIC_REPACK_OUTPUT_TEMPLATE")]
+	#[arg(value_parser = crate::filename::parse_template)]
+	pub output_template: Option<String>,
+
+	/// Prepend this to output file names, e.g. `avif80-`.
+	/// Only for outputs named after inputs, not by `--rename`, `--layout` or `--output-template`.
+	#[arg(long, value_name = "TEXT")]
+	#[arg(env = "COMIC_REPACK_PREFIX")]
+	#[arg(value_parser = crate::paths::parse_affix)]
+	pub prefix: Option<String>,
+
+	/// Append this to output file names before the extension, e.g. `-avif80`,
+	/// so outputs can be next to inputs in the same folder.
+	/// Only for outputs named after inputs, not by `--rename`, `--layout` or `--output-template`.
+	#[arg(long, value_name = "TEXT")]
+	#[arg(env = "COMIC_REPACK_SUFFIX")]
+	#[arg(value_parser = crate::paths::parse_affix)]
+	pub suffix: Option<String>,
+
+	/// Update existing output archive: convert only new or changed pages, or all of them
+	/// if settings changed, and drop pages of removed ones. Only for zip output.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_APPEND")]
+	pub append: bool,
+
+	/// Skip inputs which were produced by comic-repack with the same settings,
+	/// and mark outputs with a `comic-repack.json` entry to be recognized next time.
+	/// Makes repeated sweeps of a library converted in place idempotent.
+	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_SKIP_CONVERTED")]
+	pub skip_converted: bool,
+
+	/// Split output into volumes of about this size on page boundaries, e.g. `200M`.
+	/// Next volumes are named `name.part2.cbz`, `name.part3.cbz` and so on.
+	#[arg(long, value_name = "SIZE", conflicts_with = "append")]
+	#[arg(env = "COMIC_REPACK_SPLIT_SIZE")]
+	#[arg(value_parser = parse_size)]
+	pub split_size: Option<u64>,
+
+	/// Allow overwrite of existing files, the same as `--on-exists overwrite`.
 	/// .
 	#[arg(long, default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_FORCE")]
 	pub force: bool,
+
+	/// What to do when the output file already exists.
+	#[arg(long, value_name = "POLICY", default_value_t = OnExists::Error)]
+	#[arg(env = "COMIC_REPACK_ON_EXISTS")]
+	#[arg(value_enum)]
+	pub on_exists: OnExists,
+}
+
+
+impl Default for Config {
+	/// Defaults of the command line, ignoring `COMIC_REPACK_*` environment variables.
+	fn default() -> Self {
+		let command = <Self as clap::Args>::augment_args(clap::Command::new(env!("CARGO_PKG_NAME")));
+		let matches = command.mut_args(|arg| arg.env(None))
+		                     .get_matches_from([env!("CARGO_PKG_NAME")]);
+		Self::from_arg_matches(&matches).expect("valid defaults")
+	}
+}
+
+
+impl Config {
+	/// Extension of output archives.
+	pub fn output_ext(&self) -> &str { self.output_ext.as_deref().unwrap_or(self.archive.ext()) }
+
+	/// Quality for the output format: its `--quality-*` override or `--quality`.
+	pub fn format_quality(&self) -> u8 {
+		use image::ImageOutputFormat::*;
+		let quality = match self.format {
+			Avif => self.quality_avif,
+			WebP => self.quality_webp,
+			Jpeg(_) => self.quality_jpeg,
+			_ => None,
+		};
+		quality.unwrap_or(self.quality)
+	}
+
+	/// Read `--password-file` into `password`, once for every command.
+	pub fn read_password_file(&mut self) -> std::io::Result<()> {
+		if let Some(path) = self.password_file.take() {
+			let password = std::fs::read_to_string(path)?;
+			self.password = Some(password.trim_end_matches(['\r', '\n']).to_owned());
+		}
+		Ok(())
+	}
+
+	/// Whether adjustments change pixels of every page, so none is copied as-is.
+	pub fn alters_pixels(&self) -> bool {
+		self.grayscale ||
+		self.autolevel ||
+		self.brightness != 0 ||
+		self.contrast != 0 ||
+		self.gamma.is_some() ||
+		self.sharpen.is_some() ||
+		self.bit_depth.is_some()
+	}
+
+	/// Filter of `--junk-pattern` and default rules.
+	pub fn junk_filter(&self) -> crate::paths::JunkFilter {
+		crate::paths::JunkFilter::new(!self.no_default_filters, self.junk_pattern.clone())
+	}
+
+	/// Threads of the AVIF encoder, which also define the number of tiles.
+	pub fn avif_threads(&self) -> Option<usize> {
+		let fixed = self.deterministic.then_some(DETERMINISTIC_AVIF_THREADS);
+		self.avif.threads.map(|n| n as _).or(fixed)
+	}
+
+	/// Hash of settings affecting the output, for `--skip-converted`.
+	/// Options of how the run goes, where outputs are placed and the `--device` applied already
+	/// are left out, so a new option changes the hash only when it's added here.
+	pub fn settings_hash(&self) -> String {
+		macro_rules! settings {
+			($($field:ident),* $(,)?) => {
+				[$(format!("{}={:?}", stringify!($field), self.$field)),*].join(";")
+			};
+		}
+		let settings = settings!(
+		                         format,
+		                         cover_format,
+		                         quality,
+		                         quality_avif,
+		                         quality_webp,
+		                         quality_jpeg,
+		                         lossless,
+		                         speed,
+		                         max_width,
+		                         max_height,
+		                         color_management,
+		                         strip_metadata,
+		                         keep_16bit,
+		                         grayscale,
+		                         filters,
+		                         page_plugin,
+		                         autolevel,
+		                         brightness,
+		                         contrast,
+		                         gamma,
+		                         sharpen,
+		                         bit_depth,
+		                         dither,
+		                         stream_threshold,
+		                         same_format,
+		                         animation,
+		                         reencode_modern,
+		                         force_reencode,
+		                         adaptive,
+		                         quality_color,
+		                         quality_gray,
+		                         target_page_size,
+		                         target_archive_size,
+		                         rename_pages,
+		                         junk_pattern,
+		                         no_default_filters,
+		                         keep_structure,
+		                         on_collision,
+		                         preserve_times,
+		                         preserve_comment,
+		                         meta_convert,
+		                         deterministic,
+		                         order,
+		                         dedupe,
+		                         reuse_similar,
+		                         salvage,
+		                         working_files,
+		                         fix_orientation,
+		                         split_spreads,
+		                         rtl,
+		                         split_tall,
+		                         split_tall_overlap,
+		                         stitch,
+		                         encoder_cmd,
+		                         encoder_input,
+		                         encoder_ext,
+		                         avif,
+		                         webp,
+		                         png,
+		                         jpeg,
+		                         zip,
+		                         sevenz,
+		                         archive,
+		                         split_size,
+		);
+		crate::paths::hex(&crate::paths::digest([settings.as_bytes()]))
+	}
+
+	/// Fastest encoder settings, for pages over `--page-timeout`.
+	pub fn fastest(&self) -> Config {
+		Config { speed: 10,
+		         target_page_size: None,
+		         webp: WebpOptions { method: Some(0),
+		                             ..self.webp.clone() },
+		         png: PngOptions { optimize: None,
+		                           zopfli: false },
+		         ..self.clone() }
+	}
+
+	/// Bits of `--bit-depth`.
+	pub fn bit_depth(&self) -> Option<u8> { self.bit_depth.as_ref().and_then(|bits| bits.parse().ok()) }
+
+	/// Extension of encoded pages.
+	pub fn page_ext(&self) -> &str {
+		match self.encoder_cmd {
+			Some(_) => self.encoder_ext.as_deref().unwrap_or(self.format.ext()),
+			None => self.format.ext(),
+		}
+	}
+}
+
+
+fn parse_output_ext(s: &str) -> Result<String, String> {
+	let ext = s.strip_prefix('.').unwrap_or(s);
+	if ext.is_empty() || ext.contains(['/', '\\']) {
+		Err(format!("Invalid extension '{s}'"))
+	} else {
+		Ok(ext.to_owned())
+	}
 }
 
 
@@ -99,6 +1215,23 @@ pub enum ArchiveType {
 	Cb7,
 	#[value(name = "7z", alias("7z"))]
 	SevenZip,
+	/// The same container as the input: cbz stays cbz, cb7 stays cb7. RAR and others become cbz.
+	Keep,
+}
+
+impl ArchiveType {
+	/// Actual type for the input, resolves `keep`.
+	pub fn for_input(self, input: &std::path::Path, kind: Option<crate::source::Kind>) -> Self {
+		use crate::source::Kind;
+		let ext = input.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+		match (self, kind) {
+			(Self::Keep, Some(Kind::Zip)) if ext.as_deref() == Some("zip") => Self::Zip,
+			(Self::Keep, Some(Kind::SevenZip)) if ext.as_deref() == Some("7z") => Self::SevenZip,
+			(Self::Keep, Some(Kind::SevenZip)) => Self::Cb7,
+			(Self::Keep, _) => Self::Cbz,
+			(archive, _) => archive,
+		}
+	}
 }
 
 impl ToString for ArchiveType {
@@ -106,6 +1239,477 @@ impl ToString for ArchiveType {
 }
 
 
+/// Order of entries written to the output archive.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum EntryOrder {
+	/// As listed in the source archive.
+	Original,
+	/// Alphanumeric, so `p2` goes before `p10`.
+	Natural,
+	/// Lexicographic by name.
+	Name,
+}
+
+
+/// Order of converting archives of the batch.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum BatchOrder {
+	/// Lexicographic by path.
+	Name,
+	/// Smallest first.
+	Size,
+	/// Oldest modification time first.
+	Mtime,
+}
+
+
+/// What to do with entries getting the same output name.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum OnCollision {
+	/// Add a number to names of the next ones: `001~2.avif`.
+	Rename,
+	/// Fail the archive.
+	Error,
+}
+
+
+/// What to do with an existing output file.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum OnExists {
+	/// Fail the archive.
+	Error,
+	/// Leave the output and skip the archive, for reruns of a batch.
+	Skip,
+	/// Replace the output when the new one is complete.
+	Overwrite,
+	/// Add a number to the name of the new output: `Series v01~2.cbz`.
+	Rename,
+	/// Ask on the terminal for each existing output.
+	Ask,
+}
+
+
+/// Fallback for pages over `--page-timeout`.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum TimeoutFallback {
+	/// Retry with the fastest encoder settings, copy as-is if it times out again.
+	Fast,
+	/// Copy the page as-is.
+	Copy,
+}
+
+
+/// Hash of `--checksums`.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+	Sha256,
+}
+
+impl Checksum {
+	pub fn ext(&self) -> &'static str {
+		match self {
+			Self::Sha256 => "sha256",
+		}
+	}
+}
+
+
+/// Metadata conversion.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum MetaConvert {
+	/// ComicBookInfo JSON of the zip comment to ComicInfo.xml.
+	CbiToComicinfo,
+	/// Series, volume, chapter and year parsed from the input file name to ComicInfo.xml.
+	FilenameToComicinfo,
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Progress {
+	Auto,
+	Bars,
+	Json,
+	None,
+}
+
+
+impl Progress {
+	/// Mode for the terminal, `auto` resolved.
+	pub fn resolve(self) -> Self {
+		match self {
+			Self::Auto if console::Term::stdout().is_term() => Self::Bars,
+			Self::Auto => Self::Json,
+			mode => mode,
+		}
+	}
+}
+
+
+/// Free space check of the output directory.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum DiskCheck {
+	/// Don't start.
+	Refuse,
+	/// Start with a warning.
+	Warn,
+	Off,
+}
+
+
+/// When to show desktop notifications.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Notify {
+	Batch,
+	Archive,
+}
+
+
+/// Naming conventions of media servers.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Layout {
+	/// `Series/Series v01.cbz`, `Series/Series #012.cbz`.
+	Komga,
+	/// `Series/Series Vol. 1.cbz`, `Series/Series Ch. 12.cbz`.
+	Kavita,
+}
+
+
+/// What to do with duplicate pages.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Dedupe {
+	/// Write only the first occurrence.
+	Skip,
+	/// Write all, but report duplicates.
+	Report,
+}
+
+
+/// Policy for images already in the output format.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum SameFormat {
+	/// Keep the entry as-is.
+	Skip,
+	/// Keep the data, but use the output extension, e.g. `.JPG` becomes `.jpeg`.
+	Copy,
+	/// Decode and encode again, so scaling and quality are applied.
+	Reencode,
+}
+
+
+/// Policy for animated pages.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Animation {
+	/// Keep animation, copy as-is if the output format can't be animated.
+	Keep,
+	/// Encode only the first frame.
+	FirstFrame,
+	/// Copy as-is.
+	Copy,
+}
+
+
+/// Color management switch.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ColorManagement {
+	On,
+	Off,
+}
+
+
+/// Policy for PSD/XCF working files.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum WorkingFiles {
+	/// Exclude from the output.
+	Drop,
+	/// Render composite image to a regular page. Only PSD/PSB, others are dropped.
+	Flatten,
+	/// Copy as-is.
+	Copy,
+}
+
+
+/// Direction of rotation by 90°.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Rotation {
+	/// Clockwise.
+	Cw,
+	/// Counterclockwise.
+	Ccw,
+}
+
+
+/// Where to split two-page spreads.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum SplitSpreads {
+	/// At the detected spine shadow, falls back to the center.
+	Gutter,
+	/// In the middle.
+	Center,
+}
+
+
+/// Advanced options of AVIF encoding.
+#[derive(clap::Args, Debug, Clone)]
+pub struct AvifOptions {
+	/// Encoder threads per image. Defaults to all encode threads,
+	/// set to 1 when many pages are encoded in parallel anyway.
+	#[arg(long = "avif-threads", value_name = "N")]
+	#[arg(env = "COMIC_REPACK_AVIF_THREADS")]
+	#[arg(value_parser = clap::value_parser!(u32).range(1..))]
+	pub threads: Option<u32>,
+
+	/// Bit depth, 10 reduces banding of gradients in color pages but decodes slower.
+	/// Defaults to the encoder's choice.
+	#[arg(long = "avif-depth", value_name = "BITS")]
+	#[arg(env = "COMIC_REPACK_AVIF_DEPTH")]
+	#[arg(value_parser = ["8", "10"])]
+	pub depth: Option<String>,
+}
+
+impl AvifOptions {
+	pub fn depth(&self) -> ravif::BitDepth {
+		match self.depth.as_deref() {
+			Some("8") => ravif::BitDepth::Eight,
+			Some("10") => ravif::BitDepth::Ten,
+			_ => ravif::BitDepth::Auto,
+		}
+	}
+}
+
+
+/// Advanced options of WebP encoding.
+#[derive(clap::Args, Debug, Clone)]
+pub struct WebpOptions {
+	/// Compression effort, 0 is fastest, 6 is slowest and smallest. Defaults to 4.
+	#[arg(long = "webp-method", value_name = "N")]
+	#[arg(env = "COMIC_REPACK_WEBP_METHOD")]
+	#[arg(value_parser = clap::value_parser!(u8).range(0..=6))]
+	pub method: Option<u8>,
+
+	/// Lossless encoding with pixel preprocessing, 100 is plain lossless, lower is smaller.
+	/// Works great for black-and-white manga. Implies `--lossless`.
+	#[arg(long = "near-lossless", value_name = "LEVEL", num_args = 0..=1, default_missing_value = "60")]
+	#[arg(env = "COMIC_REPACK_NEAR_LOSSLESS")]
+	#[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+	pub near_lossless: Option<u8>,
+}
+
+
+/// Optimization of PNG output.
+#[derive(clap::Args, Debug, Clone)]
+pub struct PngOptions {
+	/// Optimize encoded PNG pages with oxipng, 0 is fastest, 6 is best.
+	#[arg(long = "png-optimize", value_name = "LEVEL", num_args = 0..=1, default_missing_value = "2")]
+	#[arg(env = "COMIC_REPACK_PNG_OPTIMIZE")]
+	#[arg(value_parser = clap::value_parser!(u8).range(0..=6))]
+	pub optimize: Option<u8>,
+
+	/// Recompress with zopfli while optimizing: a few percent smaller, many times slower.
+	/// Implies `--png-optimize`.
+	#[arg(long = "png-zopfli", default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_PNG_ZOPFLI")]
+	pub zopfli: bool,
+}
+
+
+/// Options of JPEG encoding with mozjpeg.
+#[derive(clap::Args, Debug, Clone)]
+pub struct JpegOptions {
+	/// Chroma subsampling of color pages, `444` keeps colored line-art sharp.
+	#[arg(long = "jpeg-subsampling", value_name = "MODE", default_value_t = ChromaSubsampling::Cs420)]
+	#[arg(env = "COMIC_REPACK_JPEG_SUBSAMPLING")]
+	#[arg(value_enum)]
+	pub subsampling: ChromaSubsampling,
+
+	/// Baseline instead of progressive JPEG, for old readers. Files are slightly bigger.
+	#[arg(long = "jpeg-baseline", default_value_t = false)]
+	#[arg(env = "COMIC_REPACK_JPEG_BASELINE")]
+	pub baseline: bool,
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ChromaSubsampling {
+	/// Half resolution both horizontally and vertically.
+	#[value(name = "420")]
+	Cs420,
+	/// Half resolution horizontally.
+	#[value(name = "422")]
+	Cs422,
+	/// Full resolution.
+	#[value(name = "444")]
+	Cs444,
+}
+
+impl ChromaSubsampling {
+	/// Size of a chroma pixel in luma pixels, horizontally and vertically.
+	pub fn pixel_size(&self) -> (u8, u8) {
+		match self {
+			Self::Cs420 => (2, 2),
+			Self::Cs422 => (2, 1),
+			Self::Cs444 => (1, 1),
+		}
+	}
+}
+
+
+/// Options of zip (cbz) output.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ZipOptions {
+	/// Compression of zip entries. `auto` stores already compressed images as-is
+	/// and compresses only others, such as text and XML.
+	#[arg(long = "zip-compression", value_name = "MODE", default_value_t = ZipCompression::Auto)]
+	#[arg(env = "COMIC_REPACK_ZIP_COMPRESSION")]
+	#[arg(value_enum)]
+	pub compression: ZipCompression,
+
+	/// Compression method of compressed zip entries.
+	#[arg(long = "zip-method", value_name = "METHOD", default_value_t = ZipMethod::Deflate)]
+	#[arg(env = "COMIC_REPACK_ZIP_METHOD")]
+	#[arg(value_enum)]
+	pub method: ZipMethod,
+
+	/// Compression level, meaning depends on the method. Defaults to method's default level.
+	#[arg(long = "zip-level", value_name = "N")]
+	#[arg(env = "COMIC_REPACK_ZIP_LEVEL")]
+	pub level: Option<u32>,
+}
+
+
+/// Options of 7z (cb7) output.
+#[derive(clap::Args, Debug, Clone)]
+pub struct SevenZipOptions {
+	/// Compression method.
+	#[arg(long = "7z-method", value_name = "METHOD", default_value_t = SevenZipMethod::Lzma2)]
+	#[arg(env = "COMIC_REPACK_7Z_METHOD")]
+	#[arg(value_enum)]
+	pub method: SevenZipMethod,
+
+	/// Compression preset, 0 is fastest, 9 is best. Encoded pages hardly compress further,
+	/// so the default 6 is as small as 9 in a fraction of its time and memory.
+	#[arg(long = "7z-preset", value_name = "N", default_value_t = 6)]
+	#[arg(env = "COMIC_REPACK_7Z_PRESET")]
+	#[arg(value_parser = clap::value_parser!(u32).range(0..=9))]
+	pub preset: u32,
+
+	/// Dictionary size, e.g. `16M`. Defaults to the preset's one.
+	#[arg(long = "7z-dict-size", value_name = "SIZE")]
+	#[arg(env = "COMIC_REPACK_7Z_DICT_SIZE")]
+	#[arg(value_parser = parse_dict_size)]
+	pub dict_size: Option<u32>,
+
+	/// Solid compression of the whole archive (or of `--7z-chunk` entries).
+	/// Entries are kept in memory until the block is complete.
+	#[arg(long = "7z-solid", value_name = "on|off", default_value = "off")]
+	#[arg(env = "COMIC_REPACK_7Z_SOLID")]
+	#[arg(value_parser = parse_on_off)]
+	pub solid: bool,
+
+	/// Number of entries compressed together as one solid block.
+	/// Compression runs on a separate thread while next pages are encoded.
+	/// Defaults to 1, or to all entries with `--7z-solid on`.
+	#[arg(long = "7z-chunk", value_name = "N")]
+	#[arg(env = "COMIC_REPACK_7Z_CHUNK")]
+	#[arg(value_parser = clap::value_parser!(u16).range(1..))]
+	pub chunk: Option<u16>,
+}
+
+impl SevenZipOptions {
+	/// Number of entries in one solid block.
+	pub fn chunk(&self) -> usize {
+		match (self.chunk, self.solid) {
+			(Some(chunk), _) => chunk as _,
+			(None, true) => usize::MAX,
+			(None, false) => 1,
+		}
+	}
+}
+
+
+/// Compression method for 7z.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum SevenZipMethod {
+	Lzma,
+	Lzma2,
+}
+
+
+fn parse_on_off(s: &str) -> Result<bool, String> {
+	match s.to_lowercase().as_str() {
+		"on" | "true" | "yes" => Ok(true),
+		"off" | "false" | "no" => Ok(false),
+		other => Err(format!("Expected on or off, got '{other}'")),
+	}
+}
+
+
+fn parse_dict_size(s: &str) -> Result<u32, String> {
+	let size = parse_size(s)?;
+	if (4096..=1536 << 20).contains(&size) {
+		Ok(size as u32)
+	} else {
+		Err(format!("Dictionary size should be in range 4K..1536M, got {size}"))
+	}
+}
+
+
+/// Parse size with optional binary suffix, e.g. `200M`, `1.5GiB`, `350K`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+	let s = s.trim();
+	let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+	let (number, suffix) = s.split_at(split);
+	let number: f64 = number.parse().map_err(|_| format!("Invalid size: '{s}'"))?;
+	let multiplier: u64 = match suffix.trim().to_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+		"" => 1,
+		"K" => 1 << 10,
+		"M" => 1 << 20,
+		"G" => 1 << 30,
+		"T" => 1 << 40,
+		other => return Err(format!("Unknown size suffix '{other}' in '{s}'")),
+	};
+	Ok((number * multiplier as f64).round() as u64)
+}
+
+
+/// Compression method for zip entries.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ZipMethod {
+	Deflate,
+	Store,
+	Zstd,
+	Bzip2,
+	Lzma,
+	Xz,
+}
+
+impl From<ZipMethod> for async_zip::Compression {
+	fn from(method: ZipMethod) -> Self {
+		use async_zip::Compression;
+		match method {
+			ZipMethod::Deflate => Compression::Deflate,
+			ZipMethod::Store => Compression::Stored,
+			ZipMethod::Zstd => Compression::Zstd,
+			ZipMethod::Bzip2 => Compression::Bz,
+			ZipMethod::Lzma => Compression::Lzma,
+			ZipMethod::Xz => Compression::Xz,
+		}
+	}
+}
+
+
+/// Which zip entries to compress.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ZipCompression {
+	/// Compress only entries that are not compressed already.
+	Auto,
+	/// Compress every entry.
+	Always,
+	/// Store every entry without compression.
+	Never,
+}
+
+
 pub trait FormatFileExt {
 	fn ext(&self) -> &str;
 }
@@ -142,27 +1746,51 @@ impl FormatFileExt for ArchiveType {
 			Self::Cbz => "cbz",
 			Self::Cb7 => "cb7",
 			Self::SevenZip => "7z",
+			// not resolved for an input, e.g. output of `merge`:
+			Self::Keep => "cbz",
 		}
 	}
 }
 
 
-pub fn parse() -> Args { Args::parse() }
+pub fn parse() -> Args { from_matches(Args::command().get_matches()).unwrap_or_else(|err| err.exit()) }
+
+pub fn try_parse_from(args: impl IntoIterator<Item = String>) -> Result<Args, clap::Error> {
+	let args = std::iter::once(env!("CARGO_PKG_NAME").to_owned()).chain(args);
+	from_matches(Args::command().try_get_matches_from(args)?)
+}
+
+/// Parsed args with the `--device` preset applied, which needs to know what is given explicitly,
+/// and the `--password-file` read.
+fn from_matches(matches: clap::ArgMatches) -> Result<Args, clap::Error> {
+	let mut args = Args::from_arg_matches(&matches)?;
+	let (config, matches) = match (&mut args.command, matches.subcommand()) {
+		(Some(Command::Convert(ConvertArgs { config, .. })), Some((_, matches))) |
+		(Some(Command::Merge(MergeArgs { config, .. })), Some((_, matches))) |
+		(Some(Command::Split(SplitArgs { config, .. })), Some((_, matches))) |
+		(Some(Command::Bench(BenchArgs { config, .. })), Some((_, matches))) |
+		(Some(Command::Serve(ServeArgs { config, .. })), Some((_, matches))) |
+		(Some(Command::Watch(WatchArgs { config, .. })), Some((_, matches))) |
+		(Some(Command::Library { convert: ConvertArgs { config, .. }, .. }), Some((_, matches))) => (config, matches),
+		_ => (&mut args.convert.config, &matches),
+	};
+	crate::device::apply(config, matches);
+	config.read_password_file().map_err(|err| {
+		                           clap::Error::raw(clap::error::ErrorKind::Io, format!("Unable to read password file: {err}\n"))
+	                           })?;
+	Ok(args)
+}
 
 
 // --- progress ---
 
-pub fn sub_progress_bar(multibar: &MultiProgress,
-                        len: usize,
-                        pos: usize,
-                        msg: impl Into<Cow<'static, str>>)
-                        -> ProgressBar {
-	let template = format!("{{prefix:.bold}} [{{pos:>3}}/{{len:3}}] {{msg:<}} {{wide_bar:.green/.white.dim}} [{{elapsed}}] ({{eta}})");
-	let style = ProgressStyle::default_bar().template(&template)
+/// Bar of bytes with throughput, `msg` is the name and the compression ratio.
+pub fn bytes_progress_bar(multibar: &MultiProgress, len: u64, msg: impl Into<Cow<'static, str>>) -> ProgressBar {
+	let template = "{prefix:.bold} [{bytes:>9}/{total_bytes:9}] {msg:<} {wide_bar:.green/.white.dim} {binary_bytes_per_sec} [{elapsed}] ({eta})";
+	let style = ProgressStyle::default_bar().template(template)
 	                                        .unwrap()
 	                                        .progress_chars("==-");
-	let bar = multibar.add(ProgressBar::new(len as _).with_style(style).with_tab_width(2));
-	bar.set_position(pos as _);
+	let bar = multibar.add(ProgressBar::new(len).with_style(style).with_tab_width(2));
 	bar.set_message(msg);
 	bar
 }
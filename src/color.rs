@@ -0,0 +1,421 @@
+//! Color management: pages with embedded ICC profiles are converted to sRGB,
+//! which is what readers assume for untagged images, and PNG and JPEG outputs are tagged as sRGB.
+//!
+//! Matrix/TRC profiles (RGB and gray), which covers Adobe RGB, Display P3, ProPhoto and the like,
+//! are converted here. LUT-based RGB profiles and CMYK ones are converted by `qcms`,
+//! CMYK JPEGs are decoded with their profile instead of the naive conversion of `image`.
+
+use std::io::{Cursor, Write};
+use std::sync::OnceLock;
+use image::{DynamicImage, ImageDecoder, ImageFormat, RgbImage};
+
+
+/// XYZ (D50) to linear sRGB, Bradford-adapted.
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+	[3.133856, -1.6168667, -0.4906146],
+	[-0.9787684, 1.9161415, 0.0334540],
+	[0.0719453, -0.2289914, 1.4052427],
+];
+/// Profiles closer to sRGB than this are not converted.
+const SRGB_TOLERANCE: f32 = 0.01;
+/// Entries of the linear to sRGB table.
+const ENCODE_STEPS: usize = 4096;
+/// sRGB primaries in XYZ (D50), Bradford-adapted.
+const SRGB_PRIMARIES: [[f32; 3]; 3] = [
+	[0.4360747, 0.2225045, 0.0139322],
+	[0.3850649, 0.7168786, 0.0971045],
+	[0.1430804, 0.0606169, 0.7141733],
+];
+/// D65 to D50 Bradford adaptation of sRGB.
+const SRGB_CHAD: [f32; 9] = [
+	1.0478112, 0.0228866, -0.0501270, 0.0295424, 0.9904844, -0.0170491, -0.0092345, 0.0150436, 0.7521316,
+];
+const D50: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+
+/// ICC profile embedded in the image.
+pub fn icc_profile(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+	let data = Cursor::new(data);
+	match format {
+		ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(data).ok()?.icc_profile(),
+		ImageFormat::Png => image::codecs::png::PngDecoder::new(data).ok()?.icc_profile(),
+		ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(data).ok()?.icc_profile(),
+		ImageFormat::Tiff => image::codecs::tiff::TiffDecoder::new(data).ok()?.icc_profile(),
+		_ => None,
+	}
+}
+
+
+/// Tone reproduction curve of a channel.
+pub enum Curve {
+	Gamma(f32),
+	Table(Vec<f32>),
+	/// ICC parametric curve: `g, a, b, c, d, e, f`.
+	Parametric([f32; 7]),
+}
+
+impl Curve {
+	/// Encoded value to linear, both in `0..=1`.
+	fn linear(&self, x: f32) -> f32 {
+		match self {
+			Self::Gamma(gamma) => x.powf(*gamma),
+			Self::Table(table) => {
+				let pos = x * (table.len() - 1) as f32;
+				let i = (pos.floor() as usize).min(table.len() - 2);
+				let t = pos - i as f32;
+				table[i] * (1.0 - t) + table[i + 1] * t
+			},
+			Self::Parametric([g, a, b, c, d, e, f]) => {
+				if x >= *d {
+					(a * x + b).max(0.0).powf(*g) + e
+				} else {
+					c * x + f
+				}
+			},
+		}
+	}
+}
+
+
+/// Profile of a page, matrix/TRC or converted by `qcms`.
+pub enum Profile {
+	Rgb { curves: [Curve; 3], to_srgb: [[f32; 3]; 3] },
+	Gray(Curve),
+	/// LUT-based RGB profile.
+	RgbLut(Box<qcms::Profile>),
+	/// CMYK profile, applied when decoding, see [`Profile::decode_cmyk`].
+	Cmyk(Box<qcms::Profile>),
+}
+
+
+impl Profile {
+	/// Parse supported profile, `None` for unsupported ones and sRGB, which needs no conversion.
+	pub fn parse(icc: &[u8]) -> Option<Self> {
+		if icc.len() < 132 {
+			return None;
+		}
+		let color_space = &icc[16..20];
+		let profile = match color_space {
+			b"RGB " => match Self::rgb_matrix(icc) {
+				Some(profile) => profile,
+				None => Self::RgbLut(lut(icc)?),
+			},
+			b"GRAY" => Self::Gray(curve(tag(icc, b"kTRC")?)?),
+			b"CMYK" => Self::Cmyk(lut(icc)?),
+			other => {
+				debug!("ICC profile of {} is not supported", String::from_utf8_lossy(other).trim());
+				return None;
+			},
+		};
+		(!profile.is_srgb()).then_some(profile)
+	}
+
+
+	fn rgb_matrix(icc: &[u8]) -> Option<Self> {
+		// primaries are columns of the matrix to XYZ:
+		let [r, g, b] = [tag(icc, b"rXYZ")?, tag(icc, b"gXYZ")?, tag(icc, b"bXYZ")?].map(xyz);
+		let (r, g, b) = (r?, g?, b?);
+		let mut to_srgb = [[0.0; 3]; 3];
+		for (row, srgb) in to_srgb.iter_mut().zip(XYZ_TO_SRGB) {
+			for (value, column) in row.iter_mut().zip([r, g, b]) {
+				*value = srgb.iter().zip(column).map(|(a, b)| a * b).sum();
+			}
+		}
+		let curves = [curve(tag(icc, b"rTRC")?)?, curve(tag(icc, b"gTRC")?)?, curve(tag(icc, b"bTRC")?)?];
+		Some(Self::Rgb { curves, to_srgb })
+	}
+
+
+	/// Decode a CMYK JPEG converting it with the profile, `None` for other images and profiles.
+	pub fn decode_cmyk(&self, data: &[u8]) -> Option<DynamicImage> {
+		let Self::Cmyk(profile) = self else {
+			return None;
+		};
+		let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(data));
+		let pixels = decoder.decode().ok()?;
+		let info = decoder.info()?;
+		if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 {
+			return None;
+		}
+		let srgb = qcms::Profile::new_sRGB();
+		let transform = qcms::Transform::new_to(profile, &srgb, qcms::DataType::CMYK, qcms::DataType::RGB8, qcms::Intent::Perceptual)?;
+		let (width, height) = (info.width as u32, info.height as u32);
+		let mut rgb = vec![0; width as usize * height as usize * 3];
+		transform.convert(&pixels, &mut rgb);
+		RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+	}
+
+
+	fn is_srgb(&self) -> bool {
+		let close = |a: f32, b: f32| (a - b).abs() < SRGB_TOLERANCE;
+		let srgb_curve = |curve: &Curve| [0.2, 0.5, 0.8].into_iter().all(|x| close(curve.linear(x), srgb_linear(x)));
+		match self {
+			Self::Rgb { curves, to_srgb } => {
+				let identity = to_srgb.iter()
+				                      .enumerate()
+				                      .all(|(i, row)| row.iter().enumerate().all(|(j, v)| close(*v, (i == j) as u8 as f32)));
+				identity && curves.iter().all(srgb_curve)
+			},
+			Self::Gray(curve) => srgb_curve(curve),
+			Self::RgbLut(_) | Self::Cmyk(_) => false,
+		}
+	}
+
+
+	/// Convert 8-bit image to sRGB, alpha is kept. CMYK pages are converted when decoded.
+	pub fn to_srgb(&self, image: DynamicImage) -> DynamicImage {
+		let encode: Vec<u8> = (0..ENCODE_STEPS).map(|i| {
+			                                       let x = i as f32 / (ENCODE_STEPS - 1) as f32;
+			                                       (srgb_encoded(x) * 255.0).round() as u8
+		                                       })
+		                                       .collect();
+		let encode = |x: f32| encode[(x.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32).round() as usize];
+		let decode = |curve: &Curve| -> Vec<f32> { (0..=255).map(|v| curve.linear(v as f32 / 255.0)).collect() };
+
+		match self {
+			Self::Rgb { curves, to_srgb } => {
+				let decode = [decode(&curves[0]), decode(&curves[1]), decode(&curves[2])];
+				let convert = |pixel: &mut [u8]| {
+					let linear = [decode[0][pixel[0] as usize], decode[1][pixel[1] as usize], decode[2][pixel[2] as usize]];
+					for (value, row) in pixel.iter_mut().zip(to_srgb) {
+						*value = encode(row.iter().zip(linear).map(|(a, b)| a * b).sum());
+					}
+				};
+				if image.color().has_alpha() {
+					let mut image = image.to_rgba8();
+					image.pixels_mut().for_each(|pixel| convert(&mut pixel.0[..3]));
+					DynamicImage::ImageRgba8(image)
+				} else {
+					let mut image = image.to_rgb8();
+					image.pixels_mut().for_each(|pixel| convert(&mut pixel.0));
+					DynamicImage::ImageRgb8(image)
+				}
+			},
+			Self::Gray(curve) => {
+				let decode = decode(curve);
+				let lut: Vec<u8> = decode.into_iter().map(encode).collect();
+				if image.color().has_alpha() {
+					let mut image = image.to_luma_alpha8();
+					image.pixels_mut().for_each(|pixel| pixel[0] = lut[pixel[0] as usize]);
+					DynamicImage::ImageLumaA8(image)
+				} else {
+					let mut image = image.to_luma8();
+					image.iter_mut().for_each(|value| *value = lut[*value as usize]);
+					DynamicImage::ImageLuma8(image)
+				}
+			},
+			Self::RgbLut(profile) => {
+				let srgb = qcms::Profile::new_sRGB();
+				let transform = |ty| qcms::Transform::new(profile, &srgb, ty, qcms::Intent::Perceptual);
+				if image.color().has_alpha() {
+					let Some(transform) = transform(qcms::DataType::RGBA8) else {
+						return image;
+					};
+					let mut image = image.to_rgba8();
+					transform.apply(&mut image);
+					DynamicImage::ImageRgba8(image)
+				} else {
+					let Some(transform) = transform(qcms::DataType::RGB8) else {
+						return image;
+					};
+					let mut image = image.to_rgb8();
+					transform.apply(&mut image);
+					DynamicImage::ImageRgb8(image)
+				}
+			},
+			Self::Cmyk(_) => image,
+		}
+	}
+}
+
+
+fn lut(icc: &[u8]) -> Option<Box<qcms::Profile>> {
+	let profile = qcms::Profile::new_from_slice(icc, false);
+	if profile.is_none() {
+		debug!("ICC profile is not supported");
+	}
+	profile
+}
+
+
+/// sRGB ICC profile to embed into outputs, gray or RGB.
+pub fn srgb_icc(gray: bool) -> &'static [u8] {
+	static RGB: OnceLock<Vec<u8>> = OnceLock::new();
+	static GRAY: OnceLock<Vec<u8>> = OnceLock::new();
+	match gray {
+		true => GRAY.get_or_init(|| build_srgb_icc(true)),
+		false => RGB.get_or_init(|| build_srgb_icc(false)),
+	}
+}
+
+
+/// Compact ICC v4 profile with sRGB primaries and the sRGB curve.
+fn build_srgb_icc(gray: bool) -> Vec<u8> {
+	let fixed = |value: f32| ((value * 65536.0).round() as i32).to_be_bytes();
+	let xyz = |[x, y, z]: [f32; 3]| [&b"XYZ \0\0\0\0"[..], &fixed(x), &fixed(y), &fixed(z)].concat();
+	let text = |text: &str| {
+		let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+		let mut data = [&b"mluc\0\0\0\0"[..], &1u32.to_be_bytes(), &12u32.to_be_bytes(), b"enUS"].concat();
+		data.extend((utf16.len() as u32).to_be_bytes());
+		data.extend(28u32.to_be_bytes());
+		data.extend(utf16);
+		data
+	};
+	// parametric curve of the type 3: `g, a, b, c, d`
+	let curve: Vec<u8> = [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045].into_iter()
+	                                                                            .flat_map(fixed)
+	                                                                            .collect();
+	let curve = [&b"para\0\0\0\0\0\x03\0\0"[..], &curve].concat();
+
+	let mut tags = vec![(*b"desc", text("sRGB")), (*b"cprt", text("No copyright, use freely")), (*b"wtpt", xyz(D50))];
+	if gray {
+		tags.push((*b"kTRC", curve));
+	} else {
+		let chad: Vec<u8> = SRGB_CHAD.into_iter().flat_map(fixed).collect();
+		tags.push((*b"chad", [&b"sf32\0\0\0\0"[..], &chad].concat()));
+		for (signature, primary) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().zip(SRGB_PRIMARIES) {
+			tags.push((*signature, xyz(primary)));
+		}
+		for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+			tags.push((*signature, curve.clone()));
+		}
+	}
+
+	let mut table = Vec::new();
+	let mut data = Vec::new();
+	let start = 128 + 4 + tags.len() * 12;
+	for (signature, tag) in tags {
+		table.extend(signature);
+		table.extend(((start + data.len()) as u32).to_be_bytes());
+		table.extend((tag.len() as u32).to_be_bytes());
+		data.extend(tag);
+		// tags are 4-byte aligned:
+		data.resize(data.len().next_multiple_of(4), 0);
+	}
+
+	let mut header = [0; 128];
+	header[0..4].copy_from_slice(&((start + data.len()) as u32).to_be_bytes());
+	header[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+	header[12..16].copy_from_slice(b"mntr");
+	header[16..20].copy_from_slice(if gray { b"GRAY" } else { b"RGB " });
+	header[20..24].copy_from_slice(b"XYZ ");
+	// 2024-01-01
+	header[24..30].copy_from_slice(&[0x07, 0xe8, 0, 1, 0, 1]);
+	header[36..40].copy_from_slice(b"acsp");
+	for (i, value) in D50.into_iter().enumerate() {
+		header[68 + i * 4..72 + i * 4].copy_from_slice(&fixed(value));
+	}
+	let count = (table.len() as u32 / 12).to_be_bytes();
+	[&header[..], &count, &table, &data].concat()
+}
+
+
+/// PNG with the ICC profile in an `iCCP` chunk after the header.
+pub fn png_with_icc(png: &[u8], icc: &[u8]) -> Vec<u8> {
+	// signature and IHDR:
+	const IHDR_END: usize = 8 + 8 + 13 + 4;
+	if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+		return png.to_vec();
+	}
+	let mut compressed = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+	let compressed = compressed.write_all(icc).and_then(|_| compressed.finish()).expect("in-memory write");
+	// profile name and compression method:
+	let chunk = [&b"iCCPsRGB\0\0"[..], &compressed].concat();
+
+	let mut output = Vec::with_capacity(png.len() + chunk.len() + 8);
+	output.extend_from_slice(&png[..IHDR_END]);
+	output.extend(((chunk.len() - 4) as u32).to_be_bytes());
+	output.extend_from_slice(&chunk);
+	output.extend(crc32fast::hash(&chunk).to_be_bytes());
+	output.extend_from_slice(&png[IHDR_END..]);
+	output
+}
+
+
+fn srgb_linear(x: f32) -> f32 {
+	if x <= 0.04045 {
+		x / 12.92
+	} else {
+		((x + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+pub fn srgb_encoded(x: f32) -> f32 {
+	if x <= 0.0031308 {
+		x * 12.92
+	} else {
+		1.055 * x.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+
+/// Data of the tag with the given signature.
+fn tag<'a>(icc: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+	let count = u32(icc, 128)? as usize;
+	(0..count).find_map(|i| {
+		          let entry = 132 + i * 12;
+		          if icc.get(entry..entry + 4)? != signature {
+			          return None;
+		          }
+		          let offset = u32(icc, entry + 4)? as usize;
+		          let size = u32(icc, entry + 8)? as usize;
+		          icc.get(offset..offset.checked_add(size)?)
+	          })
+}
+
+fn u32(data: &[u8], at: usize) -> Option<u32> { Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?)) }
+
+fn u16(data: &[u8], at: usize) -> Option<u16> { Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?)) }
+
+/// s15Fixed16Number
+fn fixed(data: &[u8], at: usize) -> Option<f32> { Some(u32(data, at)? as i32 as f32 / 65536.0) }
+
+
+fn xyz(data: &[u8]) -> Option<[f32; 3]> {
+	if !data.starts_with(b"XYZ ") {
+		return None;
+	}
+	Some([fixed(data, 8)?, fixed(data, 12)?, fixed(data, 16)?])
+}
+
+
+fn curve(data: &[u8]) -> Option<Curve> {
+	match data.get(..4)? {
+		b"curv" => {
+			let count = u32(data, 8)? as usize;
+			match count {
+				0 => Some(Curve::Gamma(1.0)),
+				1 => Some(Curve::Gamma(u16(data, 12)? as f32 / 256.0)),
+				_ => {
+					let table = (0..count).map(|i| Some(u16(data, 12 + i * 2)? as f32 / 65535.0))
+					                      .collect::<Option<_>>()?;
+					Some(Curve::Table(table))
+				},
+			}
+		},
+		b"para" => {
+			let params = match u16(data, 8)? {
+				0 => 1,
+				1 => 3,
+				2 => 4,
+				3 => 5,
+				4 => 7,
+				_ => return None,
+			};
+			let values = (0..params).map(|i| fixed(data, 12 + i * 4)).collect::<Option<Vec<_>>>()?;
+			// every type is a special case of the type 4 with `g, a, b, c, d, e, f`:
+			let p = |i: usize| values.get(i).copied();
+			let (g, a, b) = (values[0], p(1).unwrap_or(1.0), p(2).unwrap_or(0.0));
+			let curve = match params {
+				1 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+				// linear part is zero below `-b / a`:
+				3 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+				4 => [g, a, b, 0.0, -b / a, values[3], values[3]],
+				5 => [g, a, b, values[3], values[4], 0.0, 0.0],
+				_ => [g, a, b, values[3], values[4], values[5], values[6]],
+			};
+			Some(Curve::Parametric(curve))
+		},
+		_ => None,
+	}
+}
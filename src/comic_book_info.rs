@@ -0,0 +1,104 @@
+//! ComicBookInfo: JSON metadata of ComicRack-era tools, stored in the zip comment.
+//!
+//! With `--meta-convert cbi-to-comicinfo` it is converted to ComicInfo.xml,
+//! which is what current readers understand and which survives 7z output.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+
+const KEY: &str = "ComicBookInfo/1.0";
+
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ComicBookInfo {
+	series: Option<String>,
+	title: Option<String>,
+	publisher: Option<String>,
+	publication_month: Option<u32>,
+	publication_year: Option<u32>,
+	/// Number or string in the wild.
+	issue: Option<Value>,
+	number_of_issues: Option<u32>,
+	volume: Option<Value>,
+	genre: Option<String>,
+	language: Option<String>,
+	rating: Option<f32>,
+	comments: Option<String>,
+	credits: Vec<Credit>,
+	tags: Vec<String>,
+}
+
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Credit {
+	person: String,
+	role: String,
+}
+
+
+impl ComicBookInfo {
+	/// Parse the archive comment, `None` if it is not ComicBookInfo.
+	pub fn parse(comment: &str) -> Option<Self> {
+		let mut json: Value = serde_json::from_str(comment.trim()).ok()?;
+		let info = json.get_mut(KEY)?.take();
+		match serde_json::from_value(info) {
+			Ok(info) => Some(info),
+			Err(err) => {
+				warn!("Invalid ComicBookInfo: {err}");
+				None
+			},
+		}
+	}
+
+
+	/// ComicInfo.xml with the same metadata.
+	pub fn to_comic_info(&self) -> String {
+		let text = |value: &Option<Value>| {
+			match value {
+				Some(Value::String(s)) => Some(s.clone()),
+				Some(Value::Number(n)) => Some(n.to_string()),
+				_ => None,
+			}
+		};
+		let people = |roles: &[&str]| {
+			let names: Vec<_> = self.credits
+			                        .iter()
+			                        .filter(|credit| roles.iter().any(|role| credit.role.eq_ignore_ascii_case(role)))
+			                        .map(|credit| credit.person.as_str())
+			                        .collect();
+			(!names.is_empty()).then(|| names.join(", "))
+		};
+		// readers expect a code there, CBI has a free-form name:
+		let language = self.language.clone().filter(|lang| lang.len() == 2);
+		let tags = (!self.tags.is_empty()).then(|| self.tags.join(", "));
+
+		let fields = [
+		              ("Title", self.title.clone()),
+		              ("Series", self.series.clone()),
+		              ("Number", text(&self.issue)),
+		              ("Count", self.number_of_issues.map(|n| n.to_string())),
+		              ("Volume", text(&self.volume)),
+		              ("Summary", self.comments.clone()),
+		              ("Year", self.publication_year.map(|n| n.to_string())),
+		              ("Month", self.publication_month.map(|n| n.to_string())),
+		              ("Writer", people(&["Writer", "Plotter", "Scripter"])),
+		              ("Penciller", people(&["Penciller", "Artist"])),
+		              ("Inker", people(&["Inker", "Artist"])),
+		              ("Colorist", people(&["Colorist", "Colorer"])),
+		              ("Letterer", people(&["Letterer"])),
+		              ("CoverArtist", people(&["Cover", "Cover Artist", "CoverArtist"])),
+		              ("Editor", people(&["Editor"])),
+		              ("Publisher", self.publisher.clone()),
+		              ("Genre", self.genre.clone()),
+		              ("Tags", tags),
+		              ("LanguageISO", language),
+		              ("CommunityRating", self.rating.map(|n| n.clamp(0.0, 5.0).to_string())),
+		];
+
+		crate::comic_info::to_xml(fields)
+	}
+}
+
@@ -0,0 +1,73 @@
+//! Minimal access to ComicInfo.xml, without a full XML parser.
+
+use std::path::Path;
+
+
+pub fn is_comic_info(uri: &str) -> bool {
+	Path::new(uri).file_name()
+	              .is_some_and(|name| name.eq_ignore_ascii_case("ComicInfo.xml"))
+}
+
+
+/// Index of the page marked as `Type="FrontCover"`, starting from 0.
+pub fn front_cover(xml: &str) -> Option<usize> {
+	xml.split("<Page ")
+	   .skip(1)
+	   .map(|page| &page[..page.find('>').unwrap_or(page.len())])
+	   .find(|attrs| attrs.contains(r#"Type="FrontCover""#))
+	   .and_then(|attrs| attribute(attrs, "Image"))
+	   .and_then(|image| image.parse().ok())
+}
+
+
+fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+	let start = attrs.find(&format!(r#"{name}=""#))? + name.len() + 2;
+	let len = attrs[start..].find('"')?;
+	Some(&attrs[start..start + len])
+}
+
+
+/// Text of the first `<name>` element, unescaped, `None` if it is missing or empty.
+pub fn element(xml: &str, name: &str) -> Option<String> {
+	let open = format!("<{name}>");
+	let start = xml.find(&open)? + open.len();
+	let len = xml[start..].find(&format!("</{name}>"))?;
+	let text = xml[start..start + len].trim();
+	(!text.is_empty()).then(|| unescape(text))
+}
+
+
+fn unescape(s: &str) -> String {
+	s.replace("&lt;", "<")
+	 .replace("&gt;", ">")
+	 .replace("&quot;", "\"")
+	 .replace("&apos;", "'")
+	 .replace("&amp;", "&")
+}
+
+
+/// ComicInfo.xml with the fields which are set, in the order of the schema.
+pub fn to_xml<'a>(fields: impl IntoIterator<Item = (&'a str, Option<String>)>) -> String {
+	let mut xml = String::from(concat!(
+		r#"<?xml version="1.0" encoding="utf-8"?>"#,
+		"\n",
+		r#"<ComicInfo xmlns:xsd="http://www.w3.org/2001/XMLSchema" "#,
+		r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">"#,
+		"\n"
+	));
+	for (name, value) in fields {
+		if let Some(value) = value.filter(|value| !value.trim().is_empty()) {
+			xml.push_str(&format!("  <{name}>{}</{name}>\n", escape(value.trim())));
+		}
+	}
+	xml.push_str("</ComicInfo>\n");
+	xml
+}
+
+
+fn escape(s: &str) -> String {
+	s.replace('&', "&amp;")
+	 .replace('<', "&lt;")
+	 .replace('>', "&gt;")
+	 .replace('"', "&quot;")
+}
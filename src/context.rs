@@ -0,0 +1,43 @@
+//! Archive being processed, so log records of archives converted in parallel with `--jobs-fs`
+//! can be told apart. It follows conversions to the encode threads of [`crate::pool`].
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+
+tokio::task_local! {
+	static ARCHIVE: Arc<str>;
+}
+
+thread_local! {
+	static THREAD_ARCHIVE: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+
+/// Run the future with records tagged by the archive's file name.
+pub fn scope<F: Future>(archive: &Path, future: F) -> impl Future<Output = F::Output> {
+	let name = archive.file_name().unwrap_or(archive.as_os_str()).to_string_lossy();
+	ARCHIVE.scope(Arc::from(name.as_ref()), future)
+}
+
+
+/// File name of the archive being processed by the current task or thread.
+pub fn current() -> Option<Arc<str>> {
+	ARCHIVE.try_with(Arc::clone)
+	       .ok()
+	       .or_else(|| THREAD_ARCHIVE.with(|archive| archive.borrow().clone()))
+}
+
+
+/// The job with the archive of the caller, for running on another thread.
+pub fn propagate<T>(job: impl FnOnce() -> T) -> impl FnOnce() -> T {
+	let archive = current();
+	move || {
+		let previous = THREAD_ARCHIVE.with(|current| current.replace(archive));
+		let res = job();
+		THREAD_ARCHIVE.with(|current| *current.borrow_mut() = previous);
+		res
+	}
+}
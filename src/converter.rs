@@ -0,0 +1,205 @@
+//! Conversion of archives from other programs, without the command line.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), comic_repack::error::Error> {
+//! let converter = comic_repack::Converter::builder().format(image::ImageOutputFormat::WebP)
+//!                                                   .quality(80)
+//!                                                   .output_dir("/srv/comics")
+//!                                                   .build()?;
+//! let report = converter.convert("Series v01.cbr").await?;
+//! println!("{} => {}", report.source_size, report.output_size);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use futures::Stream;
+use image::ImageOutputFormat;
+use tokio_util::sync::CancellationToken;
+
+use crate::cli::{ArchiveType, Config};
+use crate::error::Error;
+use crate::progress::{self, Event as ProgressEvent};
+use crate::report::ArchiveReport;
+
+
+/// Converts archives with the same settings, see [`Converter::builder`].
+#[derive(Debug, Clone)]
+pub struct Converter {
+	config: Config,
+	output_dir: PathBuf,
+	/// Parent of tokens of all conversions.
+	cancel: CancellationToken,
+	/// Streams of [`Converter::events`], shared by clones.
+	subscribers: Arc<progress::Subscribers>,
+}
+
+
+/// Settings of a [`Converter`], starting from defaults of the command line.
+#[derive(Debug, Clone)]
+pub struct ConverterBuilder {
+	config: Config,
+	output_dir: Option<PathBuf>,
+}
+
+
+impl Converter {
+	pub fn builder() -> ConverterBuilder {
+		ConverterBuilder { config: Config::default(),
+		                   output_dir: None }
+	}
+
+
+	/// Convert the archive into the output directory, named after the source unless `--layout`
+	/// or `--output-template` say otherwise.
+	pub async fn convert(&self, path: impl AsRef<Path>) -> Result<ArchiveReport, Error> {
+		self.convert_as(path, None, self.cancel.child_token()).await
+	}
+
+
+	/// Convert the archive to the given name, relative to the output directory.
+	/// Extension defaults to the output one.
+	pub async fn convert_to(&self, path: impl AsRef<Path>, name: impl Into<PathBuf>) -> Result<ArchiveReport, Error> {
+		self.convert_as(path, Some(name.into()), self.cancel.child_token()).await
+	}
+
+
+	/// Convert the archive until the token from [`Converter::cancellation_token`] is cancelled,
+	/// then the incomplete output is removed and the conversion fails.
+	pub async fn convert_cancellable(&self,
+	                                 path: impl AsRef<Path>,
+	                                 cancel: CancellationToken)
+	                                 -> Result<ArchiveReport, Error> {
+		self.convert_as(path, None, cancel).await
+	}
+
+
+	/// Token to cancel one conversion, it is also cancelled by [`Converter::cancel_all`].
+	pub fn cancellation_token(&self) -> CancellationToken { self.cancel.child_token() }
+
+
+	/// Cancel all conversions in progress, including later ones.
+	pub fn cancel_all(&self) { self.cancel.cancel() }
+
+
+	/// Progress of conversions of this converter and its clones: archive started,
+	/// page written with sizes, archive finished or failed. The stream ends when the converter
+	/// is dropped. Events a slow consumer can't keep up with are dropped.
+	pub fn events(&self) -> impl Stream<Item = ProgressEvent> { self.subscribers.subscribe() }
+
+
+	async fn convert_as(&self,
+	                    path: impl AsRef<Path>,
+	                    name: Option<PathBuf>,
+	                    cancel: CancellationToken)
+	                    -> Result<ArchiveReport, Error> {
+		let path = path.as_ref();
+		let converted = async {
+			let inout = crate::open_inout(path, &self.output_dir, name, &self.config).await?;
+			let res = crate::convert_all(inout, &self.config, &cancel, None).await?;
+			let source_size = tokio::fs::metadata(path).await?.len();
+			Ok::<_, Error>(ArchiveReport::new(res.src, res.dst_path, source_size, res.dst_size, res.pages, res.failed))
+		};
+		let reported = async {
+			match crate::context::scope(path, converted).await {
+				Ok(report) => {
+					progress::emit(ProgressEvent::ArchiveDone { path: report.source.clone(),
+					                                            output: report.output.clone(),
+					                                            size: report.output_size });
+					Ok(report)
+				},
+				Err(err) => {
+					progress::emit(ProgressEvent::ArchiveFailed { path: path.to_owned(),
+					                                              error: err.to_string() });
+					Err(err)
+				},
+			}
+		};
+		progress::scope(self.subscribers.clone(), reported).await
+	}
+
+
+	pub fn config(&self) -> &Config { &self.config }
+}
+
+
+impl ConverterBuilder {
+	pub fn format(mut self, format: ImageOutputFormat) -> Self {
+		self.config.format = format;
+		self
+	}
+
+
+	/// Quality of encoders from 1 to 100.
+	pub fn quality(mut self, quality: u8) -> Self {
+		self.config.quality = quality;
+		self
+	}
+
+
+	pub fn archive(mut self, archive: ArchiveType) -> Self {
+		self.config.archive = archive;
+		self
+	}
+
+
+	/// Directory of outputs, the current one by default.
+	pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+		self.output_dir = Some(dir.into());
+		self
+	}
+
+
+	/// Pages converted in parallel for each archive.
+	pub fn jobs(mut self, jobs: usize) -> Self {
+		self.config.jobs = jobs;
+		self
+	}
+
+
+	/// Overwrite existing outputs.
+	pub fn force(mut self, force: bool) -> Self {
+		self.config.force = force;
+		self
+	}
+
+
+	/// Password of encrypted sources.
+	pub fn password(mut self, password: impl Into<String>) -> Self {
+		self.config.password = Some(password.into());
+		self
+	}
+
+
+	/// Add a filter to the end of `--filters`, e.g. a custom [`PageFilter`](crate::filter::PageFilter).
+	pub fn filter(mut self, filter: impl crate::filter::PageFilter + 'static) -> Self {
+		self.config.filters.get_or_insert_with(Default::default).push(filter);
+		self
+	}
+
+
+	/// Any other setting, fields mean the same as options of the command line.
+	pub fn configure(mut self, f: impl FnOnce(&mut Config)) -> Self {
+		f(&mut self.config);
+		self
+	}
+
+
+	pub fn build(self) -> Result<Converter, Error> {
+		if !(1..=100).contains(&self.config.quality) {
+			return Err(format!("Quality must be from 1 to 100, not {}", self.config.quality).into());
+		}
+		let mut config = self.config;
+		config.jobs = config.jobs.max(1);
+		config.read_password_file()?;
+		let output_dir = match self.output_dir {
+			Some(dir) => dir,
+			None => std::env::current_dir()?,
+		};
+		Ok(Converter { config,
+		               output_dir,
+		               cancel: CancellationToken::new(),
+		               subscribers: Default::default() })
+	}
+}
@@ -0,0 +1,103 @@
+//! Bit depth of decoded pages: 16-bit images are rounded to 8 bits,
+//! HDR ones (OpenEXR, Radiance) are tonemapped from linear light to sRGB.
+//! With `--keep-16bit` formats which support it get 16 bits.
+
+use image::{DynamicImage, ImageBuffer, Luma, LumaA, Pixel, Rgb, Rgba};
+
+use crate::color::srgb_encoded;
+
+
+/// Image with 8 bits per channel, or 16 bits if `keep_16bit` and the source has more than 8.
+pub fn normalize(image: DynamicImage, keep_16bit: bool) -> DynamicImage {
+	match image {
+		DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+			debug!("tonemapping HDR {}x{}", image.width(), image.height());
+			tonemap(image, keep_16bit)
+		},
+		DynamicImage::ImageLuma16(_) |
+		DynamicImage::ImageLumaA16(_) |
+		DynamicImage::ImageRgb16(_) |
+		DynamicImage::ImageRgba16(_) if !keep_16bit => {
+			trace!("rounding 16-bit {}x{} to 8 bits", image.width(), image.height());
+			to_8bit(image)
+		},
+		image => image,
+	}
+}
+
+
+/// Rounding to the nearest value, `image` conversions truncate.
+fn to_8bit(image: DynamicImage) -> DynamicImage {
+	fn convert<P, Q>(image: &ImageBuffer<P, Vec<u16>>) -> ImageBuffer<Q, Vec<u8>>
+		where P: Pixel<Subpixel = u16>,
+		      Q: Pixel<Subpixel = u8> {
+		let data = image.iter().map(|v| ((*v as u32 * 255 + 32767) / 65535) as u8).collect();
+		ImageBuffer::from_raw(image.width(), image.height(), data).expect("same size")
+	}
+	match &image {
+		DynamicImage::ImageLuma16(image) => DynamicImage::ImageLuma8(convert::<_, Luma<u8>>(image)),
+		DynamicImage::ImageLumaA16(image) => DynamicImage::ImageLumaA8(convert::<_, LumaA<u8>>(image)),
+		DynamicImage::ImageRgb16(image) => DynamicImage::ImageRgb8(convert::<_, Rgb<u8>>(image)),
+		DynamicImage::ImageRgba16(image) => DynamicImage::ImageRgba8(convert::<_, Rgba<u8>>(image)),
+		_ => image,
+	}
+}
+
+
+/// Linear light to sRGB, highlights above 1.0 are compressed with extended Reinhard
+/// so the brightest value becomes white.
+fn tonemap(image: DynamicImage, to_16bit: bool) -> DynamicImage {
+	let alpha = image.color().has_alpha();
+	let image = image.into_rgba32f();
+	let white = image.pixels()
+	                 .flat_map(|pixel| pixel.0.into_iter().take(3))
+	                 .filter(|v| v.is_finite())
+	                 .fold(1.0f32, f32::max);
+	let map = |v: f32| {
+		let v = if v.is_finite() { v.max(0.0) } else { 0.0 };
+		let v = if white > 1.0 { v * (1.0 + v / (white * white)) / (1.0 + v) } else { v };
+		srgb_encoded(v.min(1.0))
+	};
+
+	let (width, height) = image.dimensions();
+	let channels = if alpha { 4 } else { 3 };
+	let values = image.pixels().flat_map(|pixel| {
+		                           let [r, g, b, a] = pixel.0;
+		                           [map(r), map(g), map(b), a.clamp(0.0, 1.0)].into_iter().take(channels)
+	                           });
+	if to_16bit {
+		let data: Vec<u16> = values.map(|v| (v * 65535.0).round() as u16).collect();
+		match alpha {
+			true => DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, data).expect("same size")),
+			false => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, data).expect("same size")),
+		}
+	} else {
+		let data: Vec<u8> = values.map(|v| (v * 255.0).round() as u8).collect();
+		match alpha {
+			true => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, data).expect("same size")),
+			false => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, data).expect("same size")),
+		}
+	}
+}
+
+
+/// 16-bit image as 10-bit YCbCr (BT.601, full range) planes and alpha for AVIF.
+pub fn avif_planes(image: &DynamicImage) -> (Vec<[u16; 3]>, Option<Vec<u16>>) {
+	const KR: f32 = 0.299;
+	const KB: f32 = 0.114;
+	let scale = 1023.0 / 65535.0;
+	let image = image.to_rgba16();
+	let planes = image.pixels()
+	                  .map(|pixel| {
+		                  let [r, g, b, _] = pixel.0.map(|v| v as f32 * scale);
+		                  let y = KR * r + (1.0 - KR - KB) * g + KB * b;
+		                  let cb = (b - y) * 0.5 / (1.0 - KB) + 512.0;
+		                  let cr = (r - y) * 0.5 / (1.0 - KR) + 512.0;
+		                  [y, cb, cr].map(|v| v.round().clamp(0.0, 1023.0) as u16)
+	                  })
+	                  .collect();
+	let alpha = image.pixels()
+	                 .any(|pixel| pixel[3] != u16::MAX)
+	                 .then(|| image.pixels().map(|pixel| (pixel[3] as f32 * scale).round() as u16).collect());
+	(planes, alpha)
+}
@@ -0,0 +1,118 @@
+//! Device presets for `--device`.
+//!
+//! Preset sets several options at once, options given explicitly (also by env) take precedence.
+
+use clap::ArgMatches;
+use clap::ValueEnum;
+use clap::parser::ValueSource;
+use image::ImageOutputFormat;
+
+use crate::cli::{Config, SameFormat};
+
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Device {
+	/// Kindle Paperwhite 5, 1236x1648 e-ink.
+	Kpw5,
+	/// Kindle Oasis, 1264x1680 e-ink.
+	KindleOasis,
+	/// Kindle Scribe, 1860x2480 e-ink.
+	KindleScribe,
+	/// Kobo Clara, 1072x1448 e-ink.
+	KoboClara,
+	/// Kobo Libra, 1264x1680 e-ink.
+	KoboLibra,
+	/// Kobo Elipsa, 1404x1872 e-ink.
+	KoboElipsa,
+	/// iPad, 1640x2360.
+	Ipad,
+	/// iPad Pro 12.9", 2048x2732.
+	IpadPro,
+	/// Generic Android tablet, 1600x2560.
+	Tablet,
+}
+
+
+/// Options set by a preset.
+pub struct Preset {
+	pub width: u32,
+	pub height: u32,
+	pub grayscale: bool,
+	pub gamma: Option<f32>,
+	pub format: ImageOutputFormat,
+	pub quality: u8,
+}
+
+
+impl Device {
+	pub fn preset(self) -> Preset {
+		// e-ink readers show 16 gray levels and don't support modern formats in comics:
+		let eink = |width, height| {
+			Preset { width,
+			         height,
+			         grayscale: true,
+			         gamma: Some(0.8),
+			         format: ImageOutputFormat::Jpeg(85),
+			         quality: 85 }
+		};
+		let tablet = |width, height| {
+			Preset { width,
+			         height,
+			         grayscale: false,
+			         gamma: None,
+			         format: ImageOutputFormat::WebP,
+			         quality: 85 }
+		};
+		match self {
+			Self::Kpw5 => eink(1236, 1648),
+			Self::KindleOasis => eink(1264, 1680),
+			Self::KindleScribe => eink(1860, 2480),
+			Self::KoboClara => eink(1072, 1448),
+			Self::KoboLibra => eink(1264, 1680),
+			Self::KoboElipsa => eink(1404, 1872),
+			Self::Ipad => tablet(1640, 2360),
+			Self::IpadPro => tablet(2048, 2732),
+			Self::Tablet => tablet(1600, 2560),
+		}
+	}
+}
+
+
+/// Apply `--device` preset to options not given explicitly.
+pub fn apply(cfg: &mut Config, matches: &ArgMatches) {
+	let Some(device) = cfg.device else { return };
+	let preset = device.preset();
+	debug!("device preset: {device:?}");
+
+	let unset = |id: &str| {
+		!matches!(
+		          matches.value_source(id),
+		          Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+		)
+	};
+	if unset("max_width") {
+		cfg.max_width = Some(preset.width);
+	}
+	if unset("max_height") {
+		cfg.max_height = Some(preset.height);
+	}
+	if unset("grayscale") {
+		cfg.grayscale = preset.grayscale;
+	}
+	if unset("gamma") {
+		cfg.gamma = preset.gamma;
+	}
+	if unset("format") {
+		cfg.format = preset.format;
+	}
+	if unset("quality") {
+		cfg.quality = preset.quality;
+	}
+	// pages are fit to the device even if their format is fine:
+	if unset("same_format") {
+		cfg.same_format = SameFormat::Reencode;
+	}
+	if unset("reencode_modern") {
+		cfg.reencode_modern = true;
+	}
+}
@@ -0,0 +1,185 @@
+//! `diff` command: compare an original archive with its converted one, to validate settings
+//! before converting a library. Pages are paired in natural order, since conversion changes
+//! their extensions and may rename them, other entries are paired by name.
+
+use std::collections::BTreeSet;
+use futures::{stream, StreamExt};
+use image::{DynamicImage, ImageFormat};
+use indicatif::HumanBytes;
+
+use crate::cli::DiffArgs;
+use crate::error::Error;
+use crate::{paths, pool};
+
+
+/// Page of one of the archives.
+struct Page {
+	name: String,
+	size: u64,
+	format: Option<ImageFormat>,
+	dimensions: Option<(u32, u32)>,
+}
+
+
+/// Comparison of a pair of pages.
+struct PageDiff {
+	original: Page,
+	converted: Page,
+	/// Largest difference of a color channel, `None` if a page can't be decoded.
+	delta: Option<u8>,
+	ssim: Option<f64>,
+}
+
+
+pub async fn run(args: &DiffArgs) -> Result<(), Error> {
+	let password = args.password.as_deref();
+	// junk is compared too:
+	let junk = paths::JunkFilter::new(false, Vec::new());
+	let (original, original_entries, _) = crate::archive_reader(&args.original, password, &junk).await?;
+	let (converted, converted_entries, _) = crate::archive_reader(&args.converted, password, &junk).await?;
+	let (original_pages, original_other) = split_pages(original_entries);
+	let (converted_pages, converted_other) = split_pages(converted_entries);
+
+	let original = std::sync::Arc::new(original);
+	let converted = std::sync::Arc::new(converted);
+	let compare_pair = |(a, b): (String, String)| {
+		let (original, converted) = (original.clone(), converted.clone());
+		async move {
+			let (mut data_a, mut data_b) = (Vec::new(), Vec::new());
+			original.read_file(&a, &mut data_a)?;
+			converted.read_file(&b, &mut data_b)?;
+			pool::run(move || compare(a, &data_a, b, &data_b)).await
+		}
+	};
+	let pairs = original_pages.iter().cloned().zip(converted_pages.iter().cloned());
+	let diffs: Vec<PageDiff> = stream::iter(pairs).map(compare_pair)
+	                                              .buffered(pool::threads())
+	                                              .collect::<Vec<_>>()
+	                                              .await
+	                                              .into_iter()
+	                                              .collect::<Result<_, _>>()?;
+
+	println!(
+	         "{:<24}{:<24}{:>18}{:>18}{:>10}{:>10}{:>7}{:>8}",
+	         "page", "converted", "original", "converted", "size", "new size", "delta", "ssim"
+	);
+	for diff in &diffs {
+		let delta = diff.delta.map_or("-".to_owned(), |delta| delta.to_string());
+		let ssim = diff.ssim.map_or("-".to_owned(), |ssim| format!("{ssim:.4}"));
+		println!(
+		         "{:<24}{:<24}{:>18}{:>18}{:>10}{:>10}{:>7}{:>8}",
+		         diff.original.name,
+		         diff.converted.name,
+		         diff.original.describe(),
+		         diff.converted.describe(),
+		         HumanBytes(diff.original.size).to_string(),
+		         HumanBytes(diff.converted.size).to_string(),
+		         delta,
+		         ssim
+		);
+	}
+
+	let size_a: u64 = diffs.iter().map(|diff| diff.original.size).sum();
+	let size_b: u64 = diffs.iter().map(|diff| diff.converted.size).sum();
+	println!();
+	println!(
+	         "{} pages: {} vs. {} ≈ {:.1}%",
+	         diffs.len(),
+	         HumanBytes(size_a),
+	         HumanBytes(size_b),
+	         size_b as f64 / size_a.max(1) as f64 * 100.0
+	);
+	let resized = diffs.iter().filter(|diff| diff.original.dimensions != diff.converted.dimensions).count();
+	if resized > 0 {
+		println!("{resized} pages have different dimensions, originals are scaled to compare pixels");
+	}
+	let ssims: Vec<_> = diffs.iter().filter_map(|diff| Some((diff.ssim?, diff))).collect();
+	if let Some((worst, page)) = ssims.iter().min_by(|a, b| a.0.total_cmp(&b.0)) {
+		let mean = ssims.iter().map(|(ssim, _)| ssim).sum::<f64>() / ssims.len() as f64;
+		println!("SSIM: mean {mean:.4}, worst {worst:.4} of {}", page.original.name);
+	}
+	if let Some(page) = diffs.iter().filter(|diff| diff.delta.is_some()).max_by_key(|diff| diff.delta) {
+		println!("Max delta: {} of {}", page.delta.unwrap_or_default(), page.original.name);
+	}
+	let undecoded = diffs.iter().filter(|diff| diff.delta.is_none()).count();
+	if undecoded > 0 {
+		println!("{undecoded} pages can't be decoded to compare pixels, e.g. AVIF");
+	}
+
+	if original_pages.len() != converted_pages.len() {
+		println!(
+		         "Page count differs: {} vs. {}, extra pages are not compared",
+		         original_pages.len(),
+		         converted_pages.len()
+		);
+	}
+	for name in original_other.difference(&converted_other) {
+		println!("Only in original: {name}");
+	}
+	for name in converted_other.difference(&original_other) {
+		println!("Only in converted: {name}");
+	}
+	if undecoded == diffs.len() {
+		return Err("No pages compared by pixels, AVIF pages can't be decoded".to_owned().into());
+	}
+	Ok(())
+}
+
+
+/// Names of pages in natural order and names of other entries.
+fn split_pages(entries: Vec<paths::StringEntry>) -> (Vec<String>, BTreeSet<String>) {
+	let numbers = paths::page_numbers(&entries);
+	let (mut pages, other): (Vec<_>, Vec<_>) = entries.into_iter()
+	                                                  .partition(|entry| numbers.contains_key(&entry.index));
+	pages.sort_by_key(|entry| numbers[&entry.index]);
+	(pages.into_iter().map(|entry| entry.uri).collect(), other.into_iter().map(|entry| entry.uri).collect())
+}
+
+
+fn compare(original: String, data_a: &[u8], converted: String, data_b: &[u8]) -> PageDiff {
+	let (image_a, image_b) = (image::load_from_memory(data_a).ok(), image::load_from_memory(data_b).ok());
+	let page = |name: String, data: &[u8], image: &Option<DynamicImage>| {
+		let dimensions = image.as_ref()
+		                      .map(|image| (image.width(), image.height()))
+		                      .or_else(|| crate::scale::dimensions(data, None));
+		Page { name,
+		       size: data.len() as u64,
+		       format: image::guess_format(data).ok(),
+		       dimensions }
+	};
+	let (original, converted) = (page(original, data_a, &image_a), page(converted, data_b, &image_b));
+	let (delta, ssim) = match (image_a, image_b) {
+		(Some(a), Some(b)) => {
+			// original is scaled to the converted one, like in `bench --ssim`:
+			let a = match a.width() == b.width() && a.height() == b.height() {
+				true => a,
+				false => a.resize_exact(b.width(), b.height(), image::imageops::FilterType::Triangle),
+			};
+			let delta = a.to_rgb8()
+			             .as_raw()
+			             .iter()
+			             .zip(b.to_rgb8().as_raw())
+			             .map(|(a, b)| a.abs_diff(*b))
+			             .max()
+			             .unwrap_or_default();
+			(Some(delta), Some(crate::bench::ssim(&a.to_luma8(), &b.to_luma8())))
+		},
+		_ => (None, None),
+	};
+	PageDiff { original,
+	           converted,
+	           delta,
+	           ssim }
+}
+
+
+impl Page {
+	/// `webp 1200x1800`
+	fn describe(&self) -> String {
+		let format = self.format.map_or("?".to_owned(), |format| format!("{format:?}").to_lowercase());
+		match self.dimensions {
+			Some((width, height)) => format!("{format} {width}x{height}"),
+			None => format,
+		}
+	}
+}
@@ -18,9 +18,18 @@ pub enum Error {
 	#[error("Encoding: {0}")]
 	ImageError(#[from] image::ImageError),
 
+	#[error("Json: {0}")]
+	Json(#[from] serde_json::Error),
+
 	#[error("Async task join: {0}")]
 	AsyncTaskError(#[from] tokio::task::JoinError),
 
+	#[error("Output {} already exists, skipping", .0.display())]
+	Exists(std::path::PathBuf),
+
+	#[error("Total output size cap reached")]
+	CapReached,
+
 	#[error("{0}")]
 	Other(String),
 }
@@ -0,0 +1,159 @@
+//! Output size estimated by converting a sample of pages, without writing anything,
+//! for `--estimate` and the `library` plan.
+
+use std::path::{Path, PathBuf};
+use futures::{stream, StreamExt};
+use indicatif::{HumanBytes, ProgressBar};
+
+use crate::cli::Config;
+use crate::error::Error;
+use crate::{paths, pool, similar, source};
+
+
+#[derive(Debug, Clone)]
+pub struct Estimate {
+	/// Image pages.
+	pub pages: usize,
+	/// Pages already WebP or AVIF.
+	pub modern: usize,
+	/// Pages converted as the sample.
+	pub sampled: usize,
+	pub source_size: u64,
+	/// Size of sampled pages before conversion.
+	pub sample_in: u64,
+	/// Size of sampled pages after conversion.
+	pub sample_out: u64,
+	pub kind: Option<source::Kind>,
+}
+
+
+impl Estimate {
+	/// All pages are WebP or AVIF already, so conversion would only repack them.
+	pub fn is_modern(&self) -> bool { self.pages > 0 && self.modern == self.pages }
+
+
+	/// Source size scaled by the ratio of the sample.
+	pub fn output_size(&self) -> u64 {
+		match self.sample_in {
+			0 => self.source_size,
+			sample_in => (self.source_size as f64 * self.sample_out as f64 / sample_in as f64) as u64,
+		}
+	}
+}
+
+
+/// Convert every `every`th page of the archive, starting from the first one, and extrapolate the output size.
+pub async fn estimate(path: &Path, cfg: &Config, every: usize) -> Result<Estimate, Error> {
+	let source_size = tokio::fs::metadata(path).await?.len();
+	let (reader, entries, _) = crate::archive_reader(path, cfg.password.as_deref(), &cfg.junk_filter()).await?;
+	let kind = reader.kind();
+	let numbers = paths::page_numbers(&entries);
+	let modern = entries.iter()
+	                    .filter(|entry| numbers.contains_key(&entry.index))
+	                    .filter(|entry| {
+		                    matches!(
+		                             image::ImageFormat::from_path(&entry.uri),
+		                             Ok(image::ImageFormat::WebP | image::ImageFormat::Avif)
+		)
+	                    })
+	                    .count();
+	let sample: Vec<_> = entries.into_iter()
+	                            .filter(|entry| numbers.get(&entry.index).is_some_and(|n| (n - 1) % every == 0))
+	                            .collect();
+	let sampled = sample.len();
+
+	let read_entry = |entry: paths::StringEntry| {
+		let mut buffer = Vec::new();
+		let res = reader.read_file(&entry.uri, &mut buffer).map(|_| buffer);
+		async move { (entry, res) }
+	};
+	let transcode_entry = |(entry, read): (paths::StringEntry, Result<Vec<u8>, Error>)| {
+		// similar pages of the sample must not be reused by the conversion:
+		let cfg = Config { reuse_similar: false,
+		                   ..cfg.clone() };
+		let origin = similar::Origin { archive: path.to_owned(),
+		                               page: entry.uri.clone() };
+		async move {
+			let data = read?;
+			let size = data.len() as u64;
+			let transcoded = pool::run(move || crate::transcode(cfg, data, entry.uri, None, origin, &Default::default())).await??;
+			Ok::<_, Error>((size, transcoded.iter().map(|page| page.data.len() as u64).sum::<u64>()))
+		}
+	};
+	let results: Vec<_> = stream::iter(sample).then(read_entry)
+	                                          .map(transcode_entry)
+	                                          .buffer_unordered(cfg.jobs.max(1))
+	                                          .collect()
+	                                          .await;
+
+	let (mut sample_in, mut sample_out) = (0, 0);
+	for res in results {
+		match res {
+			Ok((size, output)) => {
+				sample_in += size;
+				sample_out += output;
+			},
+			Err(err) => warn!("Sample page of '{}' failed: {err}", path.display()),
+		}
+	}
+	debug!("sample of '{}': {sample_in}b => {sample_out}b", path.display());
+	Ok(Estimate { pages: numbers.len(),
+	              modern,
+	              sampled,
+	              source_size,
+	              sample_in,
+	              sample_out,
+	              kind })
+}
+
+
+/// Estimate sources with `--estimate` and print the projection, `parallel` archives at once.
+pub async fn run(sources: Vec<PathBuf>, cfg: &Config, every: usize, parallel: usize, bar: &ProgressBar) -> Result<(), Error> {
+	let estimates: Vec<_> = stream::iter(sources).map(|path| {
+		                                             async move {
+			                                             let res = estimate(&path, cfg, every).await;
+			                                             bar.inc(1);
+			                                             (path, res)
+		                                             }
+	                                             })
+	                                             .buffered(parallel.max(1))
+	                                             .collect()
+	                                             .await;
+
+	let (mut source, mut output, mut failed) = (0, 0, 0);
+	for (path, res) in estimates {
+		match res {
+			Ok(estimate) => {
+				let line = format!(
+				                   "{}: {} (sampled {} of {} pages)",
+				                   path.display(),
+				                   summary(estimate.source_size, estimate.output_size()),
+				                   estimate.sampled,
+				                   estimate.pages
+				);
+				bar.suspend(|| println!("{line}"));
+				source += estimate.source_size;
+				output += estimate.output_size();
+			},
+			Err(err) => {
+				error!("Unable to estimate '{}': {err}", path.display());
+				failed += 1;
+			},
+		}
+	}
+	bar.suspend(|| println!("total: {}", summary(source, output)));
+	if failed > 0 {
+		warn!("{failed} archives can't be estimated");
+	}
+	Ok(())
+}
+
+
+/// `source => ~output (ratio%)` with human sizes.
+pub fn summary(source: u64, output: u64) -> String {
+	let ratio = match source {
+		0 => 100.0,
+		source => output as f64 / source as f64 * 100.0,
+	};
+	format!("{} => ~{} ({ratio:.1}%)", HumanBytes(source), HumanBytes(output))
+}
@@ -0,0 +1,93 @@
+//! External page encoder for `--encoder-cmd`.
+//!
+//! Decoded page is piped to the command's stdin and its stdout is the encoded page,
+//! so any encoder with stdin/stdout support can be used, e.g. `cjxl - - -q {quality}`.
+
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use clap::ValueEnum;
+use image::{DynamicImage, ImageError, ImageOutputFormat};
+use image::error::{EncodingError, ImageFormatHint};
+
+use crate::cli::Config;
+
+
+/// Command line with placeholders: `{quality}`, `{speed}`, `{width}`, `{height}`.
+#[derive(Debug, Clone)]
+pub struct EncoderCommand(Vec<String>);
+
+
+/// Format of the decoded page piped to the encoder.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum EncoderInput {
+	Png,
+	/// Binary PPM, fast to write and read, but without alpha.
+	Ppm,
+}
+
+
+pub fn parse_encoder_command(s: &str) -> Result<EncoderCommand, String> {
+	match shlex::split(s) {
+		Some(args) if !args.is_empty() => Ok(EncoderCommand(args)),
+		_ => Err(format!("Invalid encoder command '{s}'")),
+	}
+}
+
+
+impl EncoderCommand {
+	pub fn program(&self) -> &str { &self.0[0] }
+
+
+	pub fn encode(&self, image: &DynamicImage, cfg: &Config) -> Result<Vec<u8>, ImageError> {
+		let args: Vec<String> = self.0
+		                            .iter()
+		                            .map(|arg| {
+			                            arg.replace("{quality}", &cfg.quality.to_string())
+			                               .replace("{speed}", &cfg.speed.to_string())
+			                               .replace("{width}", &image.width().to_string())
+			                               .replace("{height}", &image.height().to_string())
+		                            })
+		                            .collect();
+
+		let mut input = Vec::new();
+		match cfg.encoder_input {
+			EncoderInput::Png => image.write_to(&mut Cursor::new(&mut input), ImageOutputFormat::Png)?,
+			EncoderInput::Ppm => {
+				use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+				let format = ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary));
+				DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut Cursor::new(&mut input), format)?
+			},
+		}
+
+		trace!("running {args:?}");
+		let mut child = Command::new(&args[0]).args(&args[1..])
+		                                      .stdin(Stdio::piped())
+		                                      .stdout(Stdio::piped())
+		                                      .stderr(Stdio::piped())
+		                                      .spawn()?;
+		// written from another thread, so the encoder can't block on a full stdout pipe:
+		let mut stdin = child.stdin.take().expect("stdin");
+		let writer = std::thread::spawn(move || stdin.write_all(&input));
+		let output = child.wait_with_output()?;
+		let written = writer.join().expect("encoder input thread");
+
+		if !output.status.success() {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			return Err(self.error(format!("{} ({})", output.status, stderr.trim())));
+		}
+		// the encoder may exit without reading everything, that's an error only if it failed:
+		if let Err(err) = written {
+			debug!("{}: stdin: {err}", self.program());
+		}
+		if output.stdout.is_empty() {
+			return Err(self.error("no output".to_owned()));
+		}
+		Ok(output.stdout)
+	}
+
+
+	fn error(&self, message: String) -> ImageError {
+		let format = ImageFormatHint::Name(self.program().to_owned());
+		ImageError::Encoding(EncodingError::new(format, format!("'{}' failed: {message}", self.program())))
+	}
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+
+
+/// Archives failed during the last run, with settings to re-run them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Failures {
+	/// Command line arguments of the failed run, without the binary name.
+	pub args: Vec<String>,
+	pub failed: Vec<Failure>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Failure {
+	pub path: PathBuf,
+	pub error: String,
+}
+
+
+impl Failures {
+	pub fn new(args: Vec<String>) -> Self {
+		Self { args,
+		       failed: Vec::new() }
+	}
+
+	pub async fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+		let path = path.as_ref();
+		debug!("reading failures from '{}'", path.display());
+		let data = tokio::fs::read(path).await?;
+		Ok(serde_json::from_slice(&data)?)
+	}
+
+	pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+		let path = path.as_ref();
+		debug!("writing failures to '{}'", path.display());
+		let data = serde_json::to_vec_pretty(self)?;
+		tokio::fs::write(path, data).await?;
+		Ok(())
+	}
+
+	/// Remove failures of a previous run, so `retry-failed` doesn't repeat archives converted since.
+	pub async fn clear(path: impl AsRef<Path>) -> Result<(), Error> {
+		match tokio::fs::remove_file(path.as_ref()).await {
+			Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+			_ => Ok(()),
+		}
+	}
+
+	/// Stored arguments with `overrides` applied.
+	/// Overrides are inserted before the `--` separator so they don't end up in the output dir.
+	pub fn retry_args(&self, overrides: &[String]) -> Vec<String> {
+		let mut args = self.args.clone();
+		let at = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+		args.splice(at..at, overrides.iter().cloned());
+		args
+	}
+}
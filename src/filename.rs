@@ -0,0 +1,271 @@
+//! Metadata parsed from release file names like `Series v03 c012 (2019) (digital) [Group].cbz`,
+//! for `--layout`, `--output-template` and `--meta-convert filename-to-comicinfo`.
+
+use std::path::Path;
+
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+	pub series: String,
+	pub volume: Option<String>,
+	/// Chapter or issue number.
+	pub chapter: Option<String>,
+	pub year: Option<u32>,
+}
+
+
+impl FileInfo {
+	/// ComicInfo.xml with the parsed fields.
+	pub fn to_comic_info(&self) -> String {
+		crate::comic_info::to_xml([
+			("Series", Some(self.series.clone())),
+			("Number", self.chapter.clone()),
+			("Volume", self.volume.clone()),
+			("Year", self.year.map(|year| year.to_string())),
+		])
+	}
+}
+
+
+/// Series, volume, chapter and year of the file name.
+pub fn parse(path: &Path) -> FileInfo {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let year = brackets(&stem).find_map(|group| group.trim().parse().ok().filter(|year| (1900..2100).contains(year)));
+	let mut info = FileInfo { year,
+	                          ..Default::default() };
+	let mut series = Vec::new();
+	let text = without_brackets(&stem).replace('_', " ");
+	let mut tokens = text.split_whitespace().peekable();
+	while let Some(token) = tokens.next() {
+		let lower = token.to_lowercase();
+		let (volume, chapter) = match lower.as_str() {
+			"v" | "v." | "vol" | "vol." | "volume" => (tokens.next_if(|next| is_number(next)).map(str::to_owned), None),
+			"c" | "c." | "ch" | "ch." | "chapter" => (None, tokens.next_if(|next| is_number(next)).map(str::to_owned)),
+			_ if lower.starts_with('#') && is_number(&lower[1..]) => (None, Some(token[1..].to_owned())),
+			_ => {
+				let numbered = |prefixes: &[&str]| {
+					prefixes.iter()
+					        .find_map(|prefix| lower.strip_prefix(prefix).filter(|rest| is_number(rest)))
+					        .map(str::to_owned)
+				};
+				(numbered(&["vol.", "vol", "v"]), numbered(&["ch.", "ch", "c"]))
+			},
+		};
+		match (volume, chapter) {
+			(None, None) if info.volume.is_none() && info.chapter.is_none() => series.push(token),
+			(None, None) => {},
+			(volume, chapter) => {
+				info.volume = info.volume.or(volume);
+				info.chapter = info.chapter.or(chapter);
+			},
+		}
+	}
+	// trailing number of the series is the issue number, e.g. `Series 012`:
+	if info.volume.is_none() && info.chapter.is_none() && series.len() > 1 {
+		if let Some(last) = series.last().filter(|last| is_number(last)) {
+			info.chapter = Some(last.to_string());
+			series.pop();
+		}
+	}
+	info.series = series.join(" ").trim_end_matches([' ', '-', '–']).to_owned();
+	if info.series.is_empty() {
+		info.series = stem.trim().to_owned();
+	}
+	info.volume = info.volume.map(trim_zeros);
+	info.chapter = info.chapter.map(trim_zeros);
+	info
+}
+
+
+/// Validate output name template for `--output-template`.
+pub fn parse_template(s: &str) -> Result<String, String> {
+	let sample = FileInfo { series: "Series".to_owned(),
+	                        volume: Some("1".to_owned()),
+	                        chapter: Some("1".to_owned()),
+	                        year: Some(2000) };
+	render(s, &sample, "stem").map(|_| s.to_owned())
+}
+
+
+/// Render output name template with `{series}`, `{volume}`, `{chapter}`, `{year}` and `{stem}` placeholders,
+/// `{volume:02}` pads numbers with zeros. Missing fields are empty, `/` separates folders.
+pub fn render(template: &str, info: &FileInfo, stem: &str) -> Result<String, String> {
+	let year = info.year.map(|year| year.to_string());
+	let mut result = String::with_capacity(template.len() + 16);
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		result.push_str(&rest[..start]);
+		let end = rest[start..].find('}')
+		                       .map(|end| start + end)
+		                       .ok_or_else(|| format!("Unclosed placeholder in '{template}'"))?;
+		let (key, width) = match rest[start + 1..end].split_once(':') {
+			Some((key, spec)) => {
+				// `0` alone is no padding:
+				let width = Some(spec).filter(|spec| !spec.is_empty() && spec.bytes().all(|b| b.is_ascii_digit()))
+				                      .and_then(|spec| spec.parse::<usize>().ok())
+				                      .ok_or_else(|| format!("Invalid width '{spec}' in '{template}'"))?;
+				(key, width)
+			},
+			None => (&rest[start + 1..end], 0),
+		};
+		let value = match key {
+			"series" => Some(info.series.as_str()),
+			"volume" => info.volume.as_deref(),
+			"chapter" => info.chapter.as_deref(),
+			"year" => year.as_deref(),
+			"stem" => Some(stem),
+			other => return Err(format!("Unknown placeholder '{other}' in '{template}'")),
+		};
+		result.push_str(&sanitize(&padded(value.unwrap_or_default(), width)));
+		rest = &rest[end + 1..];
+	}
+	result.push_str(rest);
+
+	// separators left around missing fields are dropped:
+	let parts: Vec<_> = result.split('/')
+	                          .map(|part| part.split_whitespace().collect::<Vec<_>>().join(" "))
+	                          .map(|part| part.trim_matches(['-', '_', ' ']).to_owned())
+	                          .collect();
+	if parts.iter().any(|part| part.is_empty() || part == "." || part == "..") {
+		return Err(format!("Invalid output name of template '{template}': '{result}'"));
+	}
+	Ok(parts.join("/"))
+}
+
+
+fn is_number(s: &str) -> bool {
+	!s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.') && s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+
+fn trim_zeros(s: String) -> String {
+	match s.trim_start_matches('0') {
+		"" => "0".to_owned(),
+		rest if rest.starts_with('.') => format!("0{rest}"),
+		rest => rest.to_owned(),
+	}
+}
+
+
+/// Integer part padded with zeros to `width`, so names sort naturally when compared as text.
+pub fn padded(number: &str, width: usize) -> String {
+	let (int, frac) = number.split_once('.').map_or((number, None), |(int, frac)| (int, Some(frac)));
+	match (int.parse::<u32>(), frac) {
+		(Ok(int), Some(frac)) => format!("{int:0width$}.{frac}"),
+		(Ok(int), None) => format!("{int:0width$}"),
+		_ => number.to_owned(),
+	}
+}
+
+
+/// Contents of top-level `(...)` and `[...]` groups, which hold year, source or scanner.
+fn brackets(s: &str) -> impl Iterator<Item = &str> {
+	s.split(['(', '['])
+	 .skip(1)
+	 .map(|group| group.split([')', ']']).next().unwrap_or_default())
+}
+
+
+/// Text outside of `(...)` and `[...]` groups.
+fn without_brackets(s: &str) -> String {
+	let mut depth = 0usize;
+	s.chars()
+	 .filter(|c| {
+		 match c {
+			 '(' | '[' | '{' => depth += 1,
+			 ')' | ']' | '}' => depth = depth.saturating_sub(1),
+			 _ => return depth == 0,
+		 }
+		 false
+	 })
+	 .collect()
+}
+
+
+/// Characters not allowed in file names on common filesystems are replaced.
+pub fn sanitize(name: &str) -> String {
+	let name: String = name.chars()
+	                       .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+	                       .collect();
+	name.trim().trim_end_matches('.').to_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn info(name: &str) -> FileInfo { parse(Path::new(name)) }
+
+	fn info_of(series: &str, volume: Option<&str>, chapter: Option<&str>, year: Option<u32>) -> FileInfo {
+		FileInfo { series: series.to_owned(),
+		           volume: volume.map(ToOwned::to_owned),
+		           chapter: chapter.map(ToOwned::to_owned),
+		           year }
+	}
+
+
+	#[test]
+	fn release_name() {
+		assert_eq!(
+		           info("Series v03 c012 (2019) [Group].cbz"),
+		           info_of("Series", Some("3"), Some("12"), Some(2019))
+		);
+		assert_eq!(
+		           info("Long Series Name Vol. 2 Ch. 7.5 (digital).cbz"),
+		           info_of("Long Series Name", Some("2"), Some("7.5"), None)
+		);
+	}
+
+
+	#[test]
+	fn hash_issue_number() {
+		assert_eq!(info("Batman #012 (2016).cbr"), info_of("Batman", None, Some("12"), Some(2016)));
+		// the name itself is the series if there is nothing else:
+		assert_eq!(info("#12.cbz"), info_of("#12", None, Some("12"), None));
+	}
+
+
+	#[test]
+	fn trailing_issue_number() {
+		assert_eq!(info("Saga 054 (2018).cbz"), info_of("Saga", None, Some("54"), Some(2018)));
+		assert_eq!(info("Saga - 000.cbz"), info_of("Saga", None, Some("0"), None));
+		// a number alone is the series:
+		assert_eq!(info("1602.cbz"), info_of("1602", None, None, None));
+	}
+
+
+	#[test]
+	fn without_numbers() {
+		assert_eq!(info("One Shot Story.cbz"), info_of("One Shot Story", None, None, None));
+		assert_eq!(info("Some_Title [Scans].cbz"), info_of("Some Title", None, None, None));
+	}
+
+
+	#[test]
+	fn render_template() {
+		let info = info_of("Series", Some("3"), Some("12.5"), Some(2019));
+		assert_eq!(render("{series}/{series} v{volume:02} c{chapter:03}", &info, "stem").unwrap(), "Series/Series v03 c012.5");
+		assert_eq!(render("{series} ({year}) {stem}", &info, "stem").unwrap(), "Series (2019) stem");
+		assert_eq!(render("{volume:0}-{chapter:1}", &info, "stem").unwrap(), "3-12.5");
+	}
+
+
+	#[test]
+	fn render_missing_fields() {
+		let info = info_of("Series", None, None, None);
+		assert_eq!(render("{series} - v{volume:02} ({year})", &info, "stem").unwrap(), "Series - v ()");
+		assert_eq!(render("{series} - {chapter}", &info, "stem").unwrap(), "Series");
+		assert!(render("{volume}/{series}", &info, "stem").is_err());
+	}
+
+
+	#[test]
+	fn render_invalid() {
+		let info = info_of("Series", None, None, None);
+		assert!(render("{series:}", &info, "stem").is_err());
+		assert!(render("{series:+2}", &info, "stem").is_err());
+		assert!(render("{series", &info, "stem").is_err());
+		assert!(render("{title}", &info, "stem").is_err());
+	}
+}
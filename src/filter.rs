@@ -0,0 +1,246 @@
+//! Chain of page filters for `--filters`, e.g. `autocrop,resize:1600,grayscale`.
+//!
+//! Filters run in order on the decoded page, after color management and adjustments,
+//! and each may turn a page into several ones. Other filters can be added from the library
+//! by implementing [`PageFilter`].
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+
+/// Maximum difference from the border color still treated as border by `autocrop`.
+const AUTOCROP_TOLERANCE: u8 = 16;
+
+
+pub trait PageFilter: Send + Sync {
+	/// Name with arguments as given to `--filters`, it is a part of settings of the run.
+	fn name(&self) -> String;
+
+	/// Filtered page, one or more.
+	fn apply(&self, image: DynamicImage) -> Vec<DynamicImage>;
+}
+
+
+/// Filters applied in order, each to all pages of the previous one.
+#[derive(Clone, Default)]
+pub struct Chain {
+	filters: Vec<Arc<dyn PageFilter>>,
+}
+
+
+impl Chain {
+	pub fn push(&mut self, filter: impl PageFilter + 'static) { self.filters.push(Arc::new(filter)); }
+
+
+	pub fn is_empty(&self) -> bool { self.filters.is_empty() }
+
+
+	pub fn apply(&self, image: DynamicImage) -> Vec<DynamicImage> {
+		let mut images = vec![image];
+		for filter in &self.filters {
+			trace!("filter: {}", filter.name());
+			images = images.into_iter().flat_map(|image| filter.apply(image)).collect();
+		}
+		images
+	}
+}
+
+
+impl Debug for Chain {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let names: Vec<_> = self.filters.iter().map(|filter| filter.name()).collect();
+		write!(f, "Chain({})", names.join(","))
+	}
+}
+
+
+/// Parse `--filters`: comma-separated names with an optional argument after `:`.
+pub fn parse(s: &str) -> Result<Chain, String> {
+	let mut chain = Chain::default();
+	for spec in s.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+		let (name, arg) = match spec.split_once(':') {
+			Some((name, arg)) => (name, Some(arg)),
+			None => (spec, None),
+		};
+		let invalid = || format!("Invalid argument of filter '{spec}'");
+		match (name, arg) {
+			("autocrop", None) => chain.push(Autocrop { tolerance: AUTOCROP_TOLERANCE }),
+			("autocrop", Some(arg)) => chain.push(Autocrop { tolerance: arg.parse().map_err(|_| invalid())? }),
+			("resize", Some(arg)) => {
+				let (width, height) = match arg.split_once('x') {
+					Some((width, height)) => (bound(width).ok_or_else(invalid)?, bound(height).ok_or_else(invalid)?),
+					None => (bound(arg).ok_or_else(invalid)?, None),
+				};
+				if width.is_none() && height.is_none() {
+					return Err(invalid());
+				}
+				chain.push(Resize { width, height });
+			},
+			("crop", Some(arg)) => {
+				let margins: Vec<u32> = arg.split('/')
+				                           .map(|margin| margin.parse())
+				                           .collect::<Result<_, _>>()
+				                           .map_err(|_| invalid())?;
+				// same order as CSS margins:
+				let [top, right, bottom, left] = match margins[..] {
+					[all] => [all; 4],
+					[vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+					[top, right, bottom, left] => [top, right, bottom, left],
+					_ => return Err(invalid()),
+				};
+				chain.push(Crop { top,
+				                  right,
+				                  bottom,
+				                  left });
+			},
+			("grayscale", None) => chain.push(Grayscale),
+			("rotate", Some(arg @ ("90" | "180" | "270"))) => chain.push(Rotate(arg.parse().unwrap())),
+			("rotate", _) => return Err(invalid()),
+			("grayscale", Some(_)) => return Err(format!("Filter '{name}' takes no argument")),
+			("resize" | "crop", None) => return Err(format!("Filter '{name}' needs an argument")),
+			_ => {
+				return Err(format!(
+				                   "Unknown filter '{name}', expected autocrop, resize, crop, grayscale or rotate"
+				))
+			},
+		}
+	}
+	Ok(chain)
+}
+
+
+/// Bound of `resize`, empty if not limited.
+fn bound(s: &str) -> Option<Option<u32>> {
+	match s {
+		"" => Some(None),
+		s => s.parse().ok().filter(|value| *value > 0).map(Some),
+	}
+}
+
+
+/// Trim borders of the color of the top-left corner, for scans with white or black margins.
+#[derive(Debug)]
+pub struct Autocrop {
+	pub tolerance: u8,
+}
+
+
+impl PageFilter for Autocrop {
+	fn name(&self) -> String { format!("autocrop:{}", self.tolerance) }
+
+
+	fn apply(&self, image: DynamicImage) -> Vec<DynamicImage> {
+		let luma = image.to_luma8();
+		let (width, height) = luma.dimensions();
+		if width == 0 || height == 0 {
+			return vec![image];
+		}
+		let border = luma.get_pixel(0, 0).0[0];
+		let is_border = |x: u32, y: u32| luma.get_pixel(x, y).0[0].abs_diff(border) <= self.tolerance;
+		let row = |y: u32| (0..width).all(|x| is_border(x, y));
+		let column = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| is_border(x, y));
+
+		let Some(top) = (0..height).find(|y| !row(*y)) else {
+			// blank page:
+			return vec![image];
+		};
+		let bottom = (top..height).rev().find(|y| !row(*y)).unwrap_or(top) + 1;
+		let left = (0..width).find(|x| !column(*x, top, bottom)).unwrap_or(0);
+		let right = (left..width).rev().find(|x| !column(*x, top, bottom)).unwrap_or(left) + 1;
+		if (left, top, right, bottom) == (0, 0, width, height) {
+			return vec![image];
+		}
+		trace!("autocrop: {width}x{height} -> {}x{} at {left},{top}", right - left, bottom - top);
+		vec![image.crop_imm(left, top, right - left, bottom - top)]
+	}
+}
+
+
+/// Downscale to fit within the width and height, like `--max-width` and `--max-height`.
+#[derive(Debug)]
+pub struct Resize {
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+}
+
+
+impl PageFilter for Resize {
+	fn name(&self) -> String {
+		let bound = |value: Option<u32>| value.map(|value| value.to_string()).unwrap_or_default();
+		format!("resize:{}x{}", bound(self.width), bound(self.height))
+	}
+
+
+	fn apply(&self, image: DynamicImage) -> Vec<DynamicImage> {
+		match crate::scale::fit_within(image.width(), image.height(), self.width, self.height) {
+			Some((width, height)) => vec![image.resize_exact(width, height, FilterType::Lanczos3)],
+			None => vec![image],
+		}
+	}
+}
+
+
+/// Cut fixed margins in pixels.
+#[derive(Debug)]
+pub struct Crop {
+	pub top: u32,
+	pub right: u32,
+	pub bottom: u32,
+	pub left: u32,
+}
+
+
+impl PageFilter for Crop {
+	fn name(&self) -> String { format!("crop:{}/{}/{}/{}", self.top, self.right, self.bottom, self.left) }
+
+
+	fn apply(&self, image: DynamicImage) -> Vec<DynamicImage> {
+		let width = image.width().saturating_sub(self.left + self.right);
+		let height = image.height().saturating_sub(self.top + self.bottom);
+		if width == 0 || height == 0 {
+			warn!("Margins of crop are bigger than the page {}x{}, not cropped", image.width(), image.height());
+			return vec![image];
+		}
+		vec![image.crop_imm(self.left, self.top, width, height)]
+	}
+}
+
+
+/// Same as `--grayscale`.
+#[derive(Debug)]
+pub struct Grayscale;
+
+
+impl PageFilter for Grayscale {
+	fn name(&self) -> String { "grayscale".to_owned() }
+
+
+	fn apply(&self, image: DynamicImage) -> Vec<DynamicImage> {
+		match image.color().has_alpha() {
+			true => vec![DynamicImage::ImageLumaA8(image.to_luma_alpha8())],
+			false => vec![DynamicImage::ImageLuma8(image.to_luma8())],
+		}
+	}
+}
+
+
+/// Rotate clockwise by degrees.
+#[derive(Debug)]
+pub struct Rotate(pub u16);
+
+
+impl PageFilter for Rotate {
+	fn name(&self) -> String { format!("rotate:{}", self.0) }
+
+
+	fn apply(&self, image: DynamicImage) -> Vec<DynamicImage> {
+		match self.0 {
+			90 => vec![image.rotate90()],
+			180 => vec![image.rotate180()],
+			270 => vec![image.rotate270()],
+			_ => vec![image],
+		}
+	}
+}
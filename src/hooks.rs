@@ -0,0 +1,163 @@
+//! Commands and webhooks run after each archive, for `--on-success`, `--on-failure` and `--webhook`.
+//!
+//! Commands get the outcome in environment variables `COMIC_STATUS` (`success` or `failure`),
+//! `COMIC_SOURCE`, `COMIC_OUTPUT`, `COMIC_SOURCE_SIZE`, `COMIC_OUTPUT_SIZE` and `COMIC_ERROR`,
+//! webhooks get the same as a JSON body of a POST request.
+//! Both are given [`TIMEOUT`], since the next archives are not reported while they run.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+
+use crate::error::Error;
+
+
+/// Longest run of a command or a webhook, the command is killed then.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+
+#[derive(Debug, Clone)]
+pub struct HookCommand(Vec<String>);
+
+
+/// `http://host[:port]/path` of `--webhook`.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+	host: String,
+	port: u16,
+	path: String,
+}
+
+
+#[derive(Serialize, Debug)]
+pub struct Outcome {
+	pub status: &'static str,
+	pub source: PathBuf,
+	pub output: Option<PathBuf>,
+	pub source_size: Option<u64>,
+	pub output_size: Option<u64>,
+	pub error: Option<String>,
+}
+
+
+impl Outcome {
+	pub fn success(source: &Path, output: &Path, source_size: u64, output_size: u64) -> Self {
+		Self { status: "success",
+		       source: source.to_owned(),
+		       output: Some(output.to_owned()),
+		       source_size: Some(source_size),
+		       output_size: Some(output_size),
+		       error: None }
+	}
+
+
+	pub fn failure(source: &Path, error: &str) -> Self {
+		Self { status: "failure",
+		       source: source.to_owned(),
+		       output: None,
+		       source_size: std::fs::metadata(source).ok().map(|meta| meta.len()),
+		       output_size: None,
+		       error: Some(error.to_owned()) }
+	}
+}
+
+
+pub fn parse_command(s: &str) -> Result<HookCommand, String> {
+	match shlex::split(s) {
+		Some(args) if !args.is_empty() => Ok(HookCommand(args)),
+		_ => Err(format!("Invalid command '{s}'")),
+	}
+}
+
+
+pub fn parse_webhook(s: &str) -> Result<Webhook, String> {
+	let Some(rest) = s.strip_prefix("http://") else {
+		return Err(format!(
+		                   "Unsupported webhook '{s}', only http:// is supported, \
+		                    for https run e.g. `curl` with `--on-success`"
+		));
+	};
+	let (authority, path) = match rest.find('/') {
+		Some(slash) => (&rest[..slash], &rest[slash..]),
+		None => (rest, "/"),
+	};
+	let (host, port) = match authority.rsplit_once(':') {
+		Some((host, port)) => (host, port.parse().map_err(|_| format!("Invalid port of webhook '{s}'"))?),
+		None => (authority, 80),
+	};
+	if host.is_empty() {
+		return Err(format!("Invalid webhook '{s}'"));
+	}
+	Ok(Webhook { host: host.to_owned(),
+	             port,
+	             path: path.to_owned() })
+}
+
+
+/// Run the command and call the webhook, failures are only logged.
+pub async fn run(outcome: &Outcome, command: Option<&HookCommand>, webhook: Option<&Webhook>) {
+	if let Some(command) = command {
+		match timeout(TIMEOUT, run_command(outcome, command)).await {
+			Ok(Ok(())) => {},
+			Ok(Err(err)) => warn!("Hook '{}' failed: {err}", command.0[0]),
+			Err(_) => warn!("Hook '{}' timed out after {}s", command.0[0], TIMEOUT.as_secs()),
+		}
+	}
+	if let Some(webhook) = webhook {
+		let url = format!("http://{}:{}{}", webhook.host, webhook.port, webhook.path);
+		match timeout(TIMEOUT, call_webhook(outcome, webhook)).await {
+			Ok(Ok(())) => {},
+			Ok(Err(err)) => warn!("Webhook {url} failed: {err}"),
+			Err(_) => warn!("Webhook {url} timed out after {}s", TIMEOUT.as_secs()),
+		}
+	}
+}
+
+
+async fn run_command(outcome: &Outcome, command: &HookCommand) -> Result<(), Error> {
+	let size = |size: Option<u64>| size.map(|size| size.to_string()).unwrap_or_default();
+	let path = |path: Option<&Path>| path.map(|path| path.as_os_str().to_owned()).unwrap_or_default();
+	let status = tokio::process::Command::new(&command.0[0]).args(&command.0[1..])
+	                                                        .env("COMIC_STATUS", outcome.status)
+	                                                        .env("COMIC_SOURCE", &outcome.source)
+	                                                        .env("COMIC_OUTPUT", path(outcome.output.as_deref()))
+	                                                        .env("COMIC_SOURCE_SIZE", size(outcome.source_size))
+	                                                        .env("COMIC_OUTPUT_SIZE", size(outcome.output_size))
+	                                                        .env("COMIC_ERROR", outcome.error.as_deref().unwrap_or_default())
+	                                                        .kill_on_drop(true)
+	                                                        .status()
+	                                                        .await?;
+	match status.success() {
+		true => Ok(()),
+		false => Err(format!("exited with {status}").into()),
+	}
+}
+
+
+async fn call_webhook(outcome: &Outcome, webhook: &Webhook) -> Result<(), Error> {
+	let body = serde_json::to_vec(outcome)?;
+	let host = match webhook.port {
+		80 => webhook.host.clone(),
+		port => format!("{}:{port}", webhook.host),
+	};
+	let head = format!(
+	                   "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+	                    Connection: close\r\n\r\n",
+	                   webhook.path,
+	                   host,
+	                   body.len()
+	);
+	let mut stream = tokio::net::TcpStream::connect((webhook.host.as_str(), webhook.port)).await?;
+	stream.write_all(head.as_bytes()).await?;
+	stream.write_all(&body).await?;
+
+	let mut response = Vec::new();
+	stream.read_to_end(&mut response).await?;
+	let status = String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_owned();
+	match status.split_whitespace().nth(1) {
+		Some(code) if code.starts_with('2') => Ok(()),
+		_ => Err(format!("response '{status}'").into()),
+	}
+}
@@ -0,0 +1,84 @@
+//! Resume journal of a batch conversion.
+//!
+//! Every started and finished archive is appended to the journal and synced to disk,
+//! so after a crash or power loss `--resume` skips finished archives and restarts the rest.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum Record<P> {
+	Started { path: P },
+	Done { path: P },
+}
+
+
+pub struct Journal {
+	path: PathBuf,
+	file: Mutex<File>,
+}
+
+
+impl Journal {
+	/// Open the journal, returns it with archives finished by the previous run if `resume`,
+	/// otherwise the journal is started over.
+	pub fn open(path: impl AsRef<Path>, resume: bool) -> Result<(Self, HashSet<PathBuf>), Error> {
+		let path = path.as_ref();
+		let mut done = HashSet::new();
+		if resume && path.try_exists()? {
+			debug!("reading journal '{}'", path.display());
+			for line in BufReader::new(File::open(path)?).lines() {
+				let line = line?;
+				// last line can be cut by a crash:
+				match serde_json::from_str(&line) {
+					Ok(Record::Done { path }) => {
+						done.insert(path);
+					},
+					Ok(Record::Started { .. }) => {},
+					Err(err) => warn!("skipping broken journal line: {err}"),
+				}
+			}
+		}
+
+		let file = std::fs::OpenOptions::new().create(true)
+		                                      .append(resume)
+		                                      .write(true)
+		                                      .truncate(!resume)
+		                                      .open(path)?;
+		let journal = Self { path: path.to_owned(),
+		                     file: Mutex::new(file) };
+		Ok((journal, done))
+	}
+
+
+	pub fn started(&self, path: &Path) -> Result<(), Error> { self.write(&Record::Started { path }) }
+
+	pub fn done(&self, path: &Path) -> Result<(), Error> { self.write(&Record::Done { path }) }
+
+
+	fn write(&self, record: &Record<&Path>) -> Result<(), Error> {
+		let mut line = serde_json::to_vec(record)?;
+		line.push(b'\n');
+		let mut file = self.file.lock().unwrap();
+		file.write_all(&line)?;
+		file.sync_data()?;
+		Ok(())
+	}
+
+
+	/// Remove the journal when the whole batch is finished.
+	pub fn remove(self) -> Result<(), Error> {
+		debug!("removing journal '{}'", self.path.display());
+		drop(self.file);
+		std::fs::remove_file(&self.path)?;
+		Ok(())
+	}
+}
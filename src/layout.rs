@@ -0,0 +1,60 @@
+//! Output paths following naming conventions of media servers, for `--layout`.
+//!
+//! Servers group books of a series by folder and read volume or chapter numbers from file names,
+//! so outputs are arranged as `Series/Series Vol. N.cbz`.
+
+use std::path::{Path, PathBuf};
+
+use crate::cli::Layout;
+use crate::comic_info;
+use crate::filename::{self, padded, sanitize, FileInfo};
+use crate::paths::StringEntry;
+use crate::source::Source;
+
+
+/// Book info from ComicInfo.xml of the archive, missing fields are taken from the file name.
+pub fn book_info(source: &Path, reader: &Source, entries: &[StringEntry]) -> FileInfo {
+	let parsed = filename::parse(source);
+	let xml = entries.iter()
+	                 .find(|entry| comic_info::is_comic_info(&entry.uri))
+	                 .and_then(|entry| {
+		                 let mut buffer = Vec::new();
+		                 reader.read_file(&entry.uri, &mut buffer).ok()?;
+		                 Some(String::from_utf8_lossy(&buffer).into_owned())
+	                 });
+	let Some(xml) = xml else {
+		return parsed;
+	};
+	FileInfo { series: comic_info::element(&xml, "Series").unwrap_or(parsed.series),
+	           volume: comic_info::element(&xml, "Volume").or(parsed.volume),
+	           chapter: comic_info::element(&xml, "Number").or(parsed.chapter),
+	           year: comic_info::element(&xml, "Year").and_then(|year| year.parse().ok())
+	                                                  .or(parsed.year) }
+}
+
+
+/// Output path relative to the output directory.
+pub fn relative_path(info: &FileInfo, layout: Layout, ext: &str) -> PathBuf {
+	let series = sanitize(&info.series);
+	let mut name = series.clone();
+	match layout {
+		Layout::Komga => {
+			if let Some(volume) = info.volume.as_deref() {
+				name.push_str(&format!(" v{}", padded(volume, 2)));
+			}
+			if let Some(chapter) = info.chapter.as_deref() {
+				name.push_str(&format!(" #{}", padded(chapter, 3)));
+			}
+		},
+		Layout::Kavita => {
+			if let Some(volume) = info.volume.as_deref() {
+				name.push_str(&format!(" Vol. {volume}"));
+			}
+			if let Some(chapter) = info.chapter.as_deref() {
+				name.push_str(&format!(" Ch. {chapter}"));
+			}
+		},
+	}
+	// not `with_extension`, names like `Series Vol. 1` have no extension to replace:
+	Path::new(&series).join(format!("{}.{ext}", sanitize(&name)))
+}
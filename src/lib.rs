@@ -0,0 +1,1730 @@
+#![feature(never_type)]
+
+#[macro_use]
+extern crate log;
+extern crate tokio;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tokio::fs::try_exists;
+use futures::TryFutureExt;
+use futures::{stream, StreamExt};
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+use tokio_util::sync::CancellationToken;
+use async_zip::ZipEntryBuilder;
+use async_zip::tokio::write::ZipFileWriter;
+use indicatif::MultiProgress;
+use image::ImageOutputFormat;
+use image::ImageEncoder;
+
+
+pub mod cli;
+pub mod logger;
+pub mod error;
+pub mod paths;
+pub mod failures;
+mod append;
+pub mod report;
+mod scale;
+mod sevenz;
+mod psd;
+pub mod slice;
+pub mod merge;
+pub mod split;
+pub mod watch;
+mod source;
+mod spread;
+mod volumes;
+mod atomic;
+pub mod progress;
+pub mod journal;
+pub mod memory;
+mod similar;
+pub mod pool;
+mod external;
+mod adaptive;
+mod comic_info;
+mod animation;
+mod strip;
+mod device;
+mod adjust;
+mod quantize;
+mod color;
+mod metadata;
+mod depth;
+mod comic_book_info;
+pub mod marker;
+pub mod removal;
+pub mod manifest;
+pub mod estimate;
+pub mod library;
+pub mod bench;
+pub mod diff;
+mod layout;
+mod filename;
+mod converter;
+pub mod filter;
+pub mod plugin;
+pub mod serve;
+pub mod hooks;
+pub mod notify;
+pub mod context;
+pub mod throttle;
+pub mod space;
+pub mod cap;
+pub mod spill;
+pub mod tune;
+
+use error::Error;
+use cli::Config;
+pub use converter::{Converter, ConverterBuilder};
+pub use progress::Event as ProgressEvent;
+
+
+/// Output archive. Writers are declared before the part file,
+/// so the file is closed before an incomplete one is removed on drop.
+enum ArchiveWriter {
+	Zip {
+		writer: ZipFileWriter<tokio::fs::File>,
+		options: cli::ZipOptions,
+		part: atomic::PartFile,
+	},
+	Sz(sevenz::SevenZipWriter, atomic::PartFile),
+}
+
+impl ArchiveWriter {
+	async fn open_file(path: impl AsRef<Path>, force: bool) -> Result<(tokio::fs::File, atomic::PartFile), Error> {
+		let path = path.as_ref();
+		debug!("opening output: '{}'", path.display());
+		let out_exists = try_exists(&path).await?;
+
+		if out_exists && !force {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::AlreadyExists,
+				format!("Output file already exists {}", path.display()),
+			).into());
+		}
+
+		if let Some(parent) = path.parent() {
+			tokio::fs::create_dir_all(parent).await?;
+		}
+
+		// stale part file of an interrupted run is overwritten:
+		let part = atomic::PartFile::new(path);
+		let output_file = tokio::fs::OpenOptions::new().write(true)
+		                                               .create(true)
+		                                               .truncate(true)
+		                                               .open(part.part())
+		                                               .await?;
+		Ok((output_file, part))
+	}
+
+	pub async fn open(path: impl AsRef<Path>, force: bool, cfg: &Config) -> Result<Self, Error> {
+		use cli::ArchiveType::*;
+		match cfg.archive {
+			// `keep` is resolved per input, zip is the default otherwise:
+			Cbz | Zip | Keep => Self::open_zip(path, force, cfg.zip.clone()).await,
+			Cb7 | SevenZip => Self::open_7z(path, force, cfg.sevenz.clone()).await,
+		}
+	}
+
+	pub async fn open_zip(path: impl AsRef<Path>, force: bool, options: cli::ZipOptions) -> Result<Self, Error> {
+		let (output_file, part) = Self::open_file(path, force).await?;
+		let writer = ZipFileWriter::new(output_file.compat_write());
+		Ok(Self::Zip { writer,
+		               options,
+		               part })
+	}
+
+	pub async fn open_7z(path: impl AsRef<Path>, force: bool, options: cli::SevenZipOptions) -> Result<Self, Error> {
+		let (output_file, part) = Self::open_file(path, force).await?;
+		let writer = sevenz::SevenZipWriter::new(output_file.into_std().await, &options)?;
+		Ok(Self::Sz(writer, part))
+	}
+
+
+	/// Write entry, `source_hash` is stored to the entry comment where supported.
+	pub async fn write_all(&mut self,
+	                       name: &str,
+	                       data: &[u8],
+	                       source_hash: Option<&str>,
+	                       modified: Option<SystemTime>)
+	                       -> Result<(), Error> {
+		debug!("writing '{name}' to output archive");
+		match self {
+			Self::Zip { writer, options, .. } => {
+				use cli::ZipCompression;
+				let store = match options.compression {
+					ZipCompression::Auto => paths::is_compressed(name),
+					ZipCompression::Always => false,
+					ZipCompression::Never => true,
+				};
+				let builder = if store {
+					trace!("storing '{name}' without compression");
+					ZipEntryBuilder::new(name.into(), async_zip::Compression::Stored)
+				} else {
+					let level = options.level
+					                   .map(async_zip::DeflateOption::Other)
+					                   .unwrap_or(async_zip::DeflateOption::Normal);
+					ZipEntryBuilder::new(name.into(), options.method.into()).deflate_option(level)
+				};
+				let builder = match source_hash {
+					Some(hash) => builder.comment(hash.into()),
+					None => builder,
+				};
+				let builder = match modified {
+					Some(time) => builder.last_modification_date(async_zip::ZipDateTime::from_chrono(&time.into())),
+					None => builder,
+				};
+				writer.write_entry_whole(builder, data).await?;
+			},
+
+			Self::Sz(writer, _) => writer.write_all(name, data, modified).await?,
+		}
+		Ok(())
+	}
+
+
+	/// Archive comment, only zip has one.
+	pub fn set_comment(&mut self, comment: String) {
+		match self {
+			Self::Zip { writer, .. } => writer.comment(comment),
+			Self::Sz(..) => debug!("7z has no archive comment, not preserving it"),
+		}
+	}
+
+
+	pub async fn close(self) -> Result<std::fs::Metadata, Error> {
+		let (meta, part) = match self {
+			Self::Zip { writer, part, .. } => {
+				let f = writer.close().await?.into_inner();
+				let meta = f.metadata().await?;
+				f.sync_data().await?;
+				(meta, part)
+			},
+			Self::Sz(writer, part) => (writer.close().await?, part),
+		};
+		part.commit().await?;
+		Ok(meta)
+	}
+}
+
+
+/// Converted entry ready to be written.
+struct Page {
+	/// Encoded page, spilled to `--temp-dir` while waiting for pages before it
+	data: spill::Buffer,
+	stats: report::PageStats,
+	/// Content hash for `--dedupe`
+	hash: Option<[u8; 32]>,
+	/// Hash of the source entry data and settings for `--append`, hex
+	source_hash: Option<String>,
+	/// Naming of a converted page, done on writing since `--dedupe skip` shifts page numbers,
+	/// `None` for pages named already.
+	naming: Option<Naming>,
+}
+
+
+struct Naming {
+	/// Number of the page among all pages of the source for `--rename-pages`.
+	number: Option<usize>,
+	/// Part of a split page.
+	part: Option<usize>,
+}
+
+
+/// Entry as read: size in the archive, data and whether it is damaged, with `--salvage`.
+type ReadEntry = Result<(usize, Vec<u8>, bool), Error>;
+
+
+pub struct ConversionResult {
+	pub src: PathBuf,
+	pub dst_path: PathBuf,
+	/// Total size of output volumes
+	pub dst_size: u64,
+	/// Successfully written pages
+	pub pages: Vec<report::PageStats>,
+	/// Pages missing in the output
+	pub failed: Vec<report::PageFailure>,
+}
+
+/// Bar of decompressed bytes of the entries.
+/// Sizes are not known for all archive types, then the size of the archive stands for them,
+/// pages are compressed images anyway.
+fn source_progress_bar(multibar: &MultiProgress,
+                       reader: &source::Source,
+                       entries: &[paths::StringEntry])
+                       -> indicatif::ProgressBar {
+	let text = reader.path()
+	                 .file_name()
+	                 .unwrap()
+	                 .to_string_lossy()
+	                 .to_string();
+	let sizes = reader.entry_sizes().unwrap_or_else(|err| {
+		                                debug!("unable to read sizes of entries: {err}");
+		                                Default::default()
+	                                });
+	let known: Option<u64> = entries.iter().map(|entry| sizes.get(&entry.uri).copied()).sum();
+	let len = known.or_else(|| std::fs::metadata(reader.path()).ok().map(|meta| meta.len()))
+	               .unwrap_or_default();
+	cli::bytes_progress_bar(multibar, len, text)
+}
+
+
+/// Advance the bar of [`source_progress_bar`] by the entry, growing it if sizes were underestimated.
+fn advance(bar: &indicatif::ProgressBar, bytes: usize) {
+	bar.inc(bytes as u64);
+	if bar.length().is_some_and(|len| bar.position() > len) {
+		bar.set_length(bar.position());
+	}
+}
+
+
+/// Output name of a converted page: renamed by `--rename-pages` with the page `number`,
+/// with the `part` of a split page and the `prefix` of a merged source.
+fn page_output_name(name: String,
+                    number: Option<usize>,
+                    part: Option<usize>,
+                    prefix: Option<&str>,
+                    cfg: &Config)
+                    -> Result<String, Error> {
+	let name = match (cfg.rename_pages.as_deref(), number) {
+		(Some(template), Some(number)) => {
+			let rendered = paths::render_page_name(template, number, &name)?;
+			// pages are renamed within their folders:
+			match Path::new(&name).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+				Some(dir) => dir.join(rendered).display().to_string(),
+				None => rendered,
+			}
+		},
+		_ => name,
+	};
+	let name = match part {
+		Some(part) => paths::with_part(&name, part),
+		None => name,
+	};
+	Ok(match prefix {
+		Some(prefix) => format!("{prefix}{name}"),
+		None => name,
+	})
+}
+
+
+/// Transcode on the pool, giving up after `secs` as `--timeout-fallback` says.
+async fn transcode_with_timeout(cfg: Config,
+                                data: Vec<u8>,
+                                name: String,
+                                rotate: Option<cli::Rotation>,
+                                origin: similar::Origin,
+                                secs: u64,
+                                cancel: &CancellationToken)
+                                -> Result<Vec<Transcoded>, Error> {
+	let timeout = std::time::Duration::from_secs(secs);
+	let attempts = match cfg.timeout_fallback {
+		cli::TimeoutFallback::Fast => vec![cfg.clone(), cfg.fastest()],
+		cli::TimeoutFallback::Copy => vec![cfg.clone()],
+	};
+	for (i, attempt) in attempts.into_iter().enumerate() {
+		let (started, on_start) = tokio::sync::oneshot::channel();
+		// an attempt given up on is cancelled, so it stops at the next check instead of holding the thread:
+		let attempt_cancel = cancel.child_token();
+		let job = {
+			let (data, name, origin, cancel) = (data.clone(), name.clone(), origin.clone(), attempt_cancel.clone());
+			move || {
+				started.send(()).ok();
+				transcode(attempt, data, name, rotate, origin, &cancel)
+			}
+		};
+		let run = pool::run(job);
+		tokio::pin!(run);
+		// time waiting for a free encode thread is not counted:
+		tokio::select! {
+			res = &mut run => return Ok(res??),
+			_ = on_start => {},
+		}
+		match tokio::time::timeout(timeout, &mut run).await {
+			Ok(res) => return Ok(res??),
+			Err(_) => attempt_cancel.cancel(),
+		}
+		match i {
+			0 => warn!("Timeout: encoding '{name}' took over {secs}s"),
+			_ => warn!("Timeout: encoding '{name}' with the fastest settings took over {secs}s"),
+		}
+	}
+	warn!("Copying '{name}' as-is after timeout");
+	let filename = output_filename(Path::new(&name), cfg.keep_structure);
+	Ok(vec![Transcoded::copy(filename.to_string_lossy().to_string(), data, &cfg)])
+}
+
+
+/// Convert entries of one source and write them to the output in order.
+/// `prefix` is prepended to names of converted entries.
+#[allow(clippy::too_many_arguments)]
+async fn convert_entries(reader: &Arc<source::Source>,
+                         mut entries: Vec<paths::StringEntry>,
+                         writer: &mut volumes::VolumeWriter,
+                         previous: Option<&append::Previous>,
+                         prefix: Option<&str>,
+                         cfg: &Config,
+                         cancel: &CancellationToken,
+                         bar: Option<indicatif::ProgressBar>)
+                         -> Result<(Vec<report::PageStats>, Vec<report::PageFailure>), Error> {
+	let jobs = cfg.jobs;
+	trace!("jobs per archive: {jobs}");
+	let source = reader.path().to_owned();
+	paths::sort_entries(&mut entries, cfg.order);
+	// pages of `--append` are reused only if converted with the same settings:
+	let settings = cfg.append.then(|| cfg.settings_hash());
+
+	// First page index => names of all pages of the strip, other pages of strips are not converted alone:
+	let strips = match cfg.stitch {
+		Some(max_height) => {
+			// every page is read, which would block the runtime:
+			let (reader, all, salvage) = (reader.clone(), entries.clone(), cfg.salvage);
+			let strips = tokio::task::spawn_blocking(move || stitch_groups(&reader, &all, max_height, salvage)).await??;
+			let joined: std::collections::HashSet<_> = strips.values().flat_map(|uris| &uris[1..]).collect();
+			entries.retain(|entry| !joined.contains(&entry.uri));
+			debug!("pages to stitch: {}, strips: {}", joined.len() + strips.len(), strips.len());
+			strips
+		},
+		None => Default::default(),
+	};
+
+	let budget_cfg;
+	let cfg = match cfg.target_archive_size {
+		Some(size) => {
+			let pages = paths::page_numbers(&entries).len().max(1);
+			debug!("target page size: {}b", size / pages as u64);
+			budget_cfg = Config { target_page_size: Some(size / pages as u64),
+			                      ..cfg.clone() };
+			&budget_cfg
+		},
+		None => cfg,
+	};
+
+	let pages = cfg.rename_pages.as_ref().map(|_| paths::page_numbers(&entries));
+	let rotate = match cfg.fix_orientation {
+		Some(_) => {
+			// every page is read, which would block the runtime:
+			let (reader, entries, salvage) = (reader.clone(), entries.clone(), cfg.salvage);
+			tokio::task::spawn_blocking(move || sideways_pages(&reader, &entries, salvage)).await??
+		},
+		None => Default::default(),
+	};
+	let cover = match cfg.cover_format {
+		Some(_) => cover_page(reader, &entries),
+		None => None,
+	};
+
+	// Entries are read and reserve memory in order,
+	// so the next page to write never waits for memory held by pages after it:
+	let salvage = cfg.salvage;
+	let read_entry = |mut entry: paths::StringEntry| {
+		let reader = reader.clone();
+		let strip = strips.get(&entry.index).cloned();
+		async move {
+			let start = std::time::Instant::now();
+			let res = match strip {
+				Some(uris) => {
+					debug!("stitching {uris:?}");
+					// stitched strip is passed on as lossless BMP:
+					entry.uri = Path::new(&entry.uri).with_extension("bmp").display().to_string();
+					// decoding pages would block the runtime:
+					let stitched = pool::run(move || stitch_pages(&reader, &uris)).await;
+					stitched.and_then(|stitched| stitched).map(|data| (data.len(), data, false))
+				},
+				None if salvage => {
+					debug!("reading '{}'", entry.uri);
+					let mut buffer = Vec::new();
+					reader.read_file_salvaged(&entry.uri, &mut buffer)
+					      .map(|damaged| (buffer.len(), buffer, damaged))
+				},
+				None => {
+					debug!("reading '{}'", entry.uri);
+					let mut buffer = Vec::new();
+					reader.read_file(&entry.uri, &mut buffer).map(|size| (size, buffer, false))
+				},
+			};
+			tune::record(tune::Stage::Read, start.elapsed());
+			let reserved = match res.as_ref() {
+				Ok((_, buffer, _)) => memory::reserve(memory::estimate(buffer)).await,
+				Err(_) => None,
+			};
+			(entry, res, reserved)
+		}
+	};
+
+	let convert_entry = |(entry, read, reserved): (paths::StringEntry, ReadEntry, _)| {
+		let source = &source;
+		let settings = &settings;
+		let page = pages.as_ref().and_then(|pages| pages.get(&entry.index).copied());
+		let rotate = cfg.fix_orientation.filter(|_| rotate.contains(&entry.index));
+		let name = entry.uri.to_owned();
+		let bar = &bar;
+		let page_cfg = match cfg.cover_format.as_ref() {
+			Some(format) if cover == Some(entry.index) => {
+				Config { format: format.to_owned(),
+				         adaptive: false,
+				         ..cfg.clone() }
+			},
+			_ => cfg.clone(),
+		};
+		// salvaged data is dropped rather than copied if it doesn't decode:
+		let damaged = matches!(read, Ok((.., true)));
+		let page_cfg = Config { strict: page_cfg.strict || damaged,
+		                        ..page_cfg };
+
+		let failed_name = name.clone();
+		// Convert read entries, then write to resulting archive
+		async move {
+			let (ar_size, buffer, _) = read?;
+			let raw_size = buffer.len();
+			let name = name.to_owned();
+			let source_hash = settings.as_ref()
+			                          .map(|settings| paths::hex(&paths::digest([&buffer[..], settings.as_bytes()])));
+
+			// TODO: mb. use name.filename instead of name
+
+			if ar_size == 0 {
+				Err(format!("no data in '{}:{name}'", source.display()).into())
+			} else if let Some((previous, indices)) =
+				previous.zip(source_hash.as_deref())
+				        .and_then(|(previous, hash)| previous.find(hash).map(|indices| (previous, indices)))
+			{
+				let mut pages = Vec::with_capacity(indices.len());
+				for index in indices {
+					let (output_name, data) = previous.read(*index).await?;
+					debug!("Unchanged: {name}, reusing {output_name}");
+					let stats = report::PageStats { name: name.clone(),
+					                                output_name,
+					                                source_size: raw_size,
+					                                output_size: data.len(),
+					                                dimensions: None,
+					                                similar_to: None };
+					let hash = cfg.dedupe.map(|_| paths::digest([&data[..]]));
+					pages.push(Page { data: spill::Buffer::spill(data).await?,
+					                  stats,
+					                  hash,
+					                  source_hash: source_hash.clone(),
+					                  naming: None });
+				}
+				if let Some(bar) = bar.as_ref() {
+					advance(bar, raw_size);
+				}
+				Ok((pages, reserved, page))
+			} else {
+				let _slot = throttle::slot().await;
+				debug!("transcoding '{name}'");
+				let origin = similar::Origin { archive: source.clone(),
+				                               page: name.clone() };
+				let transcoded = match cfg.page_timeout {
+					Some(secs) => {
+						transcode_with_timeout(page_cfg, buffer, name.clone(), rotate, origin, secs, cancel).await?
+					},
+					None => {
+						let (name, cancel) = (name.clone(), cancel.clone());
+						pool::run(move || transcode(page_cfg, buffer, name, rotate, origin, &cancel)).await??
+					},
+				};
+				let mut pages = Vec::with_capacity(transcoded.len());
+				for Transcoded { name: output_name,
+				                 data,
+				                 hash,
+				                 dimensions,
+				                 part,
+				                 similar_to, } in transcoded
+				{
+					// TODO: this log should be `info`:
+					debug!(
+					       "Encoded: {output_name}, new size: {}b vs. {}b ≈ {:.2}%",
+					       data.len(),
+					       raw_size,
+					       (data.len() as f64 / raw_size as f64) * 100.0
+					);
+					let stats = report::PageStats { name: name.clone(),
+					                                output_name,
+					                                source_size: raw_size,
+					                                output_size: data.len(),
+					                                dimensions,
+					                                similar_to };
+					pages.push(Page { data: spill::Buffer::spill(data).await?,
+					                  stats,
+					                  hash,
+					                  source_hash: source_hash.clone(),
+					                  naming: Some(Naming { number: page, part }) });
+				}
+				if let Some(bar) = bar.as_ref() {
+					advance(bar, raw_size);
+				}
+				Ok::<_, Error>((pages, reserved, page))
+			}
+		}.map_err(move |err| (failed_name, err))
+	};
+
+	// Content hash => name of already written entry:
+	let mut written = std::collections::HashMap::new();
+	// Numbers of pages skipped as duplicates, pages after them are renumbered:
+	let mut deduped = std::collections::BTreeSet::new();
+	// Names of written entries, to detect collisions of flattened folders:
+	let mut names = std::collections::HashSet::new();
+	let times = match cfg.preserve_times {
+		true => reader.modified_times().unwrap_or_else(|err| {
+			                                warn!("Unable to read modification times: {err}");
+			                                Default::default()
+		                                }),
+		false => Default::default(),
+	};
+	let mut stats = Vec::new();
+	let mut failed = Vec::new();
+
+	let total = entries.len();
+	progress::emit(progress::Event::ArchiveStart { path: source.clone(),
+	                                               pages: total });
+
+	// Conversion runs concurrently, but results are committed in the entries order:
+	let converted = stream::iter(entries.into_iter()).then(read_entry)
+	                                                 .map(convert_entry)
+	                                                 .buffered(jobs);
+	let mut converted = std::pin::pin!(converted);
+	let mut done = 0;
+	// Running compression ratio on the bar:
+	let label = source.file_name().unwrap_or_default().to_string_lossy().into_owned();
+	let (mut ratio_source, mut ratio_output) = (0, 0);
+	loop {
+		let res = tokio::select! {
+			res = converted.next() => match res {
+				Some(res) => res,
+				None => break,
+			},
+			_ = cancel.cancelled() => return Err(format!("Cancelled: {}", source.display()).into()),
+		};
+		done += 1;
+		// memory is released when pages are written:
+		let (pages, _reserved, number) = match res {
+			Ok(res) => res,
+			Err((name, err)) if cfg.strict => {
+				return Err(format!("'{name}' of '{}' failed: {err}", source.display()).into());
+			},
+			Err((name, err)) => {
+				error!("{err}");
+				failed.push(report::PageFailure { name,
+				                                  error: err.to_string() });
+				continue;
+			},
+		};
+		ratio_source += pages.first().map_or(0, |page| page.stats.source_size);
+		// pages skipped by `--page-plugin` have no output, but are renumbered over too:
+		let number = match pages.first() {
+			Some(page) => page.naming.as_ref().and_then(|naming| naming.number),
+			None => number,
+		};
+		let (count, mut skipped) = (pages.len(), 0);
+		for Page { data,
+		           stats: mut page,
+		           hash,
+		           source_hash,
+		           naming, } in pages
+		{
+			if let Some(hash) = hash {
+				if let Some(original) = written.get(&hash) {
+					match cfg.dedupe {
+						Some(cli::Dedupe::Skip) => {
+							info!("Duplicate: {} is the same as {original}, skipping", page.name);
+							skipped += 1;
+							continue;
+						},
+						_ => warn!("Duplicate: {} is the same as {original}", page.name),
+					}
+				} else {
+					written.insert(hash, page.name.clone());
+				}
+			}
+			if let Some(Naming { number, part }) = naming {
+				let number = number.map(|number| number - deduped.range(..number).count());
+				page.output_name = page_output_name(page.output_name, number, part, prefix, cfg)?;
+			}
+
+			let data = data.load().await?;
+			if !names.insert(page.output_name.clone()) {
+				match cfg.on_collision {
+					cli::OnCollision::Error => {
+						return Err(format!(
+						                   "Name collision: '{}' of '{}' is already written",
+						                   page.output_name, page.name
+						).into());
+					},
+					cli::OnCollision::Rename => {
+						let renamed = (2..).map(|n| paths::with_copy_number(&page.output_name, n))
+						                   .find(|name| !names.contains(name))
+						                   .expect("free name");
+						warn!("Collision: {} of '{}' renamed to {renamed}", page.output_name, page.name);
+						names.insert(renamed.clone());
+						page.output_name = renamed;
+					},
+				}
+			}
+
+			let name = &page.output_name;
+			let modified = times.get(&page.name).copied();
+			let start = std::time::Instant::now();
+			let res = writer.write_all(name, &data[..], source_hash.as_deref(), modified).await;
+			tune::record(tune::Stage::Write, start.elapsed());
+			match res {
+				Ok(_) => {
+					info!("Finished: {name}");
+					progress::emit(progress::Event::Page { archive: source.clone(),
+					                                       name: page.name.clone(),
+					                                       output: name.to_owned(),
+					                                       source_size: page.source_size,
+					                                       size: data.len(),
+					                                       done,
+					                                       total });
+					ratio_output += data.len();
+					if let Some(bar) = bar.as_ref() {
+						let ratio = ratio_output as f64 / ratio_source.max(1) as f64 * 100.0;
+						bar.set_message(format!("{label} {ratio:.1}%"));
+					}
+					stats.push(page);
+				},
+				// the whole output is cancelled, not the page:
+				Err(err @ Error::CapReached) => return Err(err),
+				Err(err) if cfg.strict => return Err(err),
+				Err(err) => {
+					error!("{err}");
+					failed.push(report::PageFailure { name: page.name,
+					                                  error: err.to_string() });
+				},
+			}
+		}
+		if let Some(number) = number.filter(|_| skipped == count) {
+			deduped.insert(number);
+		}
+	}
+
+	let minority = report::orientation_minority(stats.iter().filter_map(|page| Some((&page.name, page.dimensions?))));
+	if !minority.is_empty() && cfg.fix_orientation.is_none() {
+		warn!(
+		      "{} has mixed page orientation, {} pages differ: {:?}",
+		      source.display(),
+		      minority.len(),
+		      minority
+		);
+	}
+	if !failed.is_empty() {
+		warn!("{} of {total} pages of {} failed", failed.len(), source.display());
+	}
+
+	Ok((stats, failed))
+}
+
+
+/// Convert the source into the output. On error or cancellation by `cancel` the output is removed.
+pub async fn convert_all(mut inout: ProcessInOut,
+                         cfg: &Config,
+                         cancel: &CancellationToken,
+                         multibar: Option<MultiProgress>)
+                         -> Result<ConversionResult, Error> {
+	let source = inout.reader.path().to_owned();
+	let entries = std::mem::take(&mut inout.entries);
+	if cfg.preserve_comment {
+		if let Some(comment) = inout.reader.comment()? {
+			debug!("preserving archive comment: {}b", comment.len());
+			inout.writer.set_comment(comment);
+		}
+	}
+	let bar = multibar.map(|mb| source_progress_bar(&mb, &inout.reader, &entries));
+	let has_comic_info = entries.iter().any(|entry| comic_info::is_comic_info(&entry.uri)) ||
+	                     inout.previous
+	                          .as_ref()
+	                          .is_some_and(|previous| previous.generated().any(|(_, name)| comic_info::is_comic_info(&name)));
+	let converted = convert_entries(
+	                                &inout.reader,
+	                                entries,
+	                                &mut inout.writer,
+	                                inout.previous.as_ref(),
+	                                None,
+	                                cfg,
+	                                cancel,
+	                                bar,
+	).await;
+	let (stats, failed) = match converted {
+		Ok(converted) => converted,
+		Err(err) => {
+			// already closed volumes are complete archives, but the output as a whole is not:
+			inout.writer.discard().await;
+			return Err(err);
+		},
+	};
+
+	let comic_info = match cfg.meta_convert.filter(|_| !has_comic_info) {
+		Some(cli::MetaConvert::CbiToComicinfo) => {
+			let info = inout.reader.comment()?.and_then(|comment| comic_book_info::ComicBookInfo::parse(&comment));
+			info.map(|info| {
+				    info!("Converting ComicBookInfo of {} to ComicInfo.xml", source.display());
+				    info.to_comic_info()
+			    })
+		},
+		Some(cli::MetaConvert::FilenameToComicinfo) => {
+			let info = filename::parse(&source);
+			info!("ComicInfo.xml of {} from its name: {info:?}", source.display());
+			Some(info.to_comic_info())
+		},
+		None => None,
+	};
+	let written = async {
+		if let Some(xml) = comic_info {
+			inout.writer.write_all("ComicInfo.xml", xml.as_bytes(), None, None).await?;
+		}
+
+		if cfg.skip_converted {
+			inout.writer.write_all(marker::NAME, &marker::Marker::new(cfg).to_json(), None, None).await?;
+		}
+
+		// Keep entries of previous output which were not updated,
+		// but not pages of source entries which are gone or changed:
+		if let Some(previous) = inout.previous.as_ref() {
+			let mut updated: std::collections::HashSet<_> = stats.iter().map(|page| page.output_name.clone()).collect();
+			updated.insert(marker::NAME.to_owned());
+			for (index, name) in previous.generated().filter(|(_, name)| !updated.contains(name)) {
+				debug!("Keeping: {name}");
+				let (name, data) = previous.read(index).await?;
+				inout.writer.write_all(&name, &data, None, None).await?;
+			}
+		}
+		Ok::<_, Error>(())
+	};
+	if let Err(err) = written.await {
+		inout.writer.discard().await;
+		return Err(err);
+	}
+
+	let dst_size = inout.writer.close().await?;
+	if let Some(meta) = inout.source_meta.as_ref() {
+		if let Err(err) = preserve_file_meta(meta, &output_volumes(&inout.output, cfg.split_size.is_some())) {
+			warn!("Unable to preserve time and permissions of {}: {err}", source.display());
+		}
+	}
+
+	Ok(ConversionResult { src: source,
+	                      dst_path: inout.output,
+	                      dst_size,
+	                      pages: stats,
+	                      failed })
+}
+
+
+pub struct ProcessInOut {
+	reader: Arc<source::Source>,
+	/// Inner files remains to process, already resolved and filtered
+	entries: Vec<paths::StringEntry>,
+
+	output: PathBuf,
+	writer: volumes::VolumeWriter,
+	/// Previous output to update, with `--append`
+	previous: Option<append::Previous>,
+	/// Metadata of the source before the output is written, with `--preserve-file-times`
+	source_meta: Option<std::fs::Metadata>,
+}
+
+/// `rename` overrides the output file name, relative to `outdir`.
+pub async fn open_inout(source: impl AsRef<Path>,
+                        outdir: impl AsRef<Path>,
+                        rename: Option<PathBuf>,
+                        cfg: &Config)
+                        -> Result<ProcessInOut, Error> {
+	use cli::ArchiveType::*;
+	// the output may replace the source, so it's read first:
+	let source_meta = match cfg.preserve_file_times {
+		true => Some(tokio::fs::metadata(&source).await?),
+		false => None,
+	};
+	let (reader, mut entries, _) = archive_reader(&source, cfg.password.as_deref(), &cfg.junk_filter()).await?;
+	filter_working_files(&mut entries, cfg);
+	// marker of a previous conversion is replaced with the current one:
+	entries.retain(|entry| !marker::is_marker(&entry.uri));
+
+	let resolved;
+	let cfg = match cfg.archive {
+		Keep => {
+			let archive = Keep.for_input(source.as_ref(), reader.kind());
+			debug!("output archive type of '{}': {archive:?}", source.as_ref().display());
+			resolved = Config { archive, ..cfg.clone() };
+			&resolved
+		},
+		_ => cfg,
+	};
+	let book = || {
+		let info = layout::book_info(source.as_ref(), &reader, &entries);
+		debug!("book of '{}': {info:?}", source.as_ref().display());
+		info
+	};
+	let output = match (rename, cfg.layout, cfg.output_template.as_deref()) {
+		(Some(name), ..) if name.extension().is_none() => outdir.as_ref().join(name).with_extension(cfg.output_ext()),
+		(Some(name), ..) => outdir.as_ref().join(name),
+		(None, Some(layout), _) => outdir.as_ref().join(layout::relative_path(&book(), layout, cfg.output_ext())),
+		(None, None, Some(template)) => {
+			let stem = source.as_ref().file_stem().unwrap_or_default().to_string_lossy();
+			let name = filename::render(template, &book(), &stem)?;
+			outdir.as_ref().join(format!("{name}.{}", cfg.output_ext()))
+		},
+		(None, None, None) => {
+			let (prefix, suffix) = (cfg.prefix.as_deref().unwrap_or_default(), cfg.suffix.as_deref().unwrap_or_default());
+			paths::output_archive_path(&source, &outdir, cfg.output_ext(), prefix, suffix)
+		},
+	};
+
+	let previous = match cfg.archive {
+		Cbz | Zip if cfg.append => append::Previous::open(&output).await?,
+		_ if cfg.append => return Err("--append is supported only for zip output".to_owned().into()),
+		_ => None,
+	};
+	let (output, overwrite) = match previous {
+		Some(_) => (output, true),
+		None => existing_output(output, cfg).await?,
+	};
+	// previous output is replaced only when the new one is complete:
+	let force = cfg.force || overwrite;
+	let writer = volumes::VolumeWriter::open(&output, force, cfg).await?;
+	Ok(ProcessInOut { reader: Arc::new(reader),
+	                  entries,
+	                  output,
+	                  writer,
+	                  previous,
+	                  source_meta })
+}
+
+
+/// Apply `--on-exists` policy to the output path, returns the path and whether to overwrite it.
+async fn existing_output(output: PathBuf, cfg: &Config) -> Result<(PathBuf, bool), Error> {
+	use cli::OnExists;
+
+	if cfg.force || !try_exists(&output).await? {
+		return Ok((output, cfg.force));
+	}
+	let policy = match cfg.on_exists {
+		OnExists::Ask => ask_existing(&output).await?,
+		policy => policy,
+	};
+	match policy {
+		OnExists::Skip => Err(Error::Exists(output)),
+		OnExists::Overwrite => Ok((output, true)),
+		OnExists::Rename => {
+			let name = output.display().to_string();
+			for n in 2.. {
+				let renamed = PathBuf::from(paths::with_copy_number(&name, n));
+				if !try_exists(&renamed).await? {
+					info!("Output {name} exists, writing {}", renamed.display());
+					return Ok((renamed, false));
+				}
+			}
+			unreachable!("free name")
+		},
+		_ => Ok((output, false)),
+	}
+}
+
+
+/// Ask on the terminal what to do with the existing output, one question at a time.
+async fn ask_existing(output: &Path) -> Result<cli::OnExists, Error> {
+	use std::io::{BufRead, IsTerminal, Write};
+	use cli::OnExists;
+
+	static ASKING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+	if !std::io::stdin().is_terminal() {
+		return Err(format!(
+		                   "Output {} already exists, can't ask what to do without a terminal",
+		                   output.display()
+		).into());
+	}
+	let _asking = ASKING.lock().await;
+	let question = format!("Output {} already exists: [s]kip, [o]verwrite, [r]ename or [e]rror? ", output.display());
+	let answer = tokio::task::spawn_blocking(move || {
+		let mut stderr = std::io::stderr().lock();
+		let mut answer = String::new();
+		loop {
+			stderr.write_all(question.as_bytes())?;
+			stderr.flush()?;
+			answer.clear();
+			if std::io::stdin().lock().read_line(&mut answer)? == 0 {
+				return Ok::<_, std::io::Error>(OnExists::Error);
+			}
+			match answer.trim() {
+				"s" | "skip" => return Ok(OnExists::Skip),
+				"o" | "overwrite" => return Ok(OnExists::Overwrite),
+				"r" | "rename" => return Ok(OnExists::Rename),
+				"e" | "error" => return Ok(OnExists::Error),
+				_ => continue,
+			}
+		}
+	}).await??;
+	Ok(answer)
+}
+
+
+/// Apply `--working-files` policy.
+fn filter_working_files(entries: &mut Vec<paths::StringEntry>, cfg: &Config) {
+	entries.retain(|entry| {
+		       if !paths::is_working_file(&entry.uri) {
+			       return true;
+		       }
+		       let keep = match cfg.working_files {
+			       cli::WorkingFiles::Copy => true,
+			       cli::WorkingFiles::Flatten => paths::is_flattenable(&entry.uri),
+			       cli::WorkingFiles::Drop => false,
+		       };
+		       if keep {
+			       info!("working file '{}' ({:?})", entry.uri, cfg.working_files);
+		       } else {
+			       info!("dropping working file '{}'", entry.uri);
+		       }
+		       keep
+	       });
+}
+
+
+async fn archive_reader(path: impl AsRef<Path>,
+                        password: Option<&str>,
+                        junk: &paths::JunkFilter)
+                        -> Result<(source::Source, Vec<paths::StringEntry>, usize), Error> {
+	debug!("opening input: '{}'", path.as_ref().display());
+	let archive = source::Source::open(path, password)?;
+
+	trace!("filtering inner files");
+	let names = archive.list_file_names()?;
+	let total = names.len();
+	let names = paths::filter_entries(names.into_iter().enumerate().map(paths::Entry::from), junk);
+	let names: Vec<_> = paths::remove_root_entry(names).collect();
+	debug!("total: {total}, outfiltered: {}", total - names.len());
+	Ok((archive, names, total))
+}
+
+
+/// Output and its next volumes written with `--split-size`.
+pub fn output_volumes(path: &Path, split: bool) -> Vec<PathBuf> {
+	let volumes = (2..).map(|n| paths::volume_path(path, n))
+	                   .take_while(|path| split && path.exists());
+	std::iter::once(path.to_owned()).chain(volumes).collect()
+}
+
+
+/// Copy modification time and permissions of the source to outputs, for `--preserve-file-times`.
+fn preserve_file_meta(meta: &std::fs::Metadata, outputs: &[PathBuf]) -> Result<(), Error> {
+	let modified = meta.modified()?;
+	for output in outputs {
+		std::fs::File::options().write(true).open(output)?.set_modified(modified)?;
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			// archives are never executable, and the owner can always replace them:
+			let mode = (meta.permissions().mode() & 0o666) | 0o600;
+			std::fs::set_permissions(output, std::fs::Permissions::from_mode(mode))?;
+		}
+	}
+	Ok(())
+}
+
+
+/// Read back every entry of outputs, for `--verify`, off the async runtime.
+pub async fn verify_output(outputs: &[PathBuf]) -> Result<(), Error> {
+	let outputs = outputs.to_vec();
+	tokio::task::spawn_blocking(move || {
+		for path in &outputs {
+			let entries = source::Source::open(path, None)?.verify()?;
+			debug!("verified {entries} entries of '{}'", path.display());
+		}
+		Ok(())
+	}).await?
+}
+
+
+/// Index of the cover entry: marked in ComicInfo.xml or the first page.
+fn cover_page(reader: &source::Source, entries: &[paths::StringEntry]) -> Option<usize> {
+	let numbers = paths::page_numbers(entries);
+	let marked = entries.iter()
+	                    .find(|entry| comic_info::is_comic_info(&entry.uri))
+	                    .and_then(|entry| {
+		                    let mut buffer = Vec::new();
+		                    reader.read_file(&entry.uri, &mut buffer).ok()?;
+		                    comic_info::front_cover(&String::from_utf8_lossy(&buffer))
+	                    });
+	let page = marked.map(|index| index + 1).unwrap_or(1);
+	let cover = numbers.into_iter().find(|(_, number)| *number == page).map(|(index, _)| index);
+	debug!("cover: page {page}, entry {cover:?}");
+	cover
+}
+
+
+/// Indices of image entries with orientation of the minority.
+/// With `salvage` unreadable pages are left out.
+fn sideways_pages(reader: &source::Source,
+                  entries: &[paths::StringEntry],
+                  salvage: bool)
+                  -> Result<std::collections::HashSet<usize>, Error> {
+	let mut sizes = Vec::new();
+	for entry in entries.iter()
+	                    .filter(|entry| image::ImageFormat::from_path(&entry.uri).is_ok())
+	{
+		let mut buffer = Vec::new();
+		if let Err(err) = reader.read_file(&entry.uri, &mut buffer) {
+			if !salvage {
+				return Err(err);
+			}
+			debug!("'{}' is unreadable: {err}", entry.uri);
+		}
+		let format = image::ImageFormat::from_path(&entry.uri).ok();
+		if let Some(size) = scale::dimensions(&buffer, format) {
+			sizes.push((entry.index, size));
+		}
+	}
+	let minority = report::orientation_minority(sizes);
+	debug!("pages to rotate: {}", minority.len());
+	Ok(minority.into_iter().collect())
+}
+
+
+/// Consecutive pages of the same width which fit into `max_height` together,
+/// first page index => names of all pages of the group. Single pages are not included.
+/// With `salvage` unreadable pages break strips as non-images do.
+fn stitch_groups(reader: &source::Source,
+                 entries: &[paths::StringEntry],
+                 max_height: u32,
+                 salvage: bool)
+                 -> Result<std::collections::HashMap<usize, Vec<String>>, Error> {
+	// width, total height, pages:
+	let mut groups: Vec<(u32, u32, Vec<&paths::StringEntry>)> = Vec::new();
+	for entry in entries {
+		let size = match image::ImageFormat::from_path(&entry.uri) {
+			Ok(format) => {
+				let mut buffer = Vec::new();
+				if let Err(err) = reader.read_file(&entry.uri, &mut buffer) {
+					if !salvage {
+						return Err(err);
+					}
+					debug!("'{}' is unreadable: {err}", entry.uri);
+				}
+				scale::dimensions(&buffer, Some(format))
+			},
+			Err(_) => None,
+		};
+		match (size, groups.last_mut()) {
+			(Some((w, h)), Some((width, height, pages)))
+				if !pages.is_empty() && *width == w && *height + h <= max_height =>
+			{
+				*height += h;
+				pages.push(entry);
+			},
+			(Some((w, h)), _) => groups.push((w, h, vec![entry])),
+			// non-images break strips:
+			(None, _) => groups.push((0, 0, Vec::new())),
+		}
+	}
+	Ok(groups.into_iter()
+	         .filter(|(.., pages)| pages.len() > 1)
+	         .map(|(.., pages)| (pages[0].index, pages.iter().map(|entry| entry.uri.clone()).collect()))
+	         .collect())
+}
+
+
+/// Read and decode pages, join them into one image encoded as BMP.
+fn stitch_pages(reader: &source::Source, uris: &[String]) -> Result<Vec<u8>, Error> {
+	let mut pages = Vec::with_capacity(uris.len());
+	for uri in uris {
+		let mut buffer = Vec::new();
+		reader.read_file(uri, &mut buffer)?;
+		pages.push(image::load_from_memory(&buffer)?);
+	}
+	let mut data = Vec::new();
+	strip::stitch(&pages).write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Bmp)?;
+	Ok(data)
+}
+
+
+struct Transcoded {
+	name: String,
+	data: Vec<u8>,
+	/// Content hash for `--dedupe`: decoded pixels of transcoded images, raw data otherwise.
+	hash: Option<[u8; 32]>,
+	/// Resolution of the source image
+	dimensions: Option<(u32, u32)>,
+	/// Part number of split spread or tall page, starting from 1
+	part: Option<usize>,
+	/// Similar page whose encode was reused
+	similar_to: Option<similar::Origin>,
+}
+
+/// Name of the page in the output, inner folders are dropped unless `keep_structure`.
+fn output_filename(uri: &Path, keep_structure: bool) -> std::ffi::OsString {
+	match keep_structure {
+		true => uri.as_os_str().to_owned(),
+		false => uri.file_name().expect("filename").to_owned(),
+	}
+}
+
+
+impl Transcoded {
+	/// Entry copied as-is, except of metadata with `--strip-metadata`.
+	fn copy(name: String, data: Vec<u8>, cfg: &Config) -> Self {
+		let data = match cfg.strip_metadata {
+			true => metadata::strip(data, image::ImageFormat::from_path(&name).ok()),
+			false => data,
+		};
+		let hash = cfg.dedupe.map(|_| paths::digest([&data[..]]));
+		let dimensions = scale::dimensions(&data, None);
+		Self { name,
+		       data,
+		       hash,
+		       dimensions,
+		       part: None,
+		       similar_to: None }
+	}
+}
+
+
+fn transcode<S: AsRef<str> + Debug>(cfg: Config,
+                                    data: Vec<u8>,
+                                    name: S,
+                                    rotate: Option<cli::Rotation>,
+                                    origin: similar::Origin,
+                                    cancel: &CancellationToken)
+                                    -> Result<Vec<Transcoded>, image::ImageError> {
+	check_cancelled(cancel)?;
+	// the rest uses `quality` of the output format:
+	let cfg = Config { quality: cfg.format_quality(),
+	                   ..cfg };
+	let uri = Path::new(name.as_ref());
+	let filename = output_filename(uri, cfg.keep_structure);
+	let format = uri.extension()
+	                .and_then(|ext| ext.to_str())
+	                .map(image::ImageFormat::from_extension)
+	                .flatten();
+
+	if format.is_none() {
+		if let Some(ext) = uri.extension() {
+			match ext.to_string_lossy().as_ref().to_lowercase().as_str() {
+				"txt" | "md" | "xml" | "html" | "svg" | "info" | "json" | "yml" | "yaml" => {
+					debug!("'{}' Seems to text, so just copying as-is.", uri.display());
+					return Ok(vec![Transcoded::copy(name.as_ref().to_string(), data, &cfg)]);
+				},
+				_ => {},
+			}
+		}
+	}
+
+	let out_format = match &cfg.format {
+		ImageOutputFormat::Jpeg(_) => ImageOutputFormat::Jpeg(cfg.quality.clamp(0, 100)),
+		format => format.to_owned(),
+	};
+
+	let dimensions = scale::dimensions(&data, format);
+	let split = rotate.is_none() && cfg.split_spreads.is_some() && dimensions.is_some_and(|(w, h)| w > h);
+	let tall = rotate.is_none() &&
+	           !split &&
+	           cfg.split_tall
+	              .is_some_and(|max| dimensions.is_some_and(|(_, h)| h > max));
+	// rotated, split and adjusted pages are re-encoded even if the format is fine:
+	let filters = cfg.filters.as_ref().filter(|filters| !filters.is_empty());
+	let modify = rotate.is_some() ||
+	             split ||
+	             tall ||
+	             filters.is_some() ||
+	             cfg.page_plugin.is_some() ||
+	             cfg.alters_pixels() ||
+	             cfg.force_reencode;
+
+	if !modify && format.is_some() && format == image_format(&out_format) {
+		match cfg.same_format {
+			cli::SameFormat::Skip => {
+				warn!("SKIP with reason: same format: {out_format:?}");
+				return Ok(vec![Transcoded::copy(filename.to_string_lossy().to_string(), data, &cfg)]);
+			},
+			cli::SameFormat::Copy => {
+				debug!("copying with reason: same format: {out_format:?}");
+				let name = Path::new(&filename).with_extension(cfg.page_ext());
+				return Ok(vec![Transcoded::copy(name.display().to_string(), data, &cfg)]);
+			},
+			cli::SameFormat::Reencode => debug!("re-encoding same format: {out_format:?}"),
+		}
+	}
+
+	if !modify &&
+	   !cfg.reencode_modern &&
+	   matches!(
+	            format,
+	            Some(image::ImageFormat::WebP) | Some(image::ImageFormat::Avif)
+	) {
+		warn!(
+		      "SKIP with reason: src is already good format: {:?}",
+		      format.as_ref().unwrap()
+		);
+		return Ok(vec![Transcoded::copy(filename.to_string_lossy().to_string(), data, &cfg)]);
+	}
+
+
+	if cfg.animation != cli::Animation::FirstFrame {
+		let frames = format.and_then(|format| animation::frames(&data, format).ok().flatten());
+		if let Some(frames) = frames {
+			debug!("'{}' is animated, {} frames", uri.display(), frames.len());
+			let name = Path::new(&filename).with_extension(cfg.page_ext()).display().to_string();
+			let webp = cfg.format == ImageOutputFormat::WebP && cfg.encoder_cmd.is_none();
+			match cfg.animation {
+				cli::Animation::Keep if webp => {
+					let data = animation::encode_webp(&frames, &cfg)?;
+					return Ok(vec![Transcoded::copy(name, data, &cfg)]);
+				},
+				_ => {
+					debug!("copying animated '{}' as-is", uri.display());
+					return Ok(vec![Transcoded::copy(filename.to_string_lossy().to_string(), data, &cfg)]);
+				},
+			}
+		}
+	}
+
+	let mut load_cfg = cfg.clone();
+	if rotate.is_some() {
+		// bounds are applied before rotation:
+		std::mem::swap(&mut load_cfg.max_width, &mut load_cfg.max_height);
+	}
+	if split {
+		// bounds are applied to halves:
+		load_cfg.max_width = None;
+		load_cfg.max_height = None;
+	}
+	if tall {
+		// bounds are applied to pieces:
+		load_cfg.max_height = None;
+	}
+	let profile = match (cfg.color_management, format) {
+		(cli::ColorManagement::On, Some(format)) => {
+			color::icc_profile(&data, format).and_then(|icc| color::Profile::parse(&icc))
+		},
+		_ => None,
+	};
+	let cmyk = profile.as_ref().and_then(|profile| profile.decode_cmyk(&data));
+	let image = if let Some(image) = cmyk {
+		debug!("converted CMYK of '{}' to sRGB", uri.display());
+		Ok(scale::fit(image, &load_cfg))
+	} else if cfg.working_files == cli::WorkingFiles::Flatten && paths::is_flattenable(uri) {
+		debug!("flattening '{}'", uri.display());
+		psd::decode(&data).map(|image| scale::fit(image, &load_cfg))
+	} else {
+		scale::load(&data, format, &load_cfg)
+	};
+	let keep_16bit = cfg.keep_16bit &&
+	                 cfg.encoder_cmd.is_none() &&
+	                 matches!(cfg.format, ImageOutputFormat::Png | ImageOutputFormat::Avif);
+	let image = image.map(|image| depth::normalize(image, keep_16bit));
+	let image = image.map(|image| {
+		                 match profile {
+			                 Some(profile) => {
+				                 debug!("converting '{}' to sRGB", uri.display());
+				                 profile.to_srgb(image)
+			                 },
+			                 None => image,
+		                 }
+	                 });
+	let image = image.map(|image| {
+		                 match rotate {
+			                 Some(cli::Rotation::Cw) => image.rotate90(),
+			                 Some(cli::Rotation::Ccw) => image.rotate270(),
+			                 None => image,
+		                 }
+	                 });
+	let image = image.map(|image| {
+		                 match cfg.grayscale {
+			                 true if image.color().has_alpha() => image::DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+			                 true => image::DynamicImage::ImageLuma8(image.to_luma8()),
+			                 false => image,
+		                 }
+	                 });
+	let image = image.map(|image| adjust::apply(image, &cfg));
+	let images = image.map(|image| {
+		                  match filters {
+			                  Some(filters) => filters.apply(image),
+			                  None => vec![image],
+		                  }
+	                  });
+	let images = match (images, cfg.page_plugin.as_ref()) {
+		(Ok(images), Some(plugin)) => {
+			let mut kept = Vec::with_capacity(images.len());
+			for image in images {
+				match plugin.process(image, name.as_ref())? {
+					plugin::Decision::Keep(image) => kept.push(image),
+					plugin::Decision::Skip => debug!("plugin skipped '{}'", uri.display()),
+					plugin::Decision::Copy => {
+						debug!("plugin copies '{}' as-is", uri.display());
+						return Ok(vec![Transcoded::copy(filename.to_string_lossy().to_string(), data, &cfg)]);
+					},
+				}
+			}
+			if kept.is_empty() {
+				return Ok(Vec::new());
+			}
+			Ok(kept)
+		},
+		(images, _) => images,
+	};
+
+
+	let images = match images {
+		Err(err) if cfg.strict && format.is_some() => return Err(err),
+		images => images,
+	};
+	if let Ok(images) = images {
+		trace!(
+		       "original image: {}, len: {} ({format:?}, {:?})",
+		       uri.display(),
+		       data.len(),
+		       images.first().map(|image| image.color())
+		);
+
+		let parts: Vec<_> = images.into_iter()
+		                          .flat_map(|image| {
+			                          match (cfg.split_spreads, cfg.split_tall) {
+				                          (Some(mode), _) if split => {
+					                          spread::split(&image, mode, cfg.rtl).into_iter()
+					                                                              .map(|part| scale::fit(part, &cfg))
+					                                                              .collect()
+				                          },
+				                          (_, Some(max_height)) if tall => {
+					                          strip::split(&image, max_height, cfg.split_tall_overlap)
+						                          .into_iter()
+						                          .map(|part| scale::fit(part, &cfg))
+						                          .collect()
+				                          },
+				                          _ => vec![image],
+			                          }
+		                          })
+		                          .collect();
+		// filters may split pages too:
+		let numbered = split || tall || parts.len() > 1;
+
+		let mut result = Vec::with_capacity(parts.len());
+		for (i, image) in parts.into_iter().enumerate() {
+			let image = match cfg.sharpen {
+				Some(amount) => adjust::sharpen(image, amount),
+				None => image,
+			};
+			let image = match cfg.bit_depth() {
+				Some(bits) => quantize::quantize(image, bits, cfg.dither),
+				None => image,
+			};
+			let (image, cfg) = if cfg.adaptive {
+				let (image, cfg) = adaptive::tune(image, &cfg);
+				(image, std::borrow::Cow::Owned(cfg))
+			} else {
+				(image, std::borrow::Cow::Borrowed(&cfg))
+			};
+			let filename = Path::new(&filename).with_extension(cfg.page_ext())
+			                                   .display()
+			                                   .to_string();
+
+			let hash = cfg.dedupe.map(|_| {
+				                     let size = [image.width().to_le_bytes(), image.height().to_le_bytes()].concat();
+				                     let color = format!("{:?}", image.color());
+				                     paths::digest([&size[..], color.as_bytes(), image.as_bytes()])
+			                     });
+
+			let fingerprint = cfg.reuse_similar.then(|| similar::Fingerprint::new(&image));
+			let settings = similar::settings(&cfg);
+			let (output, similar_to) = match fingerprint.as_ref().and_then(|print| similar::find(print, &settings)) {
+				Some((output, origin)) => {
+					debug!(
+					       "Similar: {filename} matches {}:{}, reusing its encode",
+					       origin.archive.display(),
+					       origin.page
+					);
+					(output.to_vec(), Some(origin))
+				},
+				None => {
+					// decoding is done, encoding is the long part:
+					check_cancelled(cancel)?;
+					let output = encode_to_size(&image, &cfg, cancel)?;
+					if let Some(fingerprint) = fingerprint {
+						similar::insert(fingerprint, settings, &output, origin.clone());
+					}
+					(output, None)
+				},
+			};
+			trace!(
+			       "transcoded image: {filename}, len: {} ({:?})",
+			       output.len(),
+			       cfg.format
+			);
+
+			result.push(Transcoded { name: filename.clone(),
+			                         data: output,
+			                         hash,
+			                         dimensions,
+			                         part: numbered.then_some(i + 1),
+			                         similar_to });
+		}
+		Ok(result)
+	} else {
+		warn!(
+		      "Unable to decode as image: {}, so just copying as-is.",
+		      uri.display()
+		);
+		Ok(vec![Transcoded::copy(name.as_ref().to_string(), data, &cfg)])
+	}
+}
+
+
+/// JPEG with mozjpeg: trellis quantization and optimized progressive scans.
+/// Grayscale pages are encoded as grayscale JPEG.
+fn encode_jpeg(image: &image::DynamicImage,
+               quality: u8,
+               options: &cli::JpegOptions,
+               icc: Option<&[u8]>)
+               -> Result<Vec<u8>, image::ImageError> {
+	use mozjpeg::{Compress, ColorSpace};
+
+	let gray = !image.color().has_color();
+	let (pixels, color_space) = if gray {
+		(image.to_luma8().into_raw(), ColorSpace::JCS_GRAYSCALE)
+	} else {
+		(image.to_rgb8().into_raw(), ColorSpace::JCS_RGB)
+	};
+
+	// mozjpeg reports errors by unwinding:
+	let encoded = std::panic::catch_unwind(|| {
+		let mut compress = Compress::new(color_space);
+		compress.set_size(image.width() as _, image.height() as _);
+		compress.set_quality(quality as _);
+		if options.baseline {
+			compress.set_optimize_scans(false);
+		} else {
+			compress.set_progressive_mode();
+		}
+		if !gray {
+			let size = options.subsampling.pixel_size();
+			compress.set_chroma_sampling_pixel_sizes(size, size);
+		}
+		let mut compress = compress.start_compress(Vec::new())?;
+		if let Some(icc) = icc {
+			compress.write_icc_profile(icc);
+		}
+		compress.write_scanlines(&pixels)?;
+		compress.finish()
+	});
+	let encoded = encoded.map_err(|_| {
+		                     use image::error::EncodingError;
+		                     let err = EncodingError::new(image::ImageFormat::Jpeg.into(), "mozjpeg failed");
+		                     image::ImageError::Encoding(err)
+	                     })?;
+	Ok(encoded?)
+}
+
+
+/// Lossless optimization of encoded PNG with oxipng.
+fn optimize_png(data: &[u8], options: &cli::PngOptions) -> Result<Vec<u8>, image::ImageError> {
+	use image::error::EncodingError;
+
+	let mut opts = oxipng::Options::from_preset(options.optimize.unwrap_or(2));
+	if options.zopfli {
+		opts.deflate = oxipng::Deflaters::Zopfli { iterations: std::num::NonZeroU8::new(15).unwrap() };
+	}
+	let optimized = oxipng::optimize_from_memory(data, &opts);
+	let optimized = optimized.map_err(|err| EncodingError::new(image::ImageFormat::Png.into(), err))
+	                         .map_err(image::ImageError::Encoding)?;
+	trace!("optimized png: {}b -> {}b", data.len(), optimized.len());
+	Ok(optimized)
+}
+
+
+/// Stop transcoding if the conversion was cancelled.
+fn check_cancelled(cancel: &CancellationToken) -> Result<(), image::ImageError> {
+	match cancel.is_cancelled() {
+		true => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled").into()),
+		false => Ok(()),
+	}
+}
+
+
+/// Encode with the highest quality up to the configured one which fits `--target-page-size`.
+fn encode_to_size(image: &image::DynamicImage,
+                  cfg: &Config,
+                  cancel: &CancellationToken)
+                  -> Result<Vec<u8>, image::ImageError> {
+	let output = encode(image, cfg)?;
+	let lossless = cfg.lossless || cfg.webp.near_lossless.is_some();
+	let lossy = match cfg.format {
+		_ if cfg.encoder_cmd.is_some() => true,
+		ImageOutputFormat::Avif | ImageOutputFormat::Jpeg(_) => true,
+		ImageOutputFormat::WebP => !lossless,
+		_ => false,
+	};
+	let target = match cfg.target_page_size {
+		Some(target) if lossy && output.len() as u64 > target => target,
+		_ => return Ok(output),
+	};
+
+	// binary search of quality, the smallest output is kept if nothing fits:
+	let mut best = None;
+	let mut smallest = output;
+	let (mut low, mut high) = (1, cfg.quality.saturating_sub(1));
+	let mut cfg = cfg.clone();
+	while low <= high {
+		check_cancelled(cancel)?;
+		cfg.quality = low + (high - low) / 2;
+		let output = encode(image, &cfg)?;
+		trace!("quality {}: {}b of {target}b", cfg.quality, output.len());
+		if output.len() as u64 <= target {
+			low = cfg.quality + 1;
+			best = Some(output);
+		} else {
+			high = cfg.quality - 1;
+			if output.len() < smallest.len() {
+				smallest = output;
+			}
+		}
+	}
+	Ok(best.unwrap_or_else(|| {
+		       warn!("Page doesn't fit {target}b even with the lowest quality: {}b", smallest.len());
+		       smallest
+	       }))
+}
+
+
+/// Format of images encoded as `format`, regardless of the quality.
+fn image_format(format: &ImageOutputFormat) -> Option<image::ImageFormat> {
+	use image::ImageFormat;
+	match format {
+		ImageOutputFormat::Avif => Some(ImageFormat::Avif),
+		ImageOutputFormat::WebP => Some(ImageFormat::WebP),
+		ImageOutputFormat::Jpeg(_) => Some(ImageFormat::Jpeg),
+		ImageOutputFormat::Png => Some(ImageFormat::Png),
+		ImageOutputFormat::Pnm(_) => Some(ImageFormat::Pnm),
+		ImageOutputFormat::Gif => Some(ImageFormat::Gif),
+		ImageOutputFormat::Ico => Some(ImageFormat::Ico),
+		ImageOutputFormat::Bmp => Some(ImageFormat::Bmp),
+		ImageOutputFormat::Tga => Some(ImageFormat::Tga),
+		ImageOutputFormat::Qoi => Some(ImageFormat::Qoi),
+		ImageOutputFormat::Tiff => Some(ImageFormat::Tiff),
+		ImageOutputFormat::Farbfeld => Some(ImageFormat::Farbfeld),
+		ImageOutputFormat::OpenExr => Some(ImageFormat::OpenExr),
+		_ => None,
+	}
+}
+
+
+/// Encode image to the output format.
+fn encode(image: &image::DynamicImage, cfg: &Config) -> Result<Vec<u8>, image::ImageError> {
+	if let Some(command) = cfg.encoder_cmd.as_ref() {
+		return command.encode(image, cfg);
+	}
+
+	let mut output: Vec<u8> = Vec::new();
+	// pages are sRGB with color management, which is told to readers of PNG and JPEG:
+	let icc = (cfg.color_management == cli::ColorManagement::On).then(|| color::srgb_icc(!image.color().has_color()));
+
+	match &cfg.format {
+		ImageOutputFormat::Avif => {
+			use image::error::EncodingError;
+			use ravif::{Encoder, ColorModel, Img, RGB8, RGBA8};
+			// `image` doesn't expose bit depth, so `ravif` is used directly:
+			let encoder = Encoder::new().with_quality(cfg.quality as _)
+			                            .with_alpha_quality(cfg.quality as _)
+			                            .with_speed(cfg.speed)
+			                            .with_internal_color_model(ColorModel::YCbCr)
+			                            .with_bit_depth(cfg.avif.depth())
+			                            .with_num_threads(cfg.avif_threads());
+			let (width, height) = (image.width() as usize, image.height() as usize);
+			let high_depth = {
+				use image::ColorType::*;
+				matches!(image.color(), L16 | La16 | Rgb16 | Rgba16)
+			};
+			let encoded = if high_depth {
+				use ravif::MatrixCoefficients;
+				use rav1e::prelude::PixelRange;
+				let (planes, alpha) = depth::avif_planes(image);
+				encoder.encode_raw_planes_10_bit(width, height, planes, alpha, PixelRange::Full, MatrixCoefficients::BT601)
+			} else if image.color().has_alpha() {
+				let pixels: Vec<_> = image.to_rgba8()
+				                          .pixels()
+				                          .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+				                          .collect();
+				encoder.encode_rgba(Img::new(&pixels[..], width, height))
+			} else {
+				let pixels: Vec<_> = image.to_rgb8().pixels().map(|p| RGB8::new(p[0], p[1], p[2])).collect();
+				encoder.encode_rgb(Img::new(&pixels[..], width, height))
+			};
+			let encoded = encoded.map_err(|err| EncodingError::new(image::ImageFormat::Avif.into(), err))
+			                     .map_err(image::ImageError::Encoding)?;
+			output = encoded.avif_file;
+		},
+
+		ImageOutputFormat::WebP => {
+			use image::error::EncodingError;
+			// `image` doesn't expose method and near-lossless, so `webp` is used directly:
+			let encoding_error = |err: String| {
+				image::ImageError::Encoding(EncodingError::new(image::ImageFormat::WebP.into(), err))
+			};
+			let mut config = webp::WebPConfig::new().map_err(|_| encoding_error("Invalid WebP config".to_owned()))?;
+			let lossless = cfg.lossless || cfg.webp.near_lossless.is_some();
+			config.lossless = lossless as _;
+			config.alpha_compression = !lossless as _;
+			config.quality = if lossless { 75.0 } else { cfg.quality as _ };
+			if let Some(method) = cfg.webp.method {
+				config.method = method as _;
+			}
+			if let Some(level) = cfg.webp.near_lossless {
+				config.near_lossless = level as _;
+			}
+
+			let (width, height) = (image.width(), image.height());
+			let (rgb, rgba);
+			let encoder = if image.color().has_alpha() {
+				rgba = image.to_rgba8();
+				webp::Encoder::from_rgba(&rgba, width, height)
+			} else {
+				rgb = image.to_rgb8();
+				webp::Encoder::from_rgb(&rgb, width, height)
+			};
+			let encoded = encoder.encode_advanced(&config)
+			                     .map_err(|err| encoding_error(format!("{err:?}")))?;
+			output = encoded.to_vec();
+		},
+
+		ImageOutputFormat::Jpeg(_) => output = encode_jpeg(image, cfg.quality, &cfg.jpeg, icc)?,
+
+		ImageOutputFormat::Png => {
+			use image::codecs::png::{PngEncoder, CompressionType, FilterType};
+			match (cfg.bit_depth(), image) {
+				(Some(bits @ 1..=4), image::DynamicImage::ImageLuma8(gray)) => {
+					use image::error::EncodingError;
+					let encoded = quantize::encode_png(gray, bits);
+					output = encoded.map_err(|err| EncodingError::new(image::ImageFormat::Png.into(), err))
+					                .map_err(image::ImageError::Encoding)?;
+				},
+				_ => {
+					PngEncoder::new_with_quality(&mut output, CompressionType::Best, FilterType::Adaptive).write_image(
+					                                                                                                   image.as_bytes(),
+					                                                                                                   image.width(),
+					                                                                                                   image.height(),
+					                                                                                                   image.color(),
+					)?
+				},
+			}
+			if cfg.png.optimize.is_some() || cfg.png.zopfli {
+				output = optimize_png(&output, &cfg.png)?;
+			}
+			if let Some(icc) = icc {
+				output = color::png_with_icc(&output, icc);
+			}
+		},
+		#[cfg(feature = "null-codec")]
+		ImageOutputFormat::Unsupported(format) if format == cli::NULL_CODEC => {
+			// deterministic and fast: header and pixels as-is
+			output = format!("NULL\n{} {} {:?}\n", image.width(), image.height(), image.color()).into_bytes();
+			output.extend_from_slice(image.as_bytes());
+		},
+		format => {
+			use std::io::Cursor;
+			image.write_to(&mut Cursor::new(&mut output), format.to_owned())?
+		},
+	}
+
+	Ok(output)
+}
@@ -0,0 +1,137 @@
+//! `library` command: plan conversion of a whole library tree, show it, and convert it on confirmation.
+//!
+//! The plan is executed by the usual conversion, with outputs mirroring the tree in the output directory.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use indicatif::HumanBytes;
+
+use crate::cli::{ConvertArgs, FormatFileExt, LibraryArgs};
+use crate::error::Error;
+use crate::estimate::{self, Estimate};
+use crate::paths;
+
+
+#[derive(Debug)]
+pub struct Planned {
+	pub path: PathBuf,
+	/// Output path relative to the output directory.
+	pub output: PathBuf,
+	pub estimate: Estimate,
+}
+
+
+#[derive(Debug, Default)]
+pub struct Plan {
+	/// Archives with all pages in a modern format already.
+	pub modern: Vec<Planned>,
+	pub convert: Vec<Planned>,
+	/// Archives which can't be read, with the reason.
+	pub unreadable: Vec<(PathBuf, String)>,
+}
+
+
+impl Plan {
+	/// Inputs and their output names for the conversion.
+	pub fn into_inputs(self) -> (Vec<PathBuf>, HashMap<PathBuf, PathBuf>) {
+		let renames = self.convert
+		                  .iter()
+		                  .map(|planned| (planned.path.clone(), planned.output.clone()))
+		                  .collect();
+		(self.convert.into_iter().map(|planned| planned.path).collect(), renames)
+	}
+
+
+	pub fn print(&self) {
+		for planned in &self.modern {
+			println!("modern   {}", planned.path.display());
+		}
+		for (path, err) in &self.unreadable {
+			println!("error    {}: {err}", path.display());
+		}
+		for Planned { path, output, estimate } in &self.convert {
+			println!(
+			         "convert  {} => {}, {} (sampled {} of {} pages)",
+			         path.display(),
+			         output.display(),
+			         estimate::summary(estimate.source_size, estimate.output_size()),
+			         estimate.sampled,
+			         estimate.pages
+			);
+		}
+
+		let source: u64 = self.convert.iter().map(|planned| planned.estimate.source_size).sum();
+		let output: u64 = self.convert.iter().map(|planned| planned.estimate.output_size()).sum();
+		println!(
+		         "{} archives to convert, {} modern, {} unreadable",
+		         self.convert.len(),
+		         self.modern.len(),
+		         self.unreadable.len()
+		);
+		if source > 0 {
+			println!(
+			         "estimated: {}, saving ~{}",
+			         estimate::summary(source, output),
+			         HumanBytes(source.saturating_sub(output))
+			);
+		}
+	}
+}
+
+
+/// Walk the roots given as inputs and estimate every archive found.
+pub async fn plan(args: &ConvertArgs, library: &LibraryArgs) -> Result<Plan, Error> {
+	let cfg = &args.config;
+	let mut found = Vec::new();
+	for root in &args.input {
+		if root.is_dir() {
+			let mut paths = paths::walk_dir(root, &args.input_ext).await?;
+			paths.sort_by(|a, b| paths::natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+			info!("found {} archives in '{}'", paths.len(), root.display());
+			found.extend(paths.into_iter().map(|path| (relative(&path, root), path)));
+		} else {
+			found.push((PathBuf::from(root.file_name().unwrap_or_default()), root.clone()));
+		}
+	}
+
+	let mut plan = Plan::default();
+	for (relative, path) in found {
+		let estimate = match estimate::estimate(&path, cfg, library.sample_every as usize).await {
+			Ok(estimate) => estimate,
+			Err(err) => {
+				plan.unreadable.push((path, err.to_string()));
+				continue;
+			},
+		};
+		let archive = cfg.archive.for_input(&path, estimate.kind);
+		let ext = cfg.output_ext.as_deref().unwrap_or(archive.ext());
+		let planned = Planned { output: relative.with_extension(ext),
+		                        path,
+		                        estimate };
+		if planned.estimate.is_modern() && !cfg.reencode_modern {
+			plan.modern.push(planned);
+		} else {
+			plan.convert.push(planned);
+		}
+	}
+	Ok(plan)
+}
+
+
+/// Path of the archive inside the root.
+fn relative(path: &Path, root: &Path) -> PathBuf {
+	path.strip_prefix(root)
+	    .map(Path::to_owned)
+	    .unwrap_or_else(|_| PathBuf::from(path.file_name().unwrap_or_default()))
+}
+
+
+/// Ask on the terminal, anything other than `y` or `yes` is a refusal.
+pub fn confirm(question: &str) -> Result<bool, Error> {
+	print!("{question} [y/N] ");
+	std::io::stdout().flush()?;
+	let mut answer = String::new();
+	std::io::stdin().read_line(&mut answer)?;
+	Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -1,26 +1,48 @@
 use std::borrow::Cow;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
 use console::{style, Color};
 use indicatif::MultiProgress;
 use log::{Record, Level, Metadata, SetLoggerError, LevelFilter};
 
 
-#[derive(Default)]
 struct Logger<const COLORS: bool> {
 	extra_verbose: bool,
 	output: Option<MultiProgress>,
+	/// Level of the console, the file of `--log-file` gets all records.
+	level: LevelFilter,
+	file: Option<Mutex<File>>,
 }
 
 impl<const COLORS: bool> Logger<COLORS> {
-	const fn new(output: Option<MultiProgress>, extra_verbose: bool) -> Self {
+	fn new(output: Option<MultiProgress>, extra_verbose: bool, level: LevelFilter, file: Option<File>) -> Self {
 		Self { output,
-		       extra_verbose }
+		       extra_verbose,
+		       level,
+		       file: file.map(Mutex::new) }
+	}
+
+	fn write_file(&self, record: &Record) {
+		let Some(file) = self.file.as_ref() else {
+			return;
+		};
+		let line = format!(
+		                   "{} {:<5} {}:{} {}\n",
+		                   chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+		                   record.level(),
+		                   record.target(),
+		                   record.line().unwrap_or_default(),
+		                   message(record)
+		);
+		file.lock().unwrap().write_all(line.as_bytes()).ok();
 	}
 
 	fn is_enabled(&self, metadata: &Metadata) -> bool { metadata.level() <= Level::Trace }
 
 	fn do_flush(&self) {
 		let flush = || {
-			use std::io::Write;
 			std::io::stdout().flush().ok();
 			std::io::stderr().flush().ok();
 		};
@@ -40,6 +62,10 @@ impl log::Log for Logger<true> {
 		if !self.extra_verbose && !this_crate {
 			return;
 		}
+		self.write_file(record);
+		if record.level() > self.level {
+			return;
+		}
 
 		let path = {
 			let line = record.line()
@@ -61,11 +87,11 @@ impl log::Log for Logger<true> {
 		};
 
 		let render = match record.level() {
-			Level::Info => format!("{level} {}", record.args()),
-			Level::Warn => format!("{level} {}", record.args()),
-			Level::Error => format!("{level} {} {}", path, record.args()),
-			Level::Debug => format!("{level} {} {}", path, record.args()),
-			Level::Trace => format!("{level} {} {}", path, record.args()),
+			Level::Info => format!("{level} {}", message(record)),
+			Level::Warn => format!("{level} {}", message(record)),
+			Level::Error => format!("{level} {} {}", path, message(record)),
+			Level::Debug => format!("{level} {} {}", path, message(record)),
+			Level::Trace => format!("{level} {} {}", path, message(record)),
 		};
 
 		match record.level() {
@@ -100,7 +126,12 @@ impl log::Log for Logger<true> {
 		}
 	}
 
-	fn flush(&self) { self.do_flush() }
+	fn flush(&self) {
+		self.do_flush();
+		if let Some(file) = self.file.as_ref() {
+			file.lock().unwrap().flush().ok();
+		}
+	}
 	fn enabled(&self, metadata: &Metadata) -> bool { self.is_enabled(metadata) }
 }
 
@@ -117,6 +148,10 @@ impl log::Log for Logger<false> {
 		if !self.extra_verbose && !this_crate {
 			return;
 		}
+		self.write_file(record);
+		if record.level() > self.level {
+			return;
+		}
 
 		let path = {
 			let line = record.line()
@@ -144,11 +179,11 @@ impl log::Log for Logger<false> {
 		};
 
 		let render = match record.level() {
-			Level::Info => format!("{}", record.args()),
-			Level::Warn => format!("{level} {}", record.args()),
-			Level::Error => format!("{level} {} {}", path, record.args()),
-			Level::Debug => format!("{level} {} {}", path, record.args()),
-			Level::Trace => format!("{level} {} {}", path, record.args()),
+			Level::Info => message(record),
+			Level::Warn => format!("{level} {}", message(record)),
+			Level::Error => format!("{level} {} {}", path, message(record)),
+			Level::Debug => format!("{level} {} {}", path, message(record)),
+			Level::Trace => format!("{level} {} {}", path, message(record)),
 		};
 
 		match record.level() {
@@ -177,12 +212,31 @@ impl log::Log for Logger<false> {
 		}
 	}
 
-	fn flush(&self) { self.do_flush() }
+	fn flush(&self) {
+		self.do_flush();
+		if let Some(file) = self.file.as_ref() {
+			file.lock().unwrap().flush().ok();
+		}
+	}
 	fn enabled(&self, metadata: &Metadata) -> bool { self.is_enabled(metadata) }
 }
 
 
-pub fn init(verbose: u8, output: Option<MultiProgress>) -> Result<(), SetLoggerError> {
+/// Message of the record prefixed with the archive being processed, if any.
+fn message(record: &Record) -> String {
+	match crate::context::current() {
+		Some(archive) => format!("[{archive}] {}", record.args()),
+		None => record.args().to_string(),
+	}
+}
+
+
+/// Open the file of `--log-file` for appending.
+pub fn open_file(path: &Path) -> std::io::Result<File> { std::fs::OpenOptions::new().create(true).append(true).open(path) }
+
+
+/// Records up to the level of `verbose` are printed, all of them are written to the `file`.
+pub fn init(verbose: u8, output: Option<MultiProgress>, file: Option<File>) -> Result<(), SetLoggerError> {
 	let max_level = match verbose {
 		0 => LevelFilter::Warn,
 		1 => LevelFilter::Info,
@@ -191,12 +245,17 @@ pub fn init(verbose: u8, output: Option<MultiProgress>) -> Result<(), SetLoggerE
 	};
 	let extra_verbose = verbose > 3;
 
+	let limit = match file {
+		Some(_) => LevelFilter::Trace,
+		None => max_level,
+	};
+
 	let res = if console::colors_enabled() {
-		log::set_boxed_logger(Box::new(Logger::<true>::new(output, extra_verbose)))
+		log::set_boxed_logger(Box::new(Logger::<true>::new(output, extra_verbose, max_level, file)))
 	} else {
-		log::set_boxed_logger(Box::new(Logger::<false>::new(output, extra_verbose)))
+		log::set_boxed_logger(Box::new(Logger::<false>::new(output, extra_verbose, max_level, file)))
 	};
 	// set level limit anyway:
-	log::set_max_level(max_level);
+	log::set_max_level(limit);
 	res
 }
@@ -1,53 +1,184 @@
-#![feature(extract_if)]
-#![feature(never_type)]
-
 #[macro_use]
 extern crate log;
-extern crate tokio;
-use std::fmt::Debug;
-use std::sync::Arc;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
-use tokio::fs::try_exists;
-use tokio::sync::RwLock;
 use futures::TryFutureExt;
 use futures::{stream, StreamExt};
-use tokio_util::compat::TokioAsyncWriteCompatExt;
-use archive_reader::Archive;
-use async_zip::ZipEntryBuilder;
-use async_zip::tokio::write::ZipFileWriter;
 use indicatif::MultiProgress;
-use image::ImageOutputFormat;
-use image::ImageEncoder;
 
+use comic_repack::{bench, cap, cli, diff, estimate, failures, hooks, journal, library, logger, manifest, marker, memory,
+                   merge, notify, paths, pool, progress, removal, report, serve, slice, space, spill, split,
+                   throttle, tune, watch};
+use comic_repack::{convert_all, open_inout, output_volumes, verify_output, ConversionResult, ProcessInOut};
+use comic_repack::error::Error;
 
-mod cli;
-mod logger;
-mod error;
-mod paths;
 
-use error::Error;
-use cli::Config;
-use cli::FormatFileExt;
+/// Exit code when some archives or pages failed.
+const EXIT_PARTIAL: i32 = 1;
+/// Exit code when no archive was converted.
+const EXIT_FAILED: i32 = 2;
 
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let mut args = cli::parse();
+	let mut raw_args: Vec<String> = std::env::args().skip(1).collect();
+	pool::init(args.encode_threads as _)?;
+	if let Some(dir) = &args.temp_dir {
+		spill::init(dir.to_owned(), args.spill_above)?;
+	}
+	let log_file = args.log_file.as_deref().map(logger::open_file).transpose()?;
+
+	match &args.command {
+		Some(cli::Command::Slice(slice)) => {
+			logger::init(args.verbose, None, log_file)?;
+			let res = until_interrupted(slice::run(slice)).await;
+			return finish_command(res, None);
+		},
+		Some(cli::Command::Merge(merge)) => {
+			let multibar = MultiProgress::new();
+			logger::init(args.verbose, Some(multibar.clone()), log_file)?;
+			let res = until_interrupted(merge::run(merge, multibar.clone())).await;
+			return finish_command(res, Some(multibar));
+		},
+		Some(cli::Command::Bench(bench)) => {
+			logger::init(args.verbose, None, log_file)?;
+			let res = until_interrupted(bench::run(bench)).await;
+			return finish_command(res, None);
+		},
+		Some(cli::Command::Diff(diff)) => {
+			logger::init(args.verbose, None, log_file)?;
+			let res = until_interrupted(diff::run(diff)).await;
+			return finish_command(res, None);
+		},
+		Some(cli::Command::Serve(serve)) => {
+			logger::init(args.verbose, None, log_file)?;
+			let res = until_interrupted(serve::run(serve)).await;
+			return finish_command(res, None);
+		},
+		Some(cli::Command::Watch(watch)) => {
+			logger::init(args.verbose, None, log_file)?;
+			let res = until_interrupted(watch::run(watch)).await;
+			return finish_command(res, None);
+		},
+		Some(cli::Command::Split(split)) => {
+			let multibar = MultiProgress::new();
+			logger::init(args.verbose, Some(multibar.clone()), log_file)?;
+			let res = until_interrupted(split::run(split, multibar.clone())).await;
+			return finish_command(res, Some(multibar));
+		},
+		_ => {},
+	}
+
+	let mut retry_inputs = None;
+	if let Some(cli::Command::RetryFailed { overrides }) = &args.command {
+		let previous = failures::Failures::load(&args.failures_file).await?;
+		raw_args = previous.retry_args(overrides);
+		let failures_file = args.failures_file.clone();
+		args = cli::try_parse_from(raw_args.clone())?;
+		args.failures_file = failures_file;
+		retry_inputs = Some(previous.failed.into_iter().map(|failure| failure.path).collect());
+	}
+
+	let verbose = args.verbose;
+	let failures_file = args.failures_file.clone();
+	let library = match &args.command {
+		Some(cli::Command::Library { library, .. }) => Some(library.clone()),
+		_ => None,
+	};
+	let mut args = args.into_convert();
+	if let Some(inputs) = retry_inputs {
+		args.input = inputs;
+	}
 
 	let multibar = MultiProgress::new();
 	multibar.set_move_cursor(true);
 	let bar_completed = cli::main_progress_bar(&multibar)?;
+	let progress_mode = args.progress.resolve();
+	if progress_mode != cli::Progress::Bars {
+		multibar.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+	}
+	if progress_mode == cli::Progress::Json {
+		tokio::spawn(progress::print_lines(std::time::Duration::from_secs(5)));
+	}
 
 
-	logger::init(args.verbose, Some(multibar.clone()))?;
+	// records are printed around bars, or directly without them:
+	let log_output = (progress_mode == cli::Progress::Bars).then(|| multibar.clone());
+	logger::init(verbose, log_output, log_file)?;
 	trace!("input args: {:#?}", args);
 
 
 	debug!("preparing input paths");
-	let sources = paths::validate_and_unglob(args.input).await?;
+	if let Some(list) = args.files_from.as_ref() {
+		args.input.extend(paths::read_file_list(list, args.null)?);
+	}
+	let mut planned = None;
+	if let Some(library) = library.as_ref() {
+		let plan = library::plan(&args, library).await?;
+		multibar.suspend(|| plan.print());
+		if plan.convert.is_empty() {
+			info!("Nothing to convert");
+			return Ok(());
+		}
+		if args.estimate.is_some() {
+			return Ok(());
+		}
+		let question = format!("Convert {} archives?", plan.convert.len());
+		if !library.yes && !multibar.suspend(|| library::confirm(&question))? {
+			info!("Cancelled");
+			return Ok(());
+		}
+		let (inputs, renames) = plan.into_inputs();
+		args.input = inputs;
+		// `--layout` and `--output-template` arrange outputs instead of mirroring the tree:
+		let arranged = args.config.layout.is_some() || args.config.output_template.is_some();
+		planned = (!arranged).then_some(renames);
+	}
+	let (inputs, mut renames) = paths::split_renames(args.input);
+	renames.extend(planned.into_iter().flatten());
+	renames.extend(args.rename
+	                   .chunks_exact(2)
+	                   .map(|pair| (PathBuf::from(&pair[0]), PathBuf::from(&pair[1]))));
+	let walk = args.recursive.then_some(args.input_ext.as_slice());
+	let mut sources = paths::validate_and_unglob(inputs, walk).await?;
+	if args.min_input_size.is_some() || args.max_input_size.is_some() {
+		let skipped = paths::filter_size(&mut sources, args.min_input_size, args.max_input_size);
+		if skipped > 0 {
+			info!("Skipping {skipped} archives outside of input size limits");
+		}
+	}
+	paths::sort_batch(&mut sources, args.order_by, args.reverse);
+
+	if let Some(every) = args.estimate {
+		bar_completed.set_length(sources.len() as _);
+		estimate::run(sources, &args.config, every as usize, args.jobs_fs.unwrap_or(1), &bar_completed).await?;
+		multibar.clear()?;
+		return Ok(());
+	}
+
+	let (journal, done) = journal::Journal::open(&args.journal, args.resume)?;
+	if !done.is_empty() {
+		sources.retain(|path| !done.contains(path));
+		info!("Resuming: {} archives are already done", done.len());
+	}
+	if args.config.skip_converted {
+		let before = sources.len();
+		sources.retain(|path| !marker::is_converted(path, &args.config));
+		if sources.len() < before {
+			info!("Skipping {} archives already converted with the same settings", before - sources.len());
+		}
+	}
 
+	let sources_len = sources.len();
 	bar_completed.set_length(sources.len() as _);
+	if let Some(path) = args.progress_json.as_ref() {
+		progress::init(path)?;
+	}
+	if let Some(bytes) = args.max_memory {
+		memory::init(bytes);
+	}
+	progress::emit(progress::Event::Start { archives: sources.len() });
 	bar_completed.set_position(0 as _);
 
 	debug!("preparing output path");
@@ -57,407 +188,349 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	} else {
 		std::env::current_dir()? // XXX: potential inplace race & corruption!
 	};
+	space::check(&sources, &outdir, args.max_total_output, args.disk_check)?;
+
+	// archives at once, adjusted by the bottleneck while running:
+	let concurrency = args.jobs_fs.unwrap_or(args.config.jobs).max(1);
+	tune::init(concurrency, args.config.jobs.min(pool::threads()));
+	// pages of all archives at once, adjusted by signals while running:
+	throttle::init(args.config.jobs);
+	if let Some(cap) = args.max_total_output {
+		cap::init(cap);
+	}
 
-
-	let concurrency = args.jobs_fs;
-	args.config.jobs /= concurrency;
+	// First Ctrl-C cancels conversions in progress, which remove their incomplete outputs,
+	// the second one exits immediately:
+	let cancel = tokio_util::sync::CancellationToken::new();
+	tokio::spawn({
+		let cancel = cancel.clone();
+		async move {
+			if tokio::signal::ctrl_c().await.is_ok() {
+				warn!("Cancelling, press Ctrl-C again to exit immediately");
+				cancel.cancel();
+				if tokio::signal::ctrl_c().await.is_ok() {
+					std::process::exit(130);
+				}
+			}
+		}
+	});
 
 	let create_inout_task = |path: PathBuf| {
+		let journal = &journal;
 		let outdir = outdir.clone();
 		let config = args.config.clone();
 		let multibar = multibar.clone();
+		let cancel = cancel.child_token();
+		let rename = renames.get(&path).cloned();
+		let verify = args.verify;
+		let checksums = args.checksums;
+		let removal = match (args.delete_source, args.trash_source) {
+			(true, _) => Some(removal::Removal::Delete),
+			(_, true) => Some(removal::Removal::Trash),
+			_ => None,
+		};
 
 		let set_initial_progress = |inout: ProcessInOut| async move { Ok(inout) };
 
 		// TODO: remove this scope-wrapper:
-		async move {
-			open_inout(path, outdir, &config).and_then(set_initial_progress)
-			                                 .and_then(|inout| convert_all(inout, &config, Some(multibar)))
-			                                 .and_then(|res| {
-				                                 async move {
-					                                 let sp = res.src.display();
-					                                 let src = tokio::fs::metadata(&res.src).await?.len();
-					                                 let dst = res.dst.len();
-					                                 let p = (dst as f64 / src as f64) * 100.0;
-					                                 // TODO: this should be `info`:
-					                                 debug!("Archived: {sp}, new size: {dst}b vs. {src}b ≈ {p:.2}%",);
-					                                 Ok(res)
-				                                 }
-			                                 })
-			                                 .await
-		}
+		let scope = path.clone();
+		comic_repack::context::scope(&scope, async move {
+			let _slot = tune::slot().await;
+			if let Err(err) = journal.started(&path) {
+				warn!("Unable to write journal: {err}");
+			}
+			let src = path.clone();
+			let config = &config;
+			open_inout(path, outdir, rename, config).and_then(set_initial_progress)
+			                                        .and_then(|inout| convert_all(inout, config, &cancel, Some(multibar)))
+			                                        .and_then(|res| {
+				                                        async move {
+					                                        let outputs = output_volumes(&res.dst_path, config.split_size.is_some());
+					                                        if verify {
+						                                        verify_output(&outputs).await?;
+					                                        }
+					                                        if let Some(algorithm) = checksums {
+						                                        manifest::write(&res.src, outputs, algorithm, config).await?;
+					                                        }
+					                                        Ok(res)
+				                                        }
+			                                        })
+			                                        .and_then(|res| {
+				                                        async move {
+					                                        let sp = res.src.display();
+					                                        let src = tokio::fs::metadata(&res.src).await?.len();
+					                                        let dst = res.dst_size;
+					                                        let p = (dst as f64 / src as f64) * 100.0;
+					                                        // TODO: this should be `info`:
+					                                        debug!("Archived: {sp}, new size: {dst}b vs. {src}b ≈ {p:.2}%",);
+					                                        Ok((res, src))
+				                                        }
+			                                        })
+			                                        .and_then(|(res, src)| {
+				                                        async move {
+					                                        // output is already synced and committed here:
+					                                        match removal {
+						                                        // pages left out of the output would be lost:
+						                                        Some(_) if !res.failed.is_empty() => {
+							                                        let failed = res.failed.len();
+							                                        warn!("Keeping {}: {failed} pages failed", res.src.display());
+						                                        },
+						                                        Some(removal) => {
+							                                        let removed = removal::remove_source(&res.src, &res.dst_path, removal);
+							                                        if let Err(err) = removed.await {
+								                                        error!("{err}");
+							                                        }
+						                                        },
+						                                        None => {},
+					                                        }
+					                                        Ok((res, src))
+				                                        }
+			                                        })
+			                                        .await
+			                                        .map_err(|err| (src, err))
+		})
 	};
 
-	let notify = |res: Result<ConversionResult, _>| {
+	let failed = std::sync::Mutex::new(failures::Failures::new(raw_args));
+	let report = std::sync::Mutex::new(report::Report::default());
+	// Total size of outputs, for savings of `--notify`:
+	let total_output = std::sync::atomic::AtomicU64::new(0);
+	// Total size of sources of converted archives, for savings of `--notify`:
+	let total_source = std::sync::atomic::AtomicU64::new(0);
+	// Finished archives, successfully or not, to know what remains on Ctrl-C:
+	let finished = std::sync::Mutex::new(std::collections::HashSet::new());
+	// Converted archives with failed pages:
+	let partial = std::sync::Mutex::new(Vec::new());
+	// Archives not started or cancelled because of `--max-total-output`:
+	let skipped = std::sync::Mutex::new(Vec::new());
+	let notify = |res: Result<(ConversionResult, u64), (PathBuf, Error)>| {
 		let bar_completed_ref = &bar_completed;
+		let failed = &failed;
+		let partial = &partial;
+		let report = args.report.as_ref().map(|_| &report);
+		let total_output = &total_output;
+		let total_source = &total_source;
+		let notify_archive = args.notify == Some(cli::Notify::Archive);
+		let finished = &finished;
+		let journal = &journal;
+		let skipped = &skipped;
+		let (on_success, on_failure, webhook) = (args.on_success.as_ref(), args.on_failure.as_ref(), args.webhook.as_ref());
 		async move {
 			match res {
-				Ok(res) => info!("Finished: {}", res.src.display()),
-				Err(err) => error!("{err}"),
+				Ok((res, src_size)) => {
+					info!("Finished: {}", res.src.display());
+					if let Err(err) = journal.done(&res.src) {
+						warn!("Unable to write journal: {err}");
+					}
+					finished.lock().unwrap().insert(res.src.clone());
+					progress::emit(progress::Event::ArchiveDone { path: res.src.clone(),
+					                                              output: res.dst_path.clone(),
+					                                              size: res.dst_size });
+					total_output.fetch_add(res.dst_size, std::sync::atomic::Ordering::SeqCst);
+					total_source.fetch_add(src_size, std::sync::atomic::Ordering::SeqCst);
+					if notify_archive {
+						let name = res.src.file_name().unwrap_or_default().to_string_lossy();
+						notify::send(&format!("Converted {name}"), &notify::savings(src_size, res.dst_size));
+					}
+					if on_success.is_some() || webhook.is_some() {
+						let outcome = hooks::Outcome::success(&res.src, &res.dst_path, src_size, res.dst_size);
+						hooks::run(&outcome, on_success, webhook).await;
+					}
+					if !res.failed.is_empty() {
+						partial.lock().unwrap().push((res.src.clone(), res.failed.clone()));
+					}
+					if let Some(report) = report {
+						let archive = report::ArchiveReport::new(res.src,
+						                                         res.dst_path,
+						                                         src_size,
+						                                         res.dst_size,
+						                                         res.pages,
+						                                         res.failed);
+						report.lock().unwrap().archives.push(archive);
+					}
+				},
+				Err((path, Error::Exists(output))) => {
+					info!("Skipped: {}, output {} already exists", path.display(), output.display());
+					if let Err(err) = journal.done(&path) {
+						warn!("Unable to write journal: {err}");
+					}
+					finished.lock().unwrap().insert(path);
+				},
+				// its incomplete output is removed, it's stored with not started ones to resume:
+				Err((path, Error::CapReached)) => {
+					info!("Cancelled: {}, total output size cap reached", path.display());
+					skipped.lock().unwrap().push(path);
+				},
+				Err((path, err)) => {
+					error!("{err}");
+					let error = err.to_string();
+					progress::emit(progress::Event::ArchiveFailed { path: path.clone(),
+					                                                error: error.clone() });
+					finished.lock().unwrap().insert(path.clone());
+					if notify_archive {
+						let name = path.file_name().unwrap_or_default().to_string_lossy();
+						notify::send(&format!("Failed {name}"), &error);
+					}
+					if on_failure.is_some() || webhook.is_some() {
+						hooks::run(&hooks::Outcome::failure(&path, &error), on_failure, webhook).await;
+					}
+					failed.lock().unwrap().failed.push(failures::Failure { path, error });
+				},
 			}
 			bar_completed_ref.inc(1);
 		}
 	};
 
 
-	stream::iter(sources.into_iter()).map(create_inout_task)
-	                                 .buffer_unordered(concurrency)
-	                                 .for_each(notify)
-	                                 .await;
-
-	info!("Complete 🎉");
-	multibar.clear()?;
-	log::logger().flush();
-	Ok(())
-}
-
-
-enum ArchiveWriter {
-	Zip(ZipFileWriter<tokio::fs::File>),
-	Sz(sevenz_rust::SevenZWriter<std::fs::File>),
-}
-
-impl ArchiveWriter {
-	async fn open_file(path: impl AsRef<Path>, force: bool) -> Result<tokio::fs::File, Error> {
-		let path = path.as_ref();
-		debug!("opening output: '{}'", path.display());
-		let out_exists = try_exists(&path).await?;
-
-		if out_exists && !force {
-			return Err(std::io::Error::new(
-				std::io::ErrorKind::AlreadyExists,
-				format!("Output file already exists {}", path.display()),
-			).into());
-		}
-
-		if let Some(parent) = path.parent() {
-			tokio::fs::create_dir_all(parent).await?;
-		}
+	let within_cap = |path: PathBuf| {
+		let res = match cap::reached() {
+			_ if cancel.is_cancelled() => None,
+			true => {
+				skipped.lock().unwrap().push(path);
+				None
+			},
+			false => Some(path),
+		};
+		futures::future::ready(res)
+	};
 
-		let output_file = tokio::fs::OpenOptions::new().write(true)
-		                                               .create_new(!out_exists)
-		                                               .truncate(force)
-		                                               .open(&path)
-		                                               .await?;
-		Ok(output_file)
+	let batch = stream::iter(sources.clone()).filter_map(within_cap)
+	                                             .map(create_inout_task)
+	                                             .buffer_unordered(concurrency)
+	                                             .for_each(notify);
+
+	// On Ctrl-C archives in progress are cancelled, which removes their incomplete outputs,
+	// and unfinished ones are stored as failed, so `retry-failed` resumes the batch:
+	batch.await;
+	if cancel.is_cancelled() {
+		multibar.clear()?;
+		let mut failed = failed.into_inner().unwrap();
+		let finished = finished.into_inner().unwrap();
+		let error = "interrupted".to_owned();
+		failed.failed.extend(sources.into_iter()
+		                            .filter(|path| !finished.contains(path))
+		                            .map(|path| {
+			                            failures::Failure { path,
+			                                                error: error.clone() }
+		                            }));
+		failed.save(&failures_file).await?;
+		error!("Interrupted, incomplete outputs are removed");
+		warn!(
+		      "{} archives are not finished, run `retry-failed` to resume",
+		      failed.failed.len()
+		);
+		log::logger().flush();
+		std::process::exit(130);
 	}
 
-	pub async fn open_zip(path: impl AsRef<Path>, force: bool) -> Result<Self, Error> {
-		let output_file = Self::open_file(path, force).await?;
-		let writer = ZipFileWriter::new(output_file.compat_write());
-		Ok(Self::Zip(writer))
+	let mut failed = failed.into_inner().unwrap();
+	let skipped = skipped.into_inner().unwrap();
+	if !skipped.is_empty() {
+		error!(
+		      "Total output size reached {}b, {} archives are not converted",
+		      args.max_total_output.unwrap_or_default(),
+		      skipped.len()
+		);
+		let error = "total output size cap reached".to_owned();
+		failed.failed.extend(skipped.into_iter().map(|path| {
+			                                        failures::Failure { path,
+			                                                            error: error.clone() }
+		                                        }));
 	}
-
-	pub async fn open_7z(path: impl AsRef<Path>, force: bool) -> Result<Self, Error> {
-		use sevenz_rust::*;
-
-		let output_file = Self::open_file(path, force).await?;
-		let mut writer = SevenZWriter::new(output_file.into_std().await)?;
-		writer.set_content_methods(vec![
-			SevenZMethodConfiguration::new(SevenZMethod::LZMA2).with_options(MethodOptions::LZMA2(
-				lzma::LZMA2Options::with_preset(9),
-			)),
-		]);
-
-		Ok(Self::Sz(writer))
+	if let Some(path) = args.report.as_ref() {
+		let mut report = report.into_inner().unwrap();
+		report.failed = failed.failed.clone();
+		report.save(path).await?;
 	}
 
-
-	pub async fn write_all(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
-		debug!("writing '{name}' to output archive");
-		match self {
-			Self::Zip(writer) => {
-				let compression = async_zip::Compression::Deflate;
-				let builder =
-					ZipEntryBuilder::new(name.into(), compression).deflate_option(async_zip::DeflateOption::Maximum);
-				writer.write_entry_whole(builder, data).await?;
-			},
-
-			Self::Sz(writer) => {
-				use sevenz_rust::*;
-				let mut entry = SevenZArchiveEntry::default();
-				entry.name = name.to_owned();
-				writer.push_archive_entry(entry, Some(data))?;
-			},
+	if !failed.failed.is_empty() {
+		warn!(
+		      "Failed {} archives, run `retry-failed` to try them again",
+		      failed.failed.len()
+		);
+		for failure in &failed.failed {
+			warn!("  {}: {}", failure.path.display(), failure.error);
 		}
-		Ok(())
+		failed.save(&failures_file).await?;
+	} else {
+		failures::Failures::clear(&failures_file).await?;
 	}
-
-
-	pub async fn close(self) -> Result<std::fs::Metadata, Error> {
-		let meta = match self {
-			Self::Zip(writer) => {
-				let f = writer.close().await?.into_inner();
-				let meta = f.metadata().await?;
-				f.sync_data().await?;
-				meta
-			},
-			Self::Sz(writer) => {
-				let f = writer.finish()?;
-				let meta = f.metadata()?;
-				f.sync_data()?;
-				meta
-			},
-		};
-		Ok(meta)
+	if failed.failed.is_empty() {
+		journal.remove()?;
 	}
-}
-
-
-struct ConversionResult {
-	src: PathBuf,
-	dst: std::fs::Metadata,
-}
-
-async fn convert_all(mut inout: ProcessInOut,
-                     cfg: &Config,
-                     multibar: Option<MultiProgress>)
-                     -> Result<ConversionResult, Error> {
-	let jobs = cfg.jobs;
-	trace!("jobs per archive: {jobs}");
-	let source = inout.reader.path().to_owned();
-	let writer = Arc::new(RwLock::new(&mut inout.writer));
-
-	let bar = multibar.map(|mb| {
-		                  let len = inout.total_entries;
-		                  let pos = len - inout.entries.len();
-		                  let text = inout.reader
-		                                  .path()
-		                                  .file_name()
-		                                  .unwrap()
-		                                  .to_string_lossy()
-		                                  .to_string();
-		                  cli::sub_progress_bar(&mb, len, pos, text)
-	                  });
-
-	let convert_entry = |entry: paths::StringEntry| {
-		let source = &source;
-		let name = entry.uri.to_owned();
-		let reader = inout.reader.clone();
-		let bar = &bar;
-
-		// Read entries, then convert them, then write to resulting archive
-		async move {
-			debug!("reading '{name}'");
-			let mut buffer = Vec::new();
-			let ar_size = reader.read_file(&name, &mut buffer)?;
-			let raw_size = buffer.len();
-			let name = name.to_owned();
-
-			// TODO: mb. use name.filename instead of name
-
-			if ar_size == 0 {
-				Err(format!("no data in '{}:{name}'", source.display()).into())
-			} else {
-				debug!("transcoding '{name}'");
-				let (name, data) = tokio::spawn(transcode(cfg.clone(), buffer, name.clone())).await??;
-				// TODO: this log should be `info`:
-				debug!(
-				       "Encoded: {name}, new size: {}b vs. {}b ≈ {:.2}%",
-				       data.len(),
-				       raw_size,
-				       (data.len() as f64 / raw_size as f64) * 100.0
-				);
-				bar.as_ref().map(|bar| bar.inc(1));
-				Ok::<_, Error>((data, name))
-			}
-		}.and_then(|(data, name)| {
-			let writer = writer.clone();
-			async move {
-				writer.write().await.write_all(&name, &data[..]).await?;
-				Ok(name)
-			}
-		})
-	};
-
-	stream::iter(inout.entries.into_iter()).map(convert_entry)
-	                                       .buffer_unordered(jobs)
-	                                       .for_each(|res| {
-		                                       async move {
-			                                       match res {
-				                                       Ok(name) => info!("Finished: {name}"),
-			                                          Err(err) => error!("{err}"),
-			                                       }
-		                                       }
-	                                       })
-	                                       .await;
-	inout.writer
-	     .close()
-	     .await
-	     .map(|dst| ConversionResult { src: source, dst })
-}
-
-
-struct ProcessInOut {
-	reader: Arc<Archive>,
-	/// Inner files remains to process, already resolved and filtered
-	entries: Vec<paths::StringEntry>,
-	/// total number of entries before any filtering
-	total_entries: usize,
-
-	writer: ArchiveWriter,
-}
-
-async fn open_inout(source: impl AsRef<Path>,
-                    outdir: impl AsRef<Path>,
-                    cfg: &Config)
-                    -> Result<ProcessInOut, Error> {
-	use cli::ArchiveType::*;
-	let (reader, entries, total) = archive_reader(&source).await?;
-	let output = paths::output_archive_path(&source, &outdir, cfg.archive);
-	let writer = match cfg.archive {
-		Cbz | Zip => ArchiveWriter::open_zip(output.as_path(), cfg.force).await?,
-		Cb7 | SevenZip => ArchiveWriter::open_7z(output.as_path(), cfg.force).await?,
-	};
-	Ok(ProcessInOut { reader: Arc::new(reader),
-	                  entries,
-	                  writer,
-	                  total_entries: total })
-}
-
-
-async fn archive_reader(path: impl AsRef<Path>) -> Result<(Archive, Vec<paths::StringEntry>, usize), Error> {
-	debug!("opening input: '{}'", path.as_ref().display());
-	let mut archive = Archive::open(&path.as_ref());
-	archive.block_size(1024 * 1024);
-
-	trace!("filtering inner files");
-	let mut total = 0_usize;
-	let names = paths::filter_entries(archive.list_file_names()?
-	                                         .enumerate()
-	                                         .filter_map(|(i, name)| {
-		                                         total += 1;
-		                                         name.ok().map(|s| (i, s))
-	                                         })
-	                                         .map(paths::Entry::from));
-	let names: Vec<_> = paths::remove_root_entry(names).collect();
-	debug!("total: {total}, outfiltered: {}", total - names.len());
-	Ok((archive, names, total))
-}
 
+	progress::emit(progress::Event::Finish { completed: sources_len - failed.failed.len(),
+	                                         failed: failed.failed.len() });
 
-async fn transcode<S: AsRef<str> + Debug>(cfg: Config,
-                                          data: Vec<u8>,
-                                          name: S)
-                                          -> Result<(String, Vec<u8>), image::ImageError> {
-	let cfg = cfg.clone();
-	let uri = Path::new(name.as_ref());
-	let filename = uri.file_name().expect("filename").to_owned();
-	let format = uri.extension()
-	                .and_then(|ext| ext.to_str())
-	                .map(image::ImageFormat::from_extension)
-	                .flatten();
-
-	if format.is_none() {
-		if let Some(ext) = uri.extension() {
-			match ext.to_string_lossy().as_ref().to_lowercase().as_str() {
-				"txt" | "md" | "xml" | "html" | "svg" | "info" | "json" | "yml" | "yaml" => {
-					debug!("'{}' Seems to text, so just copying as-is.", uri.display());
-					return Ok((name.as_ref().to_string(), data));
-				},
-				_ => {},
+	let partial = partial.into_inner().unwrap();
+	if !partial.is_empty() {
+		let pages: usize = partial.iter().map(|(_, pages)| pages.len()).sum();
+		warn!("{pages} pages failed in {} archives, outputs are incomplete:", partial.len());
+		for (archive, pages) in &partial {
+			for page in pages {
+				warn!("  {}: {}: {}", archive.display(), page.name, page.error);
 			}
 		}
 	}
 
-	let out_format = match &cfg.format {
-		ImageOutputFormat::Jpeg(_) => ImageOutputFormat::Jpeg(cfg.quality.clamp(0, 100)),
-		format => format.to_owned(),
-	};
-
-	if Some(&out_format) == format.map(ImageOutputFormat::from).as_ref() {
-		warn!("SKIP with reason: same format: {out_format:?}");
-		return Ok((filename.to_string_lossy().to_string(), data));
-	}
-
-	if matches!(
-	            format,
-	            Some(image::ImageFormat::WebP) | Some(image::ImageFormat::Avif)
-	) {
-		warn!(
-		      "SKIP with reason: src is already good format: {:?}",
-		      format.as_ref().unwrap()
+	if args.notify.is_some() {
+		let completed = sources_len - failed.failed.len();
+		let title = match failed.failed.is_empty() && partial.is_empty() {
+			true => "comic-repack: complete",
+			false => "comic-repack: complete with failures",
+		};
+		let savings = notify::savings(
+		                              total_source.load(std::sync::atomic::Ordering::SeqCst),
+		                              total_output.load(std::sync::atomic::Ordering::SeqCst)
+		);
+		notify::send(
+		             title,
+		             &format!("{completed} of {sources_len} archives converted, {} failed, {savings}", failed.failed.len())
 		);
-		return Ok((filename.to_string_lossy().to_string(), data));
 	}
 
-
-	let image = if let Some(format) = format {
-		image::load_from_memory_with_format(&data, format)
+	multibar.clear()?;
+	let code = if sources_len > 0 && failed.failed.len() == sources_len {
+		error!("All {sources_len} archives failed");
+		EXIT_FAILED
+	} else if !failed.failed.is_empty() || !partial.is_empty() {
+		warn!("Complete with failures");
+		EXIT_PARTIAL
 	} else {
-		image::load_from_memory(&data)
+		info!("Complete 🎉");
+		0
 	};
+	log::logger().flush();
+	if code != 0 {
+		std::process::exit(code);
+	}
+	Ok(())
+}
 
 
-	if let Ok(image) = image {
-		trace!(
-		       "original image: {}, len: {} ({format:?}, {:?})",
-		       uri.display(),
-		       data.len(),
-		       image.color()
-		);
-
-		let mut output: Vec<u8> = Vec::new();
-
-		match &cfg.format {
-			ImageOutputFormat::Avif => {
-				use image::codecs::avif::{AvifEncoder, ColorSpace};
-				AvifEncoder::new_with_speed_quality(&mut output, cfg.speed, cfg.quality).with_colorspace(ColorSpace::Bt709)
-				                                                                        .write_image(
-				                                                                                     image.as_bytes(),
-				                                                                                     image.width(),
-				                                                                                     image.height(),
-				                                                                                     image.color(),
-				)?;
-			},
-
-			ImageOutputFormat::WebP => {
-				use image::codecs::webp::{WebPEncoder, WebPQuality};
-				let quality = if cfg.lossless {
-					WebPQuality::lossless()
-				} else {
-					WebPQuality::lossy(cfg.quality)
-				};
-				WebPEncoder::new_with_quality(&mut output, quality).write_image(
-				                                                                image.as_bytes(),
-				                                                                image.width(),
-				                                                                image.height(),
-				                                                                image.color(),
-				)?;
-			},
-
-			ImageOutputFormat::Png => {
-				use image::codecs::png::{PngEncoder, CompressionType, FilterType};
-				PngEncoder::new_with_quality(&mut output, CompressionType::Best, FilterType::Adaptive).write_image(
-				                                                                                                   image.as_bytes(),
-				                                                                                                   image.width(),
-				                                                                                                   image.height(),
-				                                                                                                   image.color(),
-				)?;
-			},
-			format => {
-				use std::io::Cursor;
-				image.write_to(&mut Cursor::new(&mut output), format.to_owned())?
-			},
-		}
-
-
-		let filename = Path::new(&filename).with_extension(cfg.format.ext())
-		                                   .display()
-		                                   .to_string();
-		trace!(
-		       "transcoded image: {filename}, len: {} ({:?})",
-		       data.len(),
-		       cfg.format
-		);
+/// Clean up the progress display after a command, `None` result means it was interrupted.
+fn finish_command(res: Option<Result<(), Error>>, multibar: Option<MultiProgress>) -> Result<(), Box<dyn std::error::Error>> {
+	if let Some(multibar) = multibar {
+		multibar.clear()?;
+	}
+	let res = match res {
+		Some(res) => res,
+		None => {
+			error!("Interrupted, incomplete outputs are removed");
+			log::logger().flush();
+			std::process::exit(130);
+		},
+	};
+	log::logger().flush();
+	Ok(res?)
+}
 
 
-		Ok((filename, output))
-	} else {
-		warn!(
-		      "Unable to decode as image: {}, so just copying as-is.",
-		      uri.display()
-		);
-		Ok((name.as_ref().to_string(), data))
+/// Run the future until it completes or Ctrl-C is pressed.
+/// On Ctrl-C the future is dropped, so outputs in progress are removed.
+async fn until_interrupted<F: std::future::Future>(future: F) -> Option<F::Output> {
+	tokio::select! {
+		res = future => Some(res),
+		_ = tokio::signal::ctrl_c() => None,
 	}
 }
@@ -0,0 +1,78 @@
+//! Checksum manifest written next to each output for `--checksums`,
+//! an audit trail of which source produced which output before originals are purged.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+use crate::cli::{Checksum, Config};
+use crate::error::Error;
+
+
+#[derive(Serialize, Debug)]
+struct Manifest {
+	algorithm: Checksum,
+	source: FileSum,
+	/// Output and its next volumes.
+	outputs: Vec<FileSum>,
+	version: &'static str,
+	/// Hash of settings affecting the output.
+	settings: String,
+}
+
+
+#[derive(Serialize, Debug)]
+struct FileSum {
+	path: PathBuf,
+	size: u64,
+	hash: String,
+}
+
+
+/// Path of the manifest of the output: `name.cbz.sha256.json`.
+pub fn manifest_path(output: &Path, algorithm: Checksum) -> PathBuf {
+	let mut name = output.file_name().unwrap_or_default().to_owned();
+	name.push(format!(".{}.json", algorithm.ext()));
+	output.with_file_name(name)
+}
+
+
+/// Hash the source and outputs and write the manifest, returns its path.
+pub async fn write(source: &Path, outputs: Vec<PathBuf>, algorithm: Checksum, cfg: &Config) -> Result<PathBuf, Error> {
+	let path = manifest_path(&outputs[0], algorithm);
+	let source = source.to_owned();
+	let settings = cfg.settings_hash();
+	let data = tokio::task::spawn_blocking(move || -> Result<_, Error> {
+		           let manifest = Manifest { algorithm,
+		                                     source: file_sum(source)?,
+		                                     outputs: outputs.into_iter().map(file_sum).collect::<Result<_, _>>()?,
+		                                     version: env!("CARGO_PKG_VERSION"),
+		                                     settings };
+		           Ok(serde_json::to_vec_pretty(&manifest)?)
+	           }).await
+	           .map_err(|_| "checksum task panicked".to_owned())??;
+	tokio::fs::write(&path, data).await?;
+	debug!("checksums written to '{}'", path.display());
+	Ok(path)
+}
+
+
+fn file_sum(path: PathBuf) -> Result<FileSum, Error> {
+	use sha2::{Sha256, Digest};
+	let mut file = std::fs::File::open(&path)?;
+	let mut hasher = Sha256::new();
+	let mut buffer = vec![0; 1 << 20];
+	let mut size = 0;
+	loop {
+		match file.read(&mut buffer)? {
+			0 => break,
+			n => {
+				hasher.update(&buffer[..n]);
+				size += n as u64;
+			},
+		}
+	}
+	Ok(FileSum { path,
+	             size,
+	             hash: crate::paths::hex(&hasher.finalize()) })
+}
@@ -0,0 +1,67 @@
+//! `comic-repack.json` marker written into outputs with `--skip-converted`,
+//! so archives converted with the same settings are recognized and skipped on next runs.
+
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Config;
+use crate::source::Source;
+
+
+pub const NAME: &str = "comic-repack.json";
+
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Marker {
+	/// Version of comic-repack which produced the archive.
+	pub version: String,
+	/// Hash of settings affecting the output.
+	pub settings: String,
+}
+
+
+impl Marker {
+	pub fn new(cfg: &Config) -> Self {
+		Self { version: env!("CARGO_PKG_VERSION").to_owned(),
+		       settings: cfg.settings_hash() }
+	}
+
+
+	pub fn to_json(&self) -> Vec<u8> { serde_json::to_vec_pretty(self).expect("serializable") }
+
+
+	/// Marker of the archive, `None` if it has none or it can't be read.
+	pub fn read(path: &Path) -> Option<Self> {
+		let source = Source::open(path, None).ok()?;
+		let name = source.list_file_names().ok()?.into_iter().find(|name| is_marker(name))?;
+		let mut data = Vec::new();
+		source.read_file(&name, &mut data).ok()?;
+		match serde_json::from_slice(&data) {
+			Ok(marker) => Some(marker),
+			Err(err) => {
+				debug!("invalid marker in '{}': {err}", path.display());
+				None
+			},
+		}
+	}
+}
+
+
+pub fn is_marker(uri: &str) -> bool { uri == NAME }
+
+
+/// Archive was produced by comic-repack with the same settings.
+pub fn is_converted(path: &Path, cfg: &Config) -> bool {
+	let Some(marker) = Marker::read(path) else {
+		return false;
+	};
+	let expected = Marker::new(cfg);
+	if marker.settings != expected.settings {
+		debug!("'{}' was converted with other settings", path.display());
+		return false;
+	}
+	if marker.version != expected.version {
+		debug!("'{}' was converted by comic-repack {}", path.display(), marker.version);
+	}
+	true
+}
@@ -0,0 +1,53 @@
+//! Memory budget for `--max-memory`.
+//!
+//! Every page reserves its estimated footprint (source data, decoded pixels and encoded output)
+//! before conversion and releases it when written, so concurrency is limited by bytes in flight
+//! rather than by the number of pages only.
+
+use std::sync::OnceLock;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+
+/// Budget is counted in KiB, so any reasonable size fits into permits.
+const UNIT: u64 = 1024;
+
+static BUDGET: OnceLock<Budget> = OnceLock::new();
+
+
+struct Budget {
+	semaphore: Semaphore,
+	units: u32,
+}
+
+
+/// Memory held by a page until dropped.
+pub struct Reservation(#[allow(dead_code)] SemaphorePermit<'static>);
+
+
+/// Limit memory of pages in flight to `bytes`.
+pub fn init(bytes: u64) {
+	let units = (bytes / UNIT).clamp(1, u32::MAX as _) as u32;
+	debug!("memory budget: {units} KiB");
+	BUDGET.set(Budget { semaphore: Semaphore::new(units as _),
+	                    units })
+	      .ok();
+}
+
+
+/// Wait until `bytes` are available, `None` if there is no budget.
+/// Page larger than the whole budget waits for all others and then takes it alone.
+pub async fn reserve(bytes: u64) -> Option<Reservation> {
+	let budget = BUDGET.get()?;
+	let units = bytes.div_ceil(UNIT).clamp(1, budget.units as _) as u32;
+	trace!("reserving {units} KiB");
+	let permit = budget.semaphore.acquire_many(units).await.ok()?;
+	Some(Reservation(permit))
+}
+
+
+/// Estimated peak memory to convert the source page: the data itself, decoded pixels and output.
+pub fn estimate(data: &[u8]) -> u64 {
+	let decoded = crate::scale::dimensions(data, None).map(|(w, h)| w as u64 * h as u64 * 4)
+	                                                  .unwrap_or_default();
+	data.len() as u64 * 2 + decoded
+}
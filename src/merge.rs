@@ -0,0 +1,58 @@
+//! `merge` command: concatenate several archives into one output.
+
+use std::sync::Arc;
+use indicatif::MultiProgress;
+
+use crate::cli::MergeArgs;
+use crate::comic_info::is_comic_info;
+use crate::error::Error;
+use crate::volumes::VolumeWriter;
+
+
+pub async fn run(args: &MergeArgs, multibar: MultiProgress) -> Result<(), Error> {
+	let cfg = &args.config;
+	let mut writer = VolumeWriter::open(&args.output, cfg.force, cfg).await?;
+
+	// Chapter number prefix keeps pages of each source together and in order:
+	let width = args.input.len().to_string().len().max(2);
+	let mut pages = 0;
+	let mut failed = 0;
+	let mut has_comic_info = false;
+	for (i, input) in args.input.iter().enumerate() {
+		let (reader, mut entries, _) = crate::archive_reader(input, cfg.password.as_deref(), &cfg.junk_filter()).await?;
+		crate::filter_working_files(&mut entries, cfg);
+		let reader = Arc::new(reader);
+
+		// readers expect a single ComicInfo.xml at the root:
+		let (infos, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(|entry| is_comic_info(&entry.uri));
+		match infos.first() {
+			Some(info) if !has_comic_info => {
+				let mut data = Vec::new();
+				reader.read_file(&info.uri, &mut data)?;
+				writer.write_all("ComicInfo.xml", &data, None, None).await?;
+				has_comic_info = true;
+			},
+			Some(_) => debug!("ComicInfo.xml of {} is dropped, the first one is kept", input.display()),
+			None => {},
+		}
+
+		let prefix = format!("{:0width$}-", i + 1);
+		let bar = crate::source_progress_bar(&multibar, &reader, &entries);
+		let (stats, failures) =
+			crate::convert_entries(&reader, entries, &mut writer, None, Some(&prefix), cfg, &Default::default(), Some(bar)).await?;
+		info!("Merged: {}, {} pages", input.display(), stats.len());
+		pages += stats.len();
+		failed += failures.len();
+	}
+
+	let size = writer.close().await?;
+	info!(
+	      "Written: {}, {pages} pages from {} archives, {size}b",
+	      args.output.display(),
+	      args.input.len()
+	);
+	if failed > 0 {
+		return Err(format!("{failed} pages failed, '{}' is incomplete", args.output.display()).into());
+	}
+	Ok(())
+}
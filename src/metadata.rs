@@ -0,0 +1,123 @@
+//! Removal of EXIF, XMP, IPTC, comments and embedded thumbnails from pages copied as-is,
+//! for `--strip-metadata`. Re-encoded pages carry no metadata anyway.
+//!
+//! Only metadata is removed, image data and color information (ICC, Adobe transform) are kept byte to byte.
+
+use image::ImageFormat;
+
+
+/// Data without metadata, as-is for other formats or if it can't be parsed.
+pub fn strip(data: Vec<u8>, format: Option<ImageFormat>) -> Vec<u8> {
+	let stripped = match format {
+		Some(ImageFormat::Jpeg) => strip_jpeg(&data),
+		Some(ImageFormat::Png) => strip_png(&data),
+		Some(ImageFormat::WebP) => strip_webp(&data),
+		_ => None,
+	};
+	match stripped {
+		Some(stripped) => {
+			if stripped.len() != data.len() {
+				trace!("stripped metadata: {}b -> {}b", data.len(), stripped.len());
+			}
+			stripped
+		},
+		None => data,
+	}
+}
+
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+	const SOS: u8 = 0xDA;
+	const APP2: u8 = 0xE2;
+	const COM: u8 = 0xFE;
+
+	if !data.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+	let mut output = Vec::with_capacity(data.len());
+	output.extend_from_slice(&data[..2]);
+	let mut pos = 2;
+	loop {
+		let marker = *data.get(pos + 1)?;
+		if data[pos] != 0xFF {
+			return None;
+		}
+		if marker == SOS {
+			// entropy-coded data and the rest as-is:
+			output.extend_from_slice(&data[pos..]);
+			return Some(output);
+		}
+		let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+		// the length includes its own two bytes:
+		if len < 2 {
+			return None;
+		}
+		let segment = data.get(pos..pos + 2 + len)?;
+		let payload = &segment[4..];
+		// APP0 is JFIF, APP14 is Adobe color transform, ICC profile is in APP2:
+		let keep = match marker {
+			APP2 => payload.starts_with(b"ICC_PROFILE\0"),
+			0xE1 | 0xE3..=0xED | 0xEF | COM => false,
+			_ => true,
+		};
+		if keep {
+			output.extend_from_slice(segment);
+		}
+		pos += 2 + len;
+	}
+}
+
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+	const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+	const METADATA: [&[u8]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+	if !data.starts_with(SIGNATURE) {
+		return None;
+	}
+	let mut output = Vec::with_capacity(data.len());
+	output.extend_from_slice(SIGNATURE);
+	let mut pos = SIGNATURE.len();
+	while pos < data.len() {
+		let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+		// length, type, data, crc:
+		let chunk = data.get(pos..pos + 12 + len)?;
+		if !METADATA.contains(&&chunk[4..8]) {
+			output.extend_from_slice(chunk);
+		}
+		pos += chunk.len();
+	}
+	Some(output)
+}
+
+
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+	const VP8X_EXIF: u8 = 0x08;
+	const VP8X_XMP: u8 = 0x04;
+
+	if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+		return None;
+	}
+	let mut output = Vec::with_capacity(data.len());
+	output.extend_from_slice(&data[..12]);
+	let mut pos = 12;
+	while pos < data.len() {
+		let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+		// chunks are padded to even size:
+		let end = (pos + 8 + len + len % 2).min(data.len());
+		let chunk = data.get(pos..end)?;
+		match &chunk[..4] {
+			b"EXIF" | b"XMP " => {},
+			b"VP8X" => {
+				let start = output.len();
+				output.extend_from_slice(chunk);
+				*output.get_mut(start + 8)? &= !(VP8X_EXIF | VP8X_XMP);
+			},
+			_ => output.extend_from_slice(chunk),
+		}
+		pos = end;
+	}
+	let size = (output.len() - 8) as u32;
+	output[4..8].copy_from_slice(&size.to_le_bytes());
+	Some(output)
+}
@@ -0,0 +1,52 @@
+//! Desktop notifications for `--notify`, shown by the notifier of the system:
+//! `notify-send` on Linux and BSD, `osascript` on macOS and a PowerShell balloon on Windows.
+
+use std::process::{Command, Stdio};
+use indicatif::HumanBytes;
+
+
+/// Show the notification without waiting for it, failures are only logged.
+pub fn send(title: &str, body: &str) {
+	// text is passed in environment variables, so it needs no escaping in scripts:
+	let mut command = if cfg!(target_os = "macos") {
+		let mut command = Command::new("osascript");
+		command.args([
+			"-e",
+			r#"display notification (system attribute "COMIC_BODY") with title (system attribute "COMIC_TITLE")"#,
+		]);
+		command
+	} else if cfg!(windows) {
+		let mut command = Command::new("powershell");
+		command.args([
+			"-NoProfile",
+			"-Command",
+			"Add-Type -AssemblyName System.Windows.Forms; $n = New-Object System.Windows.Forms.NotifyIcon; \
+			 $n.Icon = [System.Drawing.SystemIcons]::Information; $n.Visible = $true; \
+			 $n.ShowBalloonTip(10000, $env:COMIC_TITLE, $env:COMIC_BODY, 'Info'); Start-Sleep 10; $n.Dispose()",
+		]);
+		command
+	} else {
+		let mut command = Command::new("notify-send");
+		command.args(["--app-name=comic-repack", title, body]);
+		command
+	};
+	let spawned = command.env("COMIC_TITLE", title)
+	                     .env("COMIC_BODY", body)
+	                     .stdin(Stdio::null())
+	                     .stdout(Stdio::null())
+	                     .stderr(Stdio::null())
+	                     .spawn();
+	if let Err(err) = spawned {
+		warn!("Unable to show notification: {err}");
+	}
+}
+
+
+/// `saved 1.2 GiB (35.0%)` of the total sizes.
+pub fn savings(source: u64, output: u64) -> String {
+	let ratio = match source {
+		0 => 0.0,
+		source => (source as f64 - output as f64) / source as f64 * 100.0,
+	};
+	format!("saved {} ({ratio:.1}%)", HumanBytes(source.saturating_sub(output)))
+}
@@ -1,13 +1,17 @@
 use std::ffi::OsStr;
+use std::io::BufRead;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
-use crate::cli::ArchiveType;
-use crate::cli::FormatFileExt;
+use crate::cli::{BatchOrder, EntryOrder};
 
 
-pub async fn validate_and_unglob(mut paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-	let unexisting = paths.extract_if(|p| !p.try_exists().ok().unwrap_or(false));
+/// Resolve globs, and directories if `walk` has extensions of archives to find there.
+pub async fn validate_and_unglob(mut paths: Vec<PathBuf>,
+                                 walk: Option<&[String]>)
+                                 -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+	let unexisting = paths.extract_if(.., |p| !p.try_exists().ok().unwrap_or(false));
 	let mut resolved = Vec::new();
 	for query in unexisting {
 		let current = resolved.len();
@@ -22,6 +26,15 @@ pub async fn validate_and_unglob(mut paths: Vec<PathBuf>) -> Result<Vec<PathBuf>
 	}
 	paths.append(&mut resolved);
 
+	if let Some(exts) = walk {
+		let dirs: Vec<_> = paths.extract_if(.., |p| p.is_dir()).collect();
+		for dir in dirs {
+			let current = paths.len();
+			paths.extend(walk_dir(&dir, exts).await?);
+			debug!("found {} archives in '{}'", paths.len() - current, dir.display());
+		}
+	}
+
 	// Need dedup but not sort because we want to keep the order, user's preferred order...
 	// Or not? Ok, just sort & dedup:
 	paths.sort();
@@ -30,6 +43,88 @@ pub async fn validate_and_unglob(mut paths: Vec<PathBuf>) -> Result<Vec<PathBuf>
 	Ok(paths)
 }
 
+/// Inputs listed in the file, or stdin for `-`, separated by newlines or NULs.
+/// The list is read by parts, so only the paths are kept in memory.
+pub fn read_file_list(path: &Path, null: bool) -> Result<Vec<PathBuf>, std::io::Error> {
+	let reader: Box<dyn BufRead> = if path == Path::new("-") {
+		Box::new(std::io::stdin().lock())
+	} else {
+		Box::new(std::io::BufReader::new(std::fs::File::open(path)?))
+	};
+	let separator = if null { b'\0' } else { b'\n' };
+	let mut inputs = Vec::new();
+	for item in reader.split(separator) {
+		let mut item = item?;
+		if !null && item.ends_with(b"\r") {
+			item.pop();
+		}
+		if !item.is_empty() {
+			inputs.push(path_from_bytes(item));
+		}
+	}
+	debug!("{} inputs listed in '{}'", inputs.len(), path.display());
+	Ok(inputs)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+	use std::os::unix::ffi::OsStringExt;
+	std::ffi::OsString::from_vec(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf { String::from_utf8_lossy(&bytes).into_owned().into() }
+
+
+/// Extract `input=output name` pairs from inputs.
+/// An argument is a pair only if it doesn't exist as is, but its part before `=` does.
+pub fn split_renames(inputs: Vec<PathBuf>) -> (Vec<PathBuf>, std::collections::HashMap<PathBuf, PathBuf>) {
+	let mut renames = std::collections::HashMap::new();
+	let inputs = inputs.into_iter()
+	                   .map(|input| {
+		                   if input.exists() {
+			                   return input;
+		                   }
+		                   let s = input.to_string_lossy();
+		                   let pair = s.match_indices('=')
+		                               .map(|(i, _)| (&s[..i], &s[i + 1..]))
+		                               .find(|(src, dst)| !dst.is_empty() && Path::new(src).exists());
+		                   match pair {
+			                   Some((src, dst)) => {
+				                   let src = PathBuf::from(src);
+				                   renames.insert(src.clone(), PathBuf::from(dst));
+				                   src
+			                   },
+			                   None => input,
+		                   }
+	                   })
+	                   .collect();
+	(inputs, renames)
+}
+
+/// Files with given extensions in the directory and its subdirectories.
+pub async fn walk_dir(dir: &Path, exts: &[String]) -> Result<Vec<PathBuf>, std::io::Error> {
+	let matches = |path: &Path| {
+		path.extension()
+		    .is_some_and(|ext| exts.iter().any(|wanted| ext.eq_ignore_ascii_case(wanted.trim_start_matches('.'))))
+	};
+	let mut found = Vec::new();
+	let mut pending = vec![dir.to_owned()];
+	while let Some(dir) = pending.pop() {
+		let mut entries = tokio::fs::read_dir(&dir).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			let path = entry.path();
+			if entry.file_type().await?.is_dir() {
+				pending.push(path);
+			} else if matches(&path) {
+				found.push(path);
+			}
+		}
+	}
+	Ok(found)
+}
+
+
 pub async fn unglob<S: AsRef<str>>(pattern: S)
                                    -> Result<impl Iterator<Item = PathBuf>, Box<dyn std::error::Error>> {
 	use glob::glob;
@@ -49,16 +144,28 @@ fn sanitize_path(path: &Path) -> PathBuf {
 }
 
 
-pub fn output_archive_path(source: impl AsRef<Path>, outdir: impl AsRef<Path>, archive: ArchiveType) -> PathBuf {
+/// Output path for the source in `outdir`, with `prefix` and `suffix` around the file stem.
+pub fn output_archive_path(source: impl AsRef<Path>,
+                           outdir: impl AsRef<Path>,
+                           ext: &str,
+                           prefix: &str,
+                           suffix: &str)
+                           -> PathBuf {
 	let source = source.as_ref();
 	let subpath = if source.is_absolute() {
 		source.file_name()
 		      .map(Path::new)
 		      .or(source.parent())
 		      .expect("invalid path")
-		      .with_extension(archive.ext())
+		      .with_extension(ext)
 	} else {
-		sanitize_path(source).with_extension(archive.ext())
+		sanitize_path(source).with_extension(ext)
+	};
+	let subpath = match subpath.file_stem() {
+		Some(stem) if !prefix.is_empty() || !suffix.is_empty() => {
+			subpath.with_file_name(format!("{prefix}{}{suffix}.{ext}", stem.to_string_lossy()))
+		},
+		_ => subpath,
 	};
 
 	let output = outdir.as_ref().join(subpath);
@@ -66,6 +173,15 @@ pub fn output_archive_path(source: impl AsRef<Path>, outdir: impl AsRef<Path>, a
 }
 
 
+/// Validate `--prefix` and `--suffix`, which must not change the folder.
+pub fn parse_affix(s: &str) -> Result<String, String> {
+	match s.contains(['/', '\\']) {
+		true => Err(format!("'{s}' must not contain path separators")),
+		false => Ok(s.to_owned()),
+	}
+}
+
+
 pub type StringEntry = Entry<usize, String>;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -83,21 +199,77 @@ impl<I, S: AsRef<OsStr>> From<(I, S)> for Entry<I, S> {
 }
 
 
-pub fn filter_entries<S: AsRef<OsStr>>(entries: impl Iterator<Item = S> + Send)
-                                       -> impl Iterator<Item = S> + Send {
-	entries.filter(|entry| {
-		       let s = entry.as_ref().to_string_lossy();
-		       let uri = Path::new(&entry);
-		       let skip = s.ends_with("/") ||
-		                  uri.ends_with("Thumbs.db") ||
-		                  uri.file_name() == Some(&OsStr::new(".DS_Store")) ||
-		                  uri.iter()
-		                     .filter(|item| *item == OsStr::new("__MACOSX"))
-		                     .filter(|item| *item == OsStr::new(".DS_Store"))
-		                     .filter(|item| item.len() > 1 && item.to_string_lossy().starts_with("."))
-		                     .next()
-		                     .is_some();
+/// Rules of the default junk filter.
+#[derive(Debug, Clone, Copy)]
+enum JunkRule {
+	/// Windows thumbnail caches and folder settings: `Thumbs.db`, `ehthumbs.db`, `desktop.ini`.
+	WindowsMetadata,
+	/// macOS resource forks in `__MACOSX` folders.
+	MacResourceForks,
+	/// Hidden files and folders, such as `.DS_Store` or `._page.jpg`.
+	Hidden,
+}
 
+const DEFAULT_JUNK_RULES: [JunkRule; 3] = [JunkRule::WindowsMetadata, JunkRule::MacResourceForks, JunkRule::Hidden];
+
+impl JunkRule {
+	fn matches(self, uri: &Path) -> bool {
+		match self {
+			Self::WindowsMetadata => {
+				uri.file_name().is_some_and(|name| {
+					               ["Thumbs.db", "ehthumbs.db", "desktop.ini"].iter()
+					                                                          .any(|junk| name.eq_ignore_ascii_case(junk))
+				               })
+			},
+			Self::MacResourceForks => uri.iter().any(|item| item == "__MACOSX"),
+			Self::Hidden => {
+				uri.iter().any(|item| {
+					          let item = item.to_string_lossy();
+					          item.len() > 1 && item.starts_with('.') && item != ".."
+				          })
+			},
+		}
+	}
+}
+
+
+/// Filter of entries which are not content: OS metadata by default rules and `--junk-pattern`s.
+#[derive(Debug, Clone)]
+pub struct JunkFilter {
+	defaults: bool,
+	patterns: Vec<glob::Pattern>,
+}
+
+impl Default for JunkFilter {
+	fn default() -> Self { Self::new(true, Vec::new()) }
+}
+
+impl JunkFilter {
+	pub fn new(defaults: bool, patterns: Vec<glob::Pattern>) -> Self { Self { defaults, patterns } }
+
+	/// Patterns match either the whole path in the archive or the file name.
+	pub fn is_junk(&self, uri: &Path) -> bool {
+		if self.defaults {
+			if let Some(rule) = DEFAULT_JUNK_RULES.iter().find(|rule| rule.matches(uri)) {
+				trace!("junk by {rule:?}: {}", uri.display());
+				return true;
+			}
+		}
+		let name = uri.file_name().map(|name| name.to_string_lossy());
+		self.patterns.iter().any(|pattern| {
+			                    pattern.matches_path(uri) || name.as_ref().is_some_and(|name| pattern.matches(name))
+		                    })
+	}
+}
+
+
+/// Skip directory entries and junk.
+pub fn filter_entries<'a, S: AsRef<OsStr>>(entries: impl Iterator<Item = S> + Send + 'a,
+                                           junk: &'a JunkFilter)
+                                           -> impl Iterator<Item = S> + Send + 'a {
+	entries.filter(move |entry| {
+		       let s = entry.as_ref().to_string_lossy();
+		       let skip = s.ends_with('/') || junk.is_junk(Path::new(&entry));
 		       if skip {
 			       trace!("outfiltered inner file: {s}");
 		       }
@@ -106,6 +278,40 @@ pub fn filter_entries<S: AsRef<OsStr>>(entries: impl Iterator<Item = S> + Send)
 }
 
 
+/// Already compressed formats which can't be compressed further by the archiver.
+pub fn is_compressed(uri: impl AsRef<Path>) -> bool {
+	uri.as_ref()
+	   .extension()
+	   .map(|ext| ext.to_string_lossy().to_lowercase())
+	   .filter(|ext| {
+		   matches!(
+		            ext.as_str(),
+		            "jpg" | "jpeg" | "png" | "gif" | "webp" | "avif" | "jxl" | "heic" | "heif" | "zip" | "7z" | "rar"
+		)
+	   })
+	   .is_some()
+}
+
+
+/// Layered working files of image editors, such as PSD or XCF.
+pub fn is_working_file(uri: impl AsRef<Path>) -> bool {
+	uri.as_ref()
+	   .extension()
+	   .map(|ext| ext.to_string_lossy().to_lowercase())
+	   .filter(|ext| matches!(ext.as_str(), "psd" | "psb" | "xcf"))
+	   .is_some()
+}
+
+/// Working file which can be flattened to a raster page.
+pub fn is_flattenable(uri: impl AsRef<Path>) -> bool {
+	uri.as_ref()
+	   .extension()
+	   .map(|ext| ext.to_string_lossy().to_lowercase())
+	   .filter(|ext| matches!(ext.as_str(), "psd" | "psb"))
+	   .is_some()
+}
+
+
 /// Try to find root dir in one pass.
 /// Algorithm is stupidly simple:
 /// - find first component of path without ext => this is potential root dir
@@ -152,3 +358,286 @@ pub fn remove_root_entry<S: AsRef<OsStr>>(entries: impl Iterator<Item = S> + Sen
 		       Some(entry.as_ref()) != root.as_deref()
 	       })
 }
+
+
+/// Compare strings treating runs of digits as numbers, so `p2` < `p10`.
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+	use std::cmp::Ordering;
+
+	let mut a = a.chars().peekable();
+	let mut b = b.chars().peekable();
+	loop {
+		match (a.peek().copied(), b.peek().copied()) {
+			(None, None) => return Ordering::Equal,
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+				let take_number = |it: &mut std::iter::Peekable<std::str::Chars>| {
+					let mut digits = String::new();
+					while let Some(c) = it.next_if(char::is_ascii_digit) {
+						digits.push(c);
+					}
+					digits
+				};
+				let x = take_number(&mut a);
+				let y = take_number(&mut b);
+				let xt = x.trim_start_matches('0');
+				let yt = y.trim_start_matches('0');
+				let ord = xt.len().cmp(&yt.len()).then_with(|| xt.cmp(yt)).then_with(|| x.len().cmp(&y.len()));
+				if ord != Ordering::Equal {
+					return ord;
+				}
+			},
+			(Some(x), Some(y)) => {
+				let ord = x.to_lowercase().cmp(y.to_lowercase()).then(x.cmp(&y));
+				if ord != Ordering::Equal {
+					return ord;
+				}
+				a.next();
+				b.next();
+			},
+		}
+	}
+}
+
+
+/// Sort entries in order they should be written to the output archive.
+pub fn sort_entries(entries: &mut [StringEntry], order: EntryOrder) {
+	match order {
+		EntryOrder::Original => entries.sort_by_key(|entry| entry.index),
+		EntryOrder::Natural => entries.sort_by(|a, b| natural_cmp(&a.uri, &b.uri)),
+		EntryOrder::Name => entries.sort_by(|a, b| a.uri.cmp(&b.uri)),
+	}
+}
+
+
+/// Keep archives of the batch with size within `min..=max`, returns the number of skipped ones.
+pub fn filter_size(paths: &mut Vec<PathBuf>, min: Option<u64>, max: Option<u64>) -> usize {
+	let before = paths.len();
+	paths.retain(|path| {
+		      let Ok(size) = std::fs::metadata(path).map(|meta| meta.len()) else {
+			      // unreadable archives fail later with the reason:
+			      return true;
+		      };
+		      let keep = min.is_none_or(|min| size >= min) && max.is_none_or(|max| size <= max);
+		      if !keep {
+			      debug!("skipping '{}' of {size}b, outside of input size limits", path.display());
+		      }
+		      keep
+	      });
+	before - paths.len()
+}
+
+
+/// Sort archives of the batch, unreadable ones count as the largest and newest.
+pub fn sort_batch(paths: &mut [PathBuf], order: BatchOrder, reverse: bool) {
+	match order {
+		BatchOrder::Name => paths.sort(),
+		BatchOrder::Size => {
+			paths.sort_by_cached_key(|path| std::fs::metadata(path).map_or(u64::MAX, |meta| meta.len()))
+		},
+		BatchOrder::Mtime => {
+			paths.sort_by_cached_key(|path| {
+				      std::fs::metadata(path).and_then(|meta| meta.modified())
+				                             .map_or((1, SystemTime::UNIX_EPOCH), |time| (0, time))
+			      })
+		},
+	}
+	if reverse {
+		paths.reverse();
+	}
+}
+
+
+/// Assign page numbers (starting from 1) to image entries in natural order.
+/// Returns map of `Entry::index` => page number.
+pub fn page_numbers(entries: &[StringEntry]) -> std::collections::HashMap<usize, usize> {
+	let mut pages: Vec<_> = entries.iter()
+	                               .filter(|entry| image::ImageFormat::from_path(&entry.uri).is_ok())
+	                               .collect();
+	pages.sort_by(|a, b| natural_cmp(&a.uri, &b.uri));
+	pages.into_iter()
+	     .enumerate()
+	     .map(|(i, entry)| (entry.index, i + 1))
+	     .collect()
+}
+
+
+/// Validate page name template for `--rename-pages`.
+pub fn parse_page_template(s: &str) -> Result<String, String> {
+	render_page_name(s, 0, "page").map(|_| s.to_owned())
+}
+
+/// Render page name template with `{index}`, `{index:0N}` and `{stem}` placeholders,
+/// keeping extension of the `name`.
+pub fn render_page_name(template: &str, index: usize, name: &str) -> Result<String, String> {
+	let path = Path::new(name);
+	let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+
+	let mut result = String::with_capacity(template.len() + 8);
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		result.push_str(&rest[..start]);
+		let end = rest[start..].find('}')
+		                       .map(|end| start + end)
+		                       .ok_or_else(|| format!("Unclosed placeholder in '{template}'"))?;
+		let (key, width) = match rest[start + 1..end].split_once(':') {
+			// leading zeros are optional, `{index:0}` is not padded:
+			Some((key, spec)) if !spec.is_empty() && spec.bytes().all(|b| b.is_ascii_digit()) => {
+				let width = spec.parse::<usize>()
+				                .map_err(|_| format!("Invalid width '{spec}' in '{template}'"))?;
+				(key, Some(width))
+			},
+			Some((_, spec)) => return Err(format!("Invalid width '{spec}' in '{template}'")),
+			None => (&rest[start + 1..end], None),
+		};
+		match (key, width) {
+			("index", width) => {
+				let width = width.unwrap_or_default();
+				result.push_str(&format!("{index:0width$}"))
+			},
+			("stem", None) => result.push_str(&stem),
+			("stem", Some(_)) => return Err(format!("Placeholder 'stem' has no width in '{template}'")),
+			(other, _) => return Err(format!("Unknown placeholder '{other}' in '{template}'")),
+		}
+		rest = &rest[end + 1..];
+	}
+	result.push_str(rest);
+
+	// names are flat, backslash is a separator for readers on Windows:
+	if result.is_empty() || result.contains('/') || template.contains('\\') {
+		return Err(format!("Invalid page name template '{template}'"));
+	}
+
+	Ok(match path.extension() {
+		Some(ext) => format!("{result}.{}", ext.to_string_lossy()),
+		None => result,
+	})
+}
+
+
+/// Path of output volume: `out/name.cbz` => `out/name.part2.cbz`.
+pub fn volume_path(path: &Path, volume: usize) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	match path.extension() {
+		Some(ext) => path.with_file_name(format!("{stem}.part{volume}.{}", ext.to_string_lossy())),
+		None => path.with_file_name(format!("{stem}.part{volume}")),
+	}
+}
+
+
+/// Add part number of a split page to the name: `p01.png` => `p01-1.png`.
+pub fn with_part(name: &str, part: usize) -> String { with_stem_suffix(name, &format!("-{part}")) }
+
+/// Name for the `n`-th entry with the same name, e.g. `001~2.avif`.
+pub fn with_copy_number(name: &str, n: usize) -> String { with_stem_suffix(name, &format!("~{n}")) }
+
+fn with_stem_suffix(name: &str, suffix: &str) -> String {
+	let path = Path::new(name);
+	match (path.file_stem(), path.extension()) {
+		(Some(stem), Some(ext)) => {
+			let file = format!("{}{suffix}.{}", stem.to_string_lossy(), ext.to_string_lossy());
+			path.with_file_name(file).display().to_string()
+		},
+		_ => format!("{name}{suffix}"),
+	}
+}
+
+
+/// SHA-256 of concatenated parts.
+pub fn digest<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> [u8; 32] {
+	use sha2::{Sha256, Digest};
+	let mut hasher = Sha256::new();
+	for part in parts {
+		hasher.update(part);
+	}
+	hasher.finalize().into()
+}
+
+pub fn hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn junk(uri: &str) -> bool { JunkFilter::default().is_junk(Path::new(uri)) }
+
+	fn patterns(patterns: &[&str]) -> Vec<glob::Pattern> {
+		patterns.iter().map(|pattern| glob::Pattern::new(pattern).unwrap()).collect()
+	}
+
+
+	#[test]
+	fn windows_metadata_ignores_case() {
+		assert!(junk("Thumbs.db"));
+		assert!(junk("ch1/THUMBS.DB"));
+		assert!(junk("ehthumbs.db"));
+		assert!(junk("Desktop.ini"));
+		assert!(!junk("thumbs.db.jpg"));
+	}
+
+
+	#[test]
+	fn mac_resource_forks() {
+		assert!(junk("__MACOSX/page.jpg"));
+		assert!(junk("ch1/__MACOSX/page.jpg"));
+		assert!(!junk("__MACOSX_pages/page.jpg"));
+	}
+
+
+	#[test]
+	fn hidden() {
+		assert!(junk(".DS_Store"));
+		assert!(junk("ch1/._page.jpg"));
+		assert!(junk(".git/config"));
+		assert!(!junk("../page.jpg"));
+		assert!(!junk("./page.jpg"));
+		assert!(!junk("page.jpg"));
+	}
+
+
+	#[test]
+	fn pattern_matches_path_or_name() {
+		let filter = JunkFilter::new(true, patterns(&["*credits*", "extras/*"]));
+		assert!(filter.is_junk(Path::new("credits.png")));
+		assert!(filter.is_junk(Path::new("ch1/scan credits.png")));
+		assert!(filter.is_junk(Path::new("extras/poster.jpg")));
+		assert!(!filter.is_junk(Path::new("ch1/extras/poster.jpg")));
+		assert!(!filter.is_junk(Path::new("ch1/001.jpg")));
+	}
+
+
+	#[test]
+	fn no_default_filters() {
+		let filter = JunkFilter::new(false, patterns(&["*.txt"]));
+		assert!(!filter.is_junk(Path::new("Thumbs.db")));
+		assert!(!filter.is_junk(Path::new("__MACOSX/page.jpg")));
+		assert!(!filter.is_junk(Path::new(".DS_Store")));
+		assert!(filter.is_junk(Path::new("readme.txt")));
+	}
+
+
+	#[test]
+	fn page_name_template() {
+		assert_eq!(render_page_name("{index:03}", 7, "ch1/p7.jpg").unwrap(), "007.jpg");
+		assert_eq!(render_page_name("{index:3}", 7, "p7.jpg").unwrap(), "007.jpg");
+		assert_eq!(render_page_name("{index:0}", 7, "p7.jpg").unwrap(), "7.jpg");
+		assert_eq!(render_page_name("{index}-{stem}", 12, "ch1/p7.jpg").unwrap(), "12-p7.jpg");
+		assert_eq!(render_page_name("page", 1, "p1").unwrap(), "page");
+	}
+
+
+	#[test]
+	fn invalid_page_name_template() {
+		assert!(parse_page_template("{index:}").is_err());
+		assert!(parse_page_template("{index:x3}").is_err());
+		assert!(parse_page_template("{stem:03}").is_err());
+		assert!(parse_page_template("{page}").is_err());
+		assert!(parse_page_template("{index").is_err());
+		assert!(parse_page_template("ch/{index}").is_err());
+		assert!(parse_page_template("ch\\{index}").is_err());
+		assert!(parse_page_template("").is_err());
+		assert!(parse_page_template("{stem}").is_ok());
+	}
+}
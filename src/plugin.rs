@@ -0,0 +1,117 @@
+//! WASM page plugin for `--page-plugin`, custom page processing without forking the crate.
+//!
+//! The module is interpreted with `wasmi`, so it runs everywhere the crate does, and is
+//! instantiated anew for every page, so plugins keep no state between pages.
+//! It has no imports and exports:
+//! - `memory`,
+//! - `alloc(len: i32) -> i32`, returning a pointer to `len` bytes of its memory,
+//! - `process(pixels: i32, width: i32, height: i32, name: i32, name_len: i32) -> i32`,
+//!   given the decoded page as RGBA8 rows and its name in the archive as UTF-8.
+//!
+//! `process` may change the pixels in place and returns a [`Decision`]:
+//! `0` to keep the page with its pixels, `1` to skip the page, `2` to copy the source page as-is.
+
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use image::{ColorType, DynamicImage, ImageError, RgbaImage};
+use wasmi::{Config, Engine, Linker, Module, Store};
+
+
+/// Instructions a plugin may run for one page, so a looping plugin fails the page instead of hanging.
+const FUEL: u64 = 1 << 36;
+
+
+/// Decision of the plugin about a page.
+#[derive(Debug)]
+pub enum Decision {
+	/// Page with pixels as left by the plugin.
+	Keep(DynamicImage),
+	/// Page is left out of the output.
+	Skip,
+	/// Source page is copied as-is.
+	Copy,
+}
+
+
+/// Compiled plugin, cheap to clone.
+#[derive(Clone)]
+pub struct Plugin {
+	path: PathBuf,
+	/// Digest of the module, so changing it changes settings of the run.
+	digest: [u8; 32],
+	engine: Engine,
+	module: Arc<Module>,
+}
+
+
+impl Debug for Plugin {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let digest: String = self.digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+		write!(f, "Plugin({}, {digest})", self.path.display())
+	}
+}
+
+
+/// Load and compile the module for `--page-plugin`.
+pub fn load(s: &str) -> Result<Plugin, String> {
+	let path = Path::new(s);
+	let wasm = std::fs::read(path).map_err(|err| format!("Can't read '{s}': {err}"))?;
+	let mut config = Config::default();
+	config.consume_fuel(true);
+	let engine = Engine::new(&config);
+	let module = Module::new(&engine, &wasm[..]).map_err(|err| format!("Invalid module '{s}': {err}"))?;
+	Ok(Plugin { path: path.to_owned(),
+	            digest: crate::paths::digest([&wasm[..]]),
+	            engine,
+	            module: Arc::new(module) })
+}
+
+
+impl Plugin {
+	pub fn process(&self, image: DynamicImage, name: &str) -> Result<Decision, ImageError> {
+		self.run(image, name).map_err(|err| {
+			                     let message = format!("page plugin '{}' failed on '{name}': {err}", self.path.display());
+			                     ImageError::IoError(std::io::Error::other(message))
+		                     })
+	}
+
+
+	fn run(&self, image: DynamicImage, name: &str) -> Result<Decision, wasmi::Error> {
+		let mut store = Store::new(&self.engine, ());
+		store.set_fuel(FUEL)?;
+		let instance = Linker::<()>::new(&self.engine).instantiate(&mut store, &self.module)?
+		                                              .start(&mut store)?;
+		let memory = instance.get_memory(&store, "memory")
+		                     .ok_or_else(|| wasmi::Error::new("no exported memory"))?;
+		let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+		let process = instance.get_typed_func::<(i32, i32, i32, i32, i32), i32>(&store, "process")?;
+
+		let (width, height, color) = (image.width(), image.height(), image.color());
+		let mut pixels = image.into_rgba8().into_raw();
+		let len = |len: usize| i32::try_from(len).map_err(|_| wasmi::Error::new("page too large"));
+		let pixels_ptr = alloc.call(&mut store, len(pixels.len())?)?;
+		memory.write(&mut store, pixels_ptr as u32 as usize, &pixels)?;
+		let name_ptr = alloc.call(&mut store, len(name.len())?)?;
+		memory.write(&mut store, name_ptr as u32 as usize, name.as_bytes())?;
+
+		let params = (pixels_ptr, width as i32, height as i32, name_ptr, len(name.len())?);
+		match process.call(&mut store, params)? {
+			0 => {
+				memory.read(&store, pixels_ptr as u32 as usize, &mut pixels)?;
+				let image = RgbaImage::from_raw(width, height, pixels).expect("buffer of the page size");
+				let image = DynamicImage::ImageRgba8(image);
+				// grayscale and opaque pages stay so, to not grow the output:
+				Ok(Decision::Keep(match color {
+					ColorType::L8 | ColorType::L16 => DynamicImage::ImageLuma8(image.to_luma8()),
+					ColorType::La8 | ColorType::La16 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+					ColorType::Rgb8 | ColorType::Rgb16 | ColorType::Rgb32F => DynamicImage::ImageRgb8(image.to_rgb8()),
+					_ => image,
+				}))
+			},
+			1 => Ok(Decision::Skip),
+			2 => Ok(Decision::Copy),
+			code => Err(wasmi::Error::new(format!("unknown decision {code}"))),
+		}
+	}
+}
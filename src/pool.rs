@@ -0,0 +1,61 @@
+//! Dedicated thread pool for decoding and encoding pages.
+//!
+//! Transcoding is CPU-bound, so it runs here instead of the async runtime,
+//! which is left for I/O and progress. Parallel codecs (e.g. AVIF) use the same pool.
+
+use std::sync::OnceLock;
+use tokio::sync::oneshot;
+
+use crate::error::Error;
+
+
+static POOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
+
+
+/// Create the pool with `threads` threads, does nothing if it already exists.
+pub fn init(threads: usize) -> Result<(), Error> {
+	if POOL.get().is_some() {
+		return Ok(());
+	}
+	debug!("encode threads: {threads}");
+	let pool = rayon::ThreadPoolBuilder::new().num_threads(threads)
+	                                          .thread_name(|i| format!("encode-{i}"))
+	                                          .build()
+	                                          .map_err(|err| format!("Unable to start encode threads: {err}"))?;
+	POOL.set(pool).ok();
+	Ok(())
+}
+
+
+/// Number of threads of the pool.
+pub fn threads() -> usize { pool().current_num_threads() }
+
+
+fn pool() -> &'static rayon::ThreadPool {
+	POOL.get_or_init(|| rayon::ThreadPoolBuilder::new().build().expect("thread pool"))
+}
+
+
+/// Run the job on the pool and wait for its result.
+/// Log records of the job are tagged with the archive of the caller.
+/// A panic of the job, e.g. of a codec on a malformed page, is returned as an error,
+/// since rayon aborts the process on panics of spawned jobs.
+pub async fn run<T: Send + 'static>(job: impl FnOnce() -> T + Send + 'static) -> Result<T, Error> {
+	let (sender, receiver) = oneshot::channel();
+	let job = crate::context::propagate(move || std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)));
+	pool().spawn(move || {
+		let start = std::time::Instant::now();
+		let res = job();
+		crate::tune::record(crate::tune::Stage::Transcode, start.elapsed());
+		sender.send(res).ok();
+	});
+	let res = receiver.await
+	                  .map_err(|_| Error::from("encode thread stopped".to_owned()))?;
+	res.map_err(|panic| {
+		    let message = panic.downcast_ref::<&str>()
+		                       .map(|s| s.to_string())
+		                       .or_else(|| panic.downcast_ref::<String>().cloned())
+		                       .unwrap_or_default();
+		    Error::from(format!("encode thread panicked: {message}"))
+	    })
+}
@@ -0,0 +1,169 @@
+//! Machine-readable progress: one JSON event per line for `--progress-json`,
+//! streams of events of the process, see [`subscribe`], or of one converter, see [`scope`],
+//! and periodic progress lines instead of bars for `--progress json`.
+
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::error::Error;
+
+
+/// Events waiting for a subscriber, later ones are dropped until it catches up.
+const BUFFER: usize = 1024;
+
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+static SUBSCRIBERS: Subscribers = Subscribers::new();
+
+tokio::task_local! {
+	static SCOPE: Arc<Subscribers>;
+}
+
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+	/// Batch started.
+	Start { archives: usize },
+	ArchiveStart { path: PathBuf, pages: usize },
+	/// Page written to the output.
+	Page {
+		archive: PathBuf,
+		name: String,
+		output: String,
+		source_size: usize,
+		size: usize,
+		done: usize,
+		total: usize,
+	},
+	ArchiveDone { path: PathBuf, output: PathBuf, size: u64 },
+	ArchiveFailed { path: PathBuf, error: String },
+	/// Batch finished.
+	Finish { completed: usize, failed: usize },
+}
+
+
+/// Start writing events to the file, `-` means stdout.
+pub fn init(path: &Path) -> Result<(), Error> {
+	let sink: Box<dyn Write + Send> = if path == Path::new("-") {
+		Box::new(std::io::stdout())
+	} else {
+		Box::new(std::fs::OpenOptions::new().create(true).append(true).open(path)?)
+	};
+	SINK.set(Mutex::new(sink))
+	    .map_err(|_| Error::from("progress output is already set".to_owned()))
+}
+
+
+/// Streams of events, of the process or of one converter.
+#[derive(Debug, Default)]
+pub struct Subscribers(Mutex<Vec<mpsc::Sender<Event>>>);
+
+
+impl Subscribers {
+	pub const fn new() -> Self { Self(Mutex::new(Vec::new())) }
+
+
+	/// Events from now on, the stream ends when the subscribers are dropped.
+	pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+		let (sender, receiver) = mpsc::channel(BUFFER);
+		self.0.lock().unwrap().push(sender);
+		receiver
+	}
+
+
+	fn send(&self, event: &Event) {
+		// dropped streams are unsubscribed, full ones miss the event:
+		self.0
+		    .lock()
+		    .unwrap()
+		    .retain_mut(|subscriber| !subscriber.try_send(event.clone()).is_err_and(|err| err.is_disconnected()));
+	}
+}
+
+
+/// Events of all conversions in the process from now on, until the stream is dropped.
+pub fn subscribe() -> mpsc::Receiver<Event> { SUBSCRIBERS.subscribe() }
+
+
+/// Run the future with its events also sent to `subscribers`.
+pub fn scope<F: Future>(subscribers: Arc<Subscribers>, future: F) -> impl Future<Output = F::Output> {
+	SCOPE.scope(subscribers, future)
+}
+
+
+/// Send event to subscribers, and write it if `--progress-json` is set.
+pub fn emit(event: Event) {
+	SUBSCRIBERS.send(&event);
+	SCOPE.try_with(|subscribers| subscribers.send(&event)).ok();
+	let Some(sink) = SINK.get() else {
+		return;
+	};
+	let mut line = match serde_json::to_vec(&event) {
+		Ok(line) => line,
+		Err(err) => return error!("{err}"),
+	};
+	line.push(b'\n');
+
+	let mut sink = sink.lock().unwrap();
+	if let Err(err) = sink.write_all(&line).and_then(|_| sink.flush()) {
+		debug!("unable to write progress: {err}");
+	}
+}
+
+
+/// Print progress of archives in progress as JSON lines every `period`, until events end.
+/// Archives without new pages since the last line are not repeated.
+pub async fn print_lines(period: Duration) {
+	#[derive(Serialize)]
+	struct Line<'a> {
+		archive: &'a Path,
+		done: usize,
+		total: usize,
+	}
+	let print = |archive: &Path, done: usize, total: usize| {
+		match serde_json::to_string(&Line { archive, done, total }) {
+			Ok(line) => println!("{line}"),
+			Err(err) => error!("{err}"),
+		}
+	};
+
+	let mut events = subscribe();
+	// done and total pages, and whether it changed since the last line:
+	let mut archives = std::collections::BTreeMap::<PathBuf, (usize, usize, bool)>::new();
+	let mut ticks = tokio::time::interval(period);
+	loop {
+		tokio::select! {
+			event = events.next() => match event {
+				Some(Event::ArchiveStart { path, pages }) => {
+					archives.insert(path, (0, pages, true));
+				},
+				Some(Event::Page { archive, done, total, .. }) => {
+					archives.insert(archive, (done, total, true));
+				},
+				Some(Event::ArchiveDone { path, .. }) => {
+					if let Some((_, total, _)) = archives.remove(&path) {
+						print(&path, total, total);
+					}
+				},
+				Some(Event::ArchiveFailed { path, .. }) => {
+					archives.remove(&path);
+				},
+				Some(_) => {},
+				None => return,
+			},
+			_ = ticks.tick() => {
+				for (archive, (done, total, changed)) in archives.iter_mut().filter(|(_, (_, _, changed))| *changed) {
+					print(archive, *done, *total);
+					*changed = false;
+				}
+			},
+		}
+	}
+}
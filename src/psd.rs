@@ -0,0 +1,155 @@
+//! Minimal PSD/PSB reader for the flattened composite image,
+//! which is stored at the end of the file when saved with "maximize compatibility".
+
+use image::{DynamicImage, ImageBuffer, ImageError, ImageResult};
+use image::error::{DecodingError, ImageFormatHint};
+
+
+const SIGNATURE: &[u8] = b"8BPS";
+
+const MODE_GRAYSCALE: u16 = 1;
+const MODE_RGB: u16 = 3;
+const MODE_CMYK: u16 = 4;
+
+
+/// Decode merged (flattened) image data of PSD or PSB file.
+/// Supports 8 and 16 bit grayscale, RGB and CMYK.
+pub fn decode(data: &[u8]) -> ImageResult<DynamicImage> {
+	let mut r = Reader { data, pos: 0 };
+
+	if r.take(4)? != SIGNATURE {
+		return Err(error("not a PSD file"));
+	}
+	let version = r.u16()?;
+	let big = match version {
+		1 => false,
+		2 => true,
+		v => return Err(error(format!("unknown version {v}"))),
+	};
+	r.take(6)?;
+	let channels = r.u16()? as usize;
+	let height = r.u32()?;
+	let width = r.u32()?;
+	let depth = r.u16()?;
+	let mode = r.u16()?;
+	trace!("psd: {width}x{height}, {channels} channels, depth: {depth}, mode: {mode}");
+
+	if depth != 8 && depth != 16 {
+		return Err(error(format!("unsupported depth {depth}")));
+	}
+
+	// color mode data, image resources:
+	for _ in 0..2 {
+		let len = r.u32()? as usize;
+		r.take(len)?;
+	}
+	// layer and mask information:
+	let len = if big { r.u64()? as usize } else { r.u32()? as usize };
+	r.take(len)?;
+
+	let compression = r.u16()?;
+	let (w, h) = (width as usize, height as usize);
+	let bytes_per_sample = depth as usize / 8;
+	let row_len = w * bytes_per_sample;
+
+	// planar channels, each row_len * h bytes:
+	let planes = match compression {
+		0 => r.take(row_len * h * channels)?.to_vec(),
+		1 => {
+			let mut counts = Vec::with_capacity(channels * h);
+			for _ in 0..channels * h {
+				counts.push(if big { r.u32()? as usize } else { r.u16()? as usize });
+			}
+			let mut planes = Vec::with_capacity(row_len * h * channels);
+			for count in counts {
+				let start = planes.len();
+				unpack_bits(r.take(count)?, &mut planes);
+				planes.resize(start + row_len, 0);
+			}
+			planes
+		},
+		c => return Err(error(format!("unsupported compression {c}"))),
+	};
+
+	// take high byte of 16-bit samples:
+	let sample = |channel: usize, i: usize| planes[channel * row_len * h + i * bytes_per_sample];
+	let pixels = w * h;
+
+	let image = match (mode, channels) {
+		(MODE_GRAYSCALE, 1) => {
+			let buf = (0..pixels).map(|i| sample(0, i)).collect();
+			ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLuma8)
+		},
+		(MODE_GRAYSCALE, _) => {
+			let buf = (0..pixels).flat_map(|i| [sample(0, i), sample(1, i)]).collect();
+			ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageLumaA8)
+		},
+		(MODE_RGB, 3) => {
+			let buf = (0..pixels).flat_map(|i| [sample(0, i), sample(1, i), sample(2, i)])
+			                     .collect();
+			ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)
+		},
+		(MODE_RGB, c) if c > 3 => {
+			let buf = (0..pixels).flat_map(|i| [sample(0, i), sample(1, i), sample(2, i), sample(3, i)])
+			                     .collect();
+			ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgba8)
+		},
+		(MODE_CMYK, c) if c >= 4 => {
+			// PSD stores CMYK inverted, naive conversion without color profile:
+			let buf = (0..pixels).flat_map(|i| {
+				                     let k = sample(3, i) as u16;
+				                     [0, 1, 2].map(|c| (sample(c, i) as u16 * k / 255) as u8)
+			                     })
+			                     .collect();
+			ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)
+		},
+		_ => return Err(error(format!("unsupported color mode {mode} with {channels} channels"))),
+	};
+
+	image.ok_or_else(|| error("invalid image data"))
+}
+
+
+/// PackBits RLE.
+fn unpack_bits(mut src: &[u8], dst: &mut Vec<u8>) {
+	while let Some((&n, rest)) = src.split_first() {
+		let n = n as i8;
+		src = rest;
+		if n >= 0 {
+			let len = (n as usize + 1).min(src.len());
+			dst.extend_from_slice(&src[..len]);
+			src = &src[len..];
+		} else if n != -128 {
+			if let Some((&value, rest)) = src.split_first() {
+				dst.resize(dst.len() + (1 - n as isize) as usize, value);
+				src = rest;
+			}
+		}
+	}
+}
+
+
+fn error(msg: impl Into<String>) -> ImageError {
+	let msg: String = msg.into();
+	ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("PSD".to_owned()), msg))
+}
+
+
+struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> ImageResult<&'a [u8]> {
+		let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+		let end = end.ok_or_else(|| error("unexpected end of file"))?;
+		let slice = &self.data[self.pos..end];
+		self.pos = end;
+		Ok(slice)
+	}
+
+	fn u16(&mut self) -> ImageResult<u16> { Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap())) }
+	fn u32(&mut self) -> ImageResult<u32> { Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap())) }
+	fn u64(&mut self) -> ImageResult<u64> { Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap())) }
+}
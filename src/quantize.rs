@@ -0,0 +1,95 @@
+//! Grayscale quantization for e-ink with `--bit-depth`.
+//!
+//! E-ink readers show 16 gray levels at most, so pages with fewer levels lose nothing visible
+//! and compress much better, especially to lossless formats.
+
+use image::{DynamicImage, GrayImage};
+
+
+/// Quantize to `2^bits` gray levels spread evenly over the range, alpha is kept.
+/// Dithering spreads the error with Floyd-Steinberg, which keeps gradients at low depths.
+pub fn quantize(image: DynamicImage, bits: u8, dither: bool) -> DynamicImage {
+	let levels = ((1u32 << bits) - 1) as f32;
+	let level = |value: f32| (value.clamp(0.0, 255.0) * levels / 255.0).round() * 255.0 / levels;
+	trace!("quantizing {}x{} to {bits} bits", image.width(), image.height());
+
+	let (mut gray, alpha) = match image {
+		DynamicImage::ImageLuma8(gray) => (gray, None),
+		image if image.color().has_alpha() => {
+			let alpha = image.to_luma_alpha8();
+			(image.to_luma8(), Some(alpha))
+		},
+		image => (image.to_luma8(), None),
+	};
+
+	let (width, height) = (gray.width() as usize, gray.height() as usize);
+	if dither {
+		// errors of the current and the next row:
+		let mut errors = vec![0.0f32; width * 2 + 2];
+		let data: &mut [u8] = &mut gray;
+		for y in 0..height {
+			let (current, next) = errors.split_at_mut(width + 1);
+			next.fill(0.0);
+			for x in 0..width {
+				let value = &mut data[y * width + x];
+				let wanted = *value as f32 + current[x];
+				let quantized = level(wanted);
+				*value = quantized as u8;
+				let error = wanted - quantized;
+				current[x + 1] += error * 7.0 / 16.0;
+				if x > 0 {
+					next[x - 1] += error * 3.0 / 16.0;
+				}
+				next[x] += error * 5.0 / 16.0;
+				next[x + 1] += error / 16.0;
+			}
+			errors.copy_within(width + 1.., 0);
+		}
+	} else {
+		let lut: Vec<u8> = (0..=255).map(|value| level(value as f32) as u8).collect();
+		gray.iter_mut().for_each(|value| *value = lut[*value as usize]);
+	}
+
+	match alpha {
+		Some(mut alpha) => {
+			alpha.pixels_mut()
+			     .zip(gray.pixels())
+			     .for_each(|(pixel, gray)| pixel[0] = gray[0]);
+			DynamicImage::ImageLumaA8(alpha)
+		},
+		None => DynamicImage::ImageLuma8(gray),
+	}
+}
+
+
+/// PNG with the real low bit depth, which `image` can't write.
+pub fn encode_png(image: &GrayImage, bits: u8) -> Result<Vec<u8>, png::EncodingError> {
+	let depth = match bits {
+		1 => png::BitDepth::One,
+		2 => png::BitDepth::Two,
+		4 => png::BitDepth::Four,
+		_ => png::BitDepth::Eight,
+	};
+	let shift = 8 - bits;
+	let per_byte = 8 / bits as usize;
+	let width = image.width() as usize;
+
+	let mut data = Vec::with_capacity(width.div_ceil(per_byte) * image.height() as usize);
+	for row in image.rows() {
+		let values: Vec<u8> = row.map(|pixel| pixel[0] >> shift).collect();
+		for chunk in values.chunks(per_byte) {
+			let byte = chunk.iter()
+			                .enumerate()
+			                .fold(0u8, |byte, (i, value)| byte | value << (8 - bits as usize * (i + 1)));
+			data.push(byte);
+		}
+	}
+
+	let mut output = Vec::new();
+	let mut encoder = png::Encoder::new(&mut output, image.width(), image.height());
+	encoder.set_color(png::ColorType::Grayscale);
+	encoder.set_depth(depth);
+	encoder.set_compression(png::Compression::Best);
+	encoder.write_header()?.write_image_data(&data)?;
+	Ok(output)
+}
@@ -0,0 +1,111 @@
+//! Removal of sources after successful conversion, for `--delete-source` and `--trash-source`.
+//!
+//! Trash is the freedesktop.org home trash on Linux and BSD, `~/.Trash` on macOS.
+//! Sources on other filesystems than the trash are not moved and kept.
+
+use std::path::Path;
+
+use crate::error::Error;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+	Delete,
+	Trash,
+}
+
+
+/// Remove the source, unless it is the output itself.
+pub async fn remove_source(source: &Path, output: &Path, removal: Removal) -> Result<(), Error> {
+	let same = match (tokio::fs::canonicalize(source).await, tokio::fs::canonicalize(output).await) {
+		(Ok(source), Ok(output)) => source == output,
+		_ => false,
+	};
+	if same {
+		warn!("Not removing '{}', it was replaced by the output", source.display());
+		return Ok(());
+	}
+	match removal {
+		Removal::Delete => tokio::fs::remove_file(source).await?,
+		Removal::Trash => {
+			let source = source.to_owned();
+			tokio::task::spawn_blocking(move || trash(&source)).await
+			                                                   .map_err(|_| "trash task panicked".to_owned())??
+		},
+	}
+	info!("Removed source ({removal:?}): {}", source.display());
+	Ok(())
+}
+
+
+/// First free name for the file in the directory: `name`, `name.2`, `name.3` and so on.
+fn free_name(dir: &Path, name: &str, taken: impl Fn(&str) -> bool) -> String {
+	(1..).map(|n| if n == 1 { name.to_owned() } else { format!("{name}.{n}") })
+	     .find(|name| !dir.join(name).exists() && !taken(name))
+	     .expect("free name")
+}
+
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash(path: &Path) -> Result<(), Error> {
+	use std::io::Write;
+
+	let path = std::fs::canonicalize(path)?;
+	let home_data = || std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share"));
+	let data = std::env::var_os("XDG_DATA_HOME").map(std::path::PathBuf::from)
+	                                            .or_else(home_data)
+	                                            .ok_or_else(|| "Unable to locate trash: no $HOME".to_owned())?;
+	let files = data.join("Trash/files");
+	let info = data.join("Trash/info");
+	std::fs::create_dir_all(&files)?;
+	std::fs::create_dir_all(&info)?;
+
+	let name = path.file_name().unwrap_or_default().to_string_lossy();
+	let name = free_name(&files, &name, |name| info.join(format!("{name}.trashinfo")).exists());
+	// info file is created first, as the spec requires, and removed if the move fails:
+	let info = info.join(format!("{name}.trashinfo"));
+	let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(&info)?;
+	let date = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S");
+	write!(file, "[Trash Info]\nPath={}\nDeletionDate={date}\n", percent_encoded(&path))?;
+	drop(file);
+
+	if let Err(err) = std::fs::rename(&path, files.join(&name)) {
+		std::fs::remove_file(&info).ok();
+		return Err(format!("Unable to move '{}' to trash: {err}", path.display()).into());
+	}
+	Ok(())
+}
+
+
+#[cfg(target_os = "macos")]
+fn trash(path: &Path) -> Result<(), Error> {
+	let home = std::env::var_os("HOME").ok_or_else(|| "Unable to locate trash: no $HOME".to_owned())?;
+	let dir = Path::new(&home).join(".Trash");
+	let name = path.file_name().unwrap_or_default().to_string_lossy();
+	let name = free_name(&dir, &name, |_| false);
+	std::fs::rename(path, dir.join(name)).map_err(|err| format!("Unable to move '{}' to trash: {err}", path.display()))?;
+	Ok(())
+}
+
+
+#[cfg(not(unix))]
+fn trash(path: &Path) -> Result<(), Error> {
+	Err(format!("Trash is not supported on this platform, '{}' is kept", path.display()).into())
+}
+
+
+/// Path for `.trashinfo`, bytes other than unreserved ones and `/` are percent-encoded.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn percent_encoded(path: &Path) -> String {
+	use std::os::unix::ffi::OsStrExt;
+	path.as_os_str()
+	    .as_bytes()
+	    .iter()
+	    .map(|b| {
+		    match b {
+			    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (*b as char).to_string(),
+			    b => format!("%{b:02X}"),
+		    }
+	    })
+	    .collect()
+}
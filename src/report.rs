@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::failures::Failure;
+use crate::similar::Origin;
+
+
+/// Page is an outlier when it is this many times bigger than the archive's median.
+const OUTLIER_FACTOR: u64 = 10;
+
+
+/// JSON report of the whole run.
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+	pub archives: Vec<ArchiveReport>,
+	pub failed: Vec<Failure>,
+}
+
+impl Report {
+	pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+		let path = path.as_ref();
+		debug!("writing report to '{}'", path.display());
+		let data = serde_json::to_vec_pretty(self)?;
+		tokio::fs::write(path, data).await?;
+		Ok(())
+	}
+}
+
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PageStats {
+	pub name: String,
+	/// Name in the output archive.
+	pub output_name: String,
+	pub source_size: usize,
+	pub output_size: usize,
+	/// Source image resolution, if it is an image.
+	pub dimensions: Option<(u32, u32)>,
+	/// Similar page whose encode was reused, for `--reuse-similar`.
+	pub similar_to: Option<Origin>,
+}
+
+
+/// Page which failed to convert or to be written, it is missing in the output.
+#[derive(Serialize, Debug, Clone)]
+pub struct PageFailure {
+	pub name: String,
+	pub error: String,
+}
+
+
+#[derive(Serialize, Debug)]
+pub struct ArchiveReport {
+	pub source: PathBuf,
+	pub output: PathBuf,
+	pub source_size: u64,
+	pub output_size: u64,
+	pub pages: Vec<PageStats>,
+	pub failed_pages: Vec<PageFailure>,
+	/// Source page sizes in bytes.
+	pub size_histogram: Vec<Bucket>,
+	/// Source page resolutions in pixels (width × height).
+	pub resolution_histogram: Vec<Bucket>,
+	pub outliers: Vec<Outlier>,
+	/// Pages with orientation different from the most of pages.
+	pub mixed_orientation: Vec<String>,
+	/// Pages similar to pages of other archives.
+	pub cross_archive_duplicates: usize,
+}
+
+impl ArchiveReport {
+	pub fn new(source: PathBuf,
+	           output: PathBuf,
+	           source_size: u64,
+	           output_size: u64,
+	           pages: Vec<PageStats>,
+	           failed_pages: Vec<PageFailure>)
+	           -> Self {
+		let sizes: Vec<u64> = pages.iter().map(|p| p.source_size as u64).collect();
+		let pixels: Vec<u64> = pages.iter()
+		                            .filter_map(|p| p.dimensions)
+		                            .map(|(w, h)| w as u64 * h as u64)
+		                            .collect();
+		let outliers = outliers(&pages);
+		let mixed_orientation = orientation_minority(pages.iter().filter_map(|p| Some((p.name.clone(), p.dimensions?))));
+		if !outliers.is_empty() {
+			warn!("{} has {} outlier pages", source.display(), outliers.len());
+		}
+		let cross_archive_duplicates = pages.iter()
+		                                    .filter_map(|p| p.similar_to.as_ref())
+		                                    .filter(|origin| origin.archive != source)
+		                                    .count();
+
+		Self { source,
+		       output,
+		       source_size,
+		       output_size,
+		       size_histogram: histogram(&sizes),
+		       resolution_histogram: histogram(&pixels),
+		       outliers,
+		       mixed_orientation,
+		       cross_archive_duplicates,
+		       pages,
+		       failed_pages }
+	}
+}
+
+
+/// Histogram bucket `[from, to)`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+	pub from: u64,
+	pub to: u64,
+	pub count: usize,
+}
+
+/// Power-of-two buckets, only non-empty ones.
+pub fn histogram(values: &[u64]) -> Vec<Bucket> {
+	let mut buckets: Vec<Bucket> = Vec::new();
+	let mut values = values.to_vec();
+	values.sort_unstable();
+	for value in values {
+		let from = if value == 0 { 0 } else { 1 << value.ilog2() };
+		let to = from.max(1) * 2;
+		match buckets.last_mut() {
+			Some(bucket) if bucket.from == from => bucket.count += 1,
+			_ => buckets.push(Bucket { from, to, count: 1 }),
+		}
+	}
+	buckets
+}
+
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Outlier {
+	pub name: String,
+	pub reason: String,
+}
+
+/// Pages much bigger than median by size or resolution.
+/// Usually that's accidentally embedded PSD/TIFF.
+pub fn outliers(pages: &[PageStats]) -> Vec<Outlier> {
+	fn median(mut values: Vec<u64>) -> Option<u64> {
+		values.sort_unstable();
+		values.get(values.len() / 2).copied()
+	}
+
+	let size_median = median(pages.iter().map(|p| p.source_size as u64).collect());
+	let pixels_median = median(pages.iter()
+	                                .filter_map(|p| p.dimensions)
+	                                .map(|(w, h)| w as u64 * h as u64)
+	                                .collect());
+
+	let mut result = Vec::new();
+	for page in pages {
+		let size = page.source_size as u64;
+		if let Some(median) = size_median.filter(|m| *m > 0 && size > m * OUTLIER_FACTOR) {
+			result.push(Outlier { name: page.name.clone(),
+			                      reason: format!("size {size}b is {}× the median {median}b", size / median) });
+		}
+
+		if let (Some(median), Some((w, h))) = (pixels_median.filter(|m| *m > 0), page.dimensions) {
+			let pixels = w as u64 * h as u64;
+			if pixels > median * OUTLIER_FACTOR {
+				result.push(Outlier { name: page.name.clone(),
+				                      reason: format!("resolution {w}x{h} is {}× the median", pixels / median) });
+			}
+		}
+	}
+	result
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Orientation {
+	Portrait,
+	Landscape,
+}
+
+impl Orientation {
+	/// `None` for square.
+	pub fn of(width: u32, height: u32) -> Option<Self> {
+		match width.cmp(&height) {
+			std::cmp::Ordering::Less => Some(Self::Portrait),
+			std::cmp::Ordering::Greater => Some(Self::Landscape),
+			std::cmp::Ordering::Equal => None,
+		}
+	}
+}
+
+/// Pages with orientation of the minority, empty if all pages have the same orientation.
+/// Square pages are ignored. On a tie portrait is considered the majority.
+pub fn orientation_minority<T>(pages: impl IntoIterator<Item = (T, (u32, u32))>) -> Vec<T> {
+	let (mut portrait, mut landscape) = (Vec::new(), Vec::new());
+	for (page, (w, h)) in pages {
+		match Orientation::of(w, h) {
+			Some(Orientation::Portrait) => portrait.push(page),
+			Some(Orientation::Landscape) => landscape.push(page),
+			None => {},
+		}
+	}
+	if landscape.len() > portrait.len() {
+		portrait
+	} else {
+		landscape
+	}
+}
@@ -0,0 +1,214 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageBuffer, ImageFormat, ImageError, ImageResult};
+use image::error::{DecodingError, ImageFormatHint};
+use image::imageops::FilterType;
+
+use crate::cli::Config;
+
+
+/// Decode page and downscale it to fit `max-width`/`max-height` if requested.
+///
+/// Pages bigger than `stream-threshold` megapixels are scaled while decoding,
+/// so the full-size bitmap is never materialized (JPEG and non-interlaced PNG).
+pub fn load(data: &[u8], format: Option<ImageFormat>, cfg: &Config) -> ImageResult<DynamicImage> {
+	let size = dimensions(data, format);
+	let target = size.and_then(|(w, h)| fit_within(w, h, cfg.max_width, cfg.max_height));
+
+	if let (Some(format), Some((w, h)), Some((tw, th))) = (format, size, target) {
+		if w as u64 * h as u64 > cfg.stream_threshold as u64 * 1_000_000 {
+			debug!("streaming decode: {w}x{h} -> {tw}x{th} ({format:?})");
+			return decode_scaled(data, format, tw, th);
+		}
+	}
+
+	let image = if let Some(format) = format {
+		image::load_from_memory_with_format(data, format)?
+	} else {
+		image::load_from_memory(data)?
+	};
+
+	Ok(fit(image, cfg))
+}
+
+
+/// Downscale already decoded image to fit `max-width`/`max-height` if requested.
+pub fn fit(image: DynamicImage, cfg: &Config) -> DynamicImage {
+	if let Some((tw, th)) = fit_within(image.width(), image.height(), cfg.max_width, cfg.max_height) {
+		trace!("resizing: {}x{} -> {tw}x{th}", image.width(), image.height());
+		image.resize_exact(tw, th, FilterType::Lanczos3)
+	} else {
+		image
+	}
+}
+
+
+/// Size that fits into the given bounds keeping aspect ratio,
+/// or `None` if no downscaling needed.
+pub fn fit_within(width: u32, height: u32, max_width: Option<u32>, max_height: Option<u32>) -> Option<(u32, u32)> {
+	let rw = max_width.map(|m| m as f64 / width as f64).unwrap_or(1.0);
+	let rh = max_height.map(|m| m as f64 / height as f64).unwrap_or(1.0);
+	let ratio = rw.min(rh);
+	if ratio >= 1.0 {
+		None
+	} else {
+		let w = ((width as f64 * ratio).round() as u32).max(1);
+		let h = ((height as f64 * ratio).round() as u32).max(1);
+		Some((w, h))
+	}
+}
+
+
+/// Read dimensions from the image header only.
+pub fn dimensions(data: &[u8], format: Option<ImageFormat>) -> Option<(u32, u32)> {
+	let mut reader = image::io::Reader::new(Cursor::new(data));
+	if let Some(format) = format {
+		reader.set_format(format);
+	} else {
+		reader = reader.with_guessed_format().ok()?;
+	}
+	reader.into_dimensions().ok()
+}
+
+
+/// Decode and scale to exactly `width`x`height` using the cheapest path the codec allows.
+pub fn decode_scaled(data: &[u8], format: ImageFormat, width: u32, height: u32) -> ImageResult<DynamicImage> {
+	let image = match format {
+		ImageFormat::Jpeg => {
+			use image::codecs::jpeg::JpegDecoder;
+			let mut decoder = JpegDecoder::new(Cursor::new(data))?;
+			// DCT scaling by 1/2, 1/4 or 1/8, result is never smaller than requested:
+			decoder.scale(
+			              width.min(u16::MAX as _) as u16,
+			              height.min(u16::MAX as _) as u16,
+			)?;
+			DynamicImage::from_decoder(decoder)?
+		},
+		ImageFormat::Png => {
+			match decode_png_scaled(data, width, height)? {
+				Some(image) => image,
+				None => image::load_from_memory_with_format(data, format)?,
+			}
+		},
+		_ => {
+			warn!("streaming decode is not supported for {format:?}, decoding whole image");
+			image::load_from_memory_with_format(data, format)?
+		},
+	};
+
+	if image.width() != width || image.height() != height {
+		Ok(image.resize_exact(width, height, FilterType::Lanczos3))
+	} else {
+		Ok(image)
+	}
+}
+
+
+/// Row-by-row PNG decoding with box-filter downscaling.
+/// Returns `None` for interlaced images which can't be streamed.
+fn decode_png_scaled(data: &[u8], width: u32, height: u32) -> ImageResult<Option<DynamicImage>> {
+	use png::{ColorType, Transformations};
+
+	let err = |err: png::DecodingError| {
+		ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Png), err))
+	};
+
+	let mut decoder = png::Decoder::new(Cursor::new(data));
+	decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+	let mut reader = decoder.read_info().map_err(err)?;
+	let info = reader.info();
+	if info.interlaced {
+		return Ok(None);
+	}
+	let (src_width, src_height) = (info.width, info.height);
+	let (color, _) = reader.output_color_type();
+
+	let mut scaler = RowScaler::new(src_width, src_height, width, height, color.samples());
+	while let Some(row) = reader.next_row().map_err(err)? {
+		scaler.push(row.data());
+	}
+	let pixels = scaler.finish();
+
+	let image = match color {
+		ColorType::Grayscale => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+		ColorType::GrayscaleAlpha => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+		ColorType::Rgb => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+		ColorType::Rgba => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+		ColorType::Indexed => None,
+	};
+	Ok(image)
+}
+
+
+/// Box-filter downscaler fed one source row at a time.
+/// Keeps only one accumulated target row in addition to the output.
+struct RowScaler {
+	src_width: usize,
+	src_height: usize,
+	width: usize,
+	height: usize,
+	channels: usize,
+
+	/// Current source row
+	y: usize,
+	/// Current target row
+	row: usize,
+	acc: Vec<u64>,
+	counts: Vec<u64>,
+	output: Vec<u8>,
+}
+
+impl RowScaler {
+	fn new(src_width: u32, src_height: u32, width: u32, height: u32, channels: usize) -> Self {
+		let (width, height) = (width as usize, height as usize);
+		Self { src_width: src_width as _,
+		       src_height: src_height as _,
+		       width,
+		       height,
+		       channels,
+		       y: 0,
+		       row: 0,
+		       acc: vec![0; width * channels],
+		       counts: vec![0; width],
+		       output: Vec::with_capacity(width * height * channels) }
+	}
+
+	fn push(&mut self, row: &[u8]) {
+		let target = self.y * self.height / self.src_height;
+		if target != self.row {
+			self.flush();
+			self.row = target;
+		}
+
+		let ch = self.channels;
+		for x in 0..self.src_width {
+			let tx = x * self.width / self.src_width;
+			for c in 0..ch {
+				self.acc[tx * ch + c] += row[x * ch + c] as u64;
+			}
+			self.counts[tx] += 1;
+		}
+		self.y += 1;
+	}
+
+	fn flush(&mut self) {
+		if self.counts.iter().all(|n| *n == 0) {
+			return;
+		}
+		let ch = self.channels;
+		for (tx, count) in self.counts.iter_mut().enumerate() {
+			let n = (*count).max(1);
+			for c in 0..ch {
+				let sum = &mut self.acc[tx * ch + c];
+				self.output.push(((*sum + n / 2) / n) as u8);
+				*sum = 0;
+			}
+			*count = 0;
+		}
+	}
+
+	fn finish(mut self) -> Vec<u8> {
+		self.flush();
+		self.output
+	}
+}
@@ -0,0 +1,327 @@
+//! `serve` command: conversion jobs submitted over a Unix socket.
+//!
+//! Requests and responses are JSON objects, one per line:
+//!
+//! ```text
+//! {"id": 1, "method": "submit", "params": {"path": "/comics/Series v01.cbr"}}
+//! {"id": 1, "result": {"job": 1}}
+//! {"id": 2, "method": "status", "params": {"job": 1}}
+//! {"id": 2, "result": {"job": 1, "path": "/comics/Series v01.cbr", "state": "running", "done": 12, "total": 180}}
+//! {"id": 3, "method": "cancel", "params": {"job": 1}}
+//! {"id": 3, "result": {"job": 1, "path": "/comics/Series v01.cbr", "state": "cancelled"}}
+//! ```
+//!
+//! `status` without a job lists all jobs. Errors are returned as `{"id": 1, "error": "..."}`.
+//! The same is available over HTTP with `--http`.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+use crate::cli::ServeArgs;
+use crate::converter::Converter;
+use crate::error::Error;
+use crate::progress::{self, Event};
+
+
+mod http;
+
+
+#[derive(Deserialize, Debug)]
+struct Request {
+	#[serde(default)]
+	id: Value,
+	method: String,
+	#[serde(default)]
+	params: Params,
+}
+
+
+#[derive(Deserialize, Debug, Default)]
+struct Params {
+	path: Option<PathBuf>,
+	job: Option<usize>,
+}
+
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum State {
+	Queued,
+	/// Pages written of all pages.
+	Running { done: usize, total: usize },
+	Done { output: PathBuf, source_size: u64, output_size: u64 },
+	Failed { error: String },
+	Cancelled,
+}
+
+
+#[derive(Serialize, Debug, Clone)]
+struct Status {
+	job: usize,
+	path: PathBuf,
+	#[serde(flatten)]
+	state: State,
+}
+
+
+struct Job {
+	status: Status,
+	cancel: CancellationToken,
+}
+
+
+struct Server {
+	converter: Converter,
+	/// Jobs by number starting from 1.
+	jobs: Mutex<Vec<Job>>,
+	/// Archives converted at once.
+	slots: Semaphore,
+}
+
+
+/// Removes the socket file when the server stops.
+struct Socket(PathBuf);
+
+
+impl Drop for Socket {
+	fn drop(&mut self) { std::fs::remove_file(&self.0).ok(); }
+}
+
+
+pub async fn run(args: &ServeArgs) -> Result<(), Error> {
+	let output_dir = match &args.output {
+		Some(dir) => dir.clone(),
+		None => std::env::current_dir()?,
+	};
+	let config = args.config.clone();
+	let converter = Converter::builder().output_dir(output_dir)
+	                                    .configure(|cfg| *cfg = config)
+	                                    .build()?;
+	let server = Arc::new(Server { converter,
+	                               jobs: Mutex::new(Vec::new()),
+	                               slots: Semaphore::new(args.jobs_fs.max(1)) });
+
+	let http = match args.http {
+		Some(addr) => {
+			let listener = tokio::net::TcpListener::bind(addr).await?;
+			info!("Listening on http://{addr}");
+			Some(http::serve(listener, server.clone()))
+		},
+		None => None,
+	};
+	let socket = match &args.socket {
+		Some(path) => Some(bind(path).await?),
+		None => None,
+	};
+
+	tokio::spawn(track_progress(server.clone()));
+	let http = async {
+		match http {
+			Some(http) => http.await,
+			None => std::future::pending().await,
+		}
+	};
+	let socket = async {
+		match socket {
+			Some((listener, _socket)) => serve_socket(listener, server.clone()).await,
+			None => std::future::pending().await,
+		}
+	};
+	tokio::select! {
+		res = http => res,
+		res = socket => res,
+	}
+}
+
+
+async fn bind(path: &Path) -> Result<(UnixListener, Socket), Error> {
+	if path.exists() {
+		if UnixStream::connect(path).await.is_ok() {
+			return Err(format!("Another server is listening on '{}'", path.display()).into());
+		}
+		std::fs::remove_file(path)?;
+	}
+	let listener = UnixListener::bind(path)?;
+	info!("Listening on '{}'", path.display());
+	Ok((listener, Socket(path.to_owned())))
+}
+
+
+async fn serve_socket(listener: UnixListener, server: Arc<Server>) -> Result<(), Error> {
+	loop {
+		let (stream, _) = listener.accept().await?;
+		let server = server.clone();
+		tokio::spawn(async move {
+			if let Err(err) = serve_client(stream, server).await {
+				debug!("client: {err}");
+			}
+		});
+	}
+}
+
+
+async fn serve_client(stream: UnixStream, server: Arc<Server>) -> Result<(), Error> {
+	let (reader, mut writer) = stream.into_split();
+	let mut lines = BufReader::new(reader).lines();
+	while let Some(line) = lines.next_line().await? {
+		if line.trim().is_empty() {
+			continue;
+		}
+		let response = match serde_json::from_str::<Request>(&line) {
+			Ok(request) => {
+				trace!("request: {request:?}");
+				match handle(&server, &request.method, request.params) {
+					Ok(result) => serde_json::json!({ "id": request.id, "result": result }),
+					Err(err) => serde_json::json!({ "id": request.id, "error": err.to_string() }),
+				}
+			},
+			Err(err) => serde_json::json!({ "id": Value::Null, "error": format!("Invalid request: {err}") }),
+		};
+		let mut response = serde_json::to_vec(&response)?;
+		response.push(b'\n');
+		writer.write_all(&response).await?;
+	}
+	Ok(())
+}
+
+
+fn handle(server: &Arc<Server>, method: &str, params: Params) -> Result<Value, Error> {
+	let job = || params.job.ok_or_else(|| Error::from("Missing job".to_owned()));
+	let value = match method {
+		"submit" => {
+			let path = params.path.ok_or_else(|| Error::from("Missing path".to_owned()))?;
+			serde_json::json!({ "job": submit(server, path, false)? })
+		},
+		"status" => {
+			let jobs = server.jobs.lock().unwrap();
+			match params.job {
+				Some(job) => serde_json::to_value(&find(&jobs, job)?.status)?,
+				None => serde_json::to_value(jobs.iter().map(|job| &job.status).collect::<Vec<_>>())?,
+			}
+		},
+		"cancel" => serde_json::to_value(cancel(server, job()?)?)?,
+		other => return Err(format!("Unknown method '{other}', expected submit, status or cancel").into()),
+	};
+	Ok(value)
+}
+
+
+fn find(jobs: &[Job], job: usize) -> Result<&Job, Error> {
+	job.checked_sub(1)
+	   .and_then(|index| jobs.get(index))
+	   .ok_or_else(|| format!("No job {job}").into())
+}
+
+
+/// Queue the archive, returns the job number.
+/// Uploaded archives are in folders of their own, which are removed after the job.
+fn submit(server: &Arc<Server>, path: PathBuf, uploaded: bool) -> Result<usize, Error> {
+	if !path.is_file() {
+		return Err(format!("No archive '{}'", path.display()).into());
+	}
+	let cancel = server.converter.cancellation_token();
+	let job = {
+		let mut jobs = server.jobs.lock().unwrap();
+		let job = jobs.len() + 1;
+		jobs.push(Job { status: Status { job,
+		                                 path: path.clone(),
+		                                 state: State::Queued },
+		                cancel: cancel.clone() });
+		job
+	};
+	info!("Job {job}: {}", path.display());
+
+	let server = server.clone();
+	tokio::spawn(async move {
+		let _slot = tokio::select! {
+			slot = server.slots.acquire() => slot,
+			// cancelled while queued:
+			_ = cancel.cancelled() => {
+				if uploaded {
+					http::remove_upload(&path).await;
+				}
+				return;
+			},
+		};
+		server.set_state(job, State::Running { done: 0, total: 0 });
+		let state = match server.converter.convert_cancellable(&path, cancel.clone()).await {
+			Ok(report) => {
+				State::Done { output: report.output,
+				              source_size: report.source_size,
+				              output_size: report.output_size }
+			},
+			Err(_) if cancel.is_cancelled() => State::Cancelled,
+			Err(err) => {
+				error!("Job {job}: {err}");
+				State::Failed { error: err.to_string() }
+			},
+		};
+		server.set_state(job, state);
+		if uploaded {
+			http::remove_upload(&path).await;
+		}
+	});
+	Ok(job)
+}
+
+
+fn cancel(server: &Server, job: usize) -> Result<Status, Error> {
+	let mut jobs = server.jobs.lock().unwrap();
+	find(&jobs, job)?;
+	let job = &mut jobs[job - 1];
+	match job.status.state {
+		State::Queued | State::Running { .. } => {
+			job.cancel.cancel();
+			// running conversions report it when their outputs are removed:
+			if matches!(job.status.state, State::Queued) {
+				job.status.state = State::Cancelled;
+			}
+			Ok(job.status.clone())
+		},
+		_ => Err(format!("Job {} is finished", job.status.job).into()),
+	}
+}
+
+
+impl Server {
+	fn set_state(&self, job: usize, state: State) { self.jobs.lock().unwrap()[job - 1].status.state = state; }
+
+
+	fn running(&self, path: &Path, update: impl FnOnce(&mut usize, &mut usize)) {
+		let mut jobs = self.jobs.lock().unwrap();
+		let running = jobs.iter_mut().find_map(|job| {
+			                             match &mut job.status.state {
+				                             State::Running { done, total } if job.status.path == path => Some((done, total)),
+				                             _ => None,
+			                             }
+		                             });
+		if let Some((done, total)) = running {
+			update(done, total);
+		}
+	}
+}
+
+
+/// Pages done of running jobs, from progress events.
+async fn track_progress(server: Arc<Server>) {
+	let mut events = progress::subscribe();
+	while let Some(event) = events.next().await {
+		match event {
+			Event::ArchiveStart { path, pages } => server.running(&path, |_, total| *total = pages),
+			Event::Page { archive, done, total, .. } => {
+				server.running(&archive, |job_done, job_total| {
+					      *job_done = done;
+					      *job_total = total;
+				      })
+			},
+			_ => {},
+		}
+	}
+}
@@ -0,0 +1,244 @@
+//! HTTP mode of `serve` with `--http`, one request per connection:
+//!
+//! - `POST /jobs` with `{"path": "..."}` converts an archive on the server,
+//!   with the archive as the body and `?name=Series v01.cbz` converts the uploaded one;
+//! - `GET /jobs` and `GET /jobs/{job}` return status, the same as `status` of the socket;
+//! - `DELETE /jobs/{job}` cancels the job;
+//! - `GET /jobs/{job}/output` downloads the converted archive;
+//! - `GET /events` streams progress events as server-sent events.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use futures::StreamExt;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{find, handle, submit, Params, Server, State};
+use crate::error::Error;
+use crate::progress;
+
+
+/// Status code and message of a failed request.
+type Response = Result<Value, (u16, String)>;
+
+
+pub async fn serve(listener: TcpListener, server: Arc<Server>) -> Result<(), Error> {
+	loop {
+		let (stream, addr) = listener.accept().await?;
+		let server = server.clone();
+		tokio::spawn(async move {
+			if let Err(err) = serve_client(stream, server).await {
+				debug!("http client {addr}: {err}");
+			}
+		});
+	}
+}
+
+
+async fn serve_client(stream: TcpStream, server: Arc<Server>) -> Result<(), Error> {
+	let mut stream = BufReader::new(stream);
+	let mut line = String::new();
+	stream.read_line(&mut line).await?;
+	let mut request = line.split_whitespace();
+	let method = request.next().unwrap_or_default().to_owned();
+	let target = request.next().unwrap_or_default().to_owned();
+
+	let (mut length, mut json) = (None, false);
+	loop {
+		let mut header = String::new();
+		if stream.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+			break;
+		}
+		if let Some((name, value)) = header.split_once(':') {
+			match name.trim().to_ascii_lowercase().as_str() {
+				"content-length" => length = value.trim().parse::<u64>().ok(),
+				"content-type" => json = value.trim().starts_with("application/json"),
+				_ => {},
+			}
+		}
+	}
+	trace!("http request: {method} {target}");
+
+	let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+	let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+	let job = |job: &str| job.parse::<usize>().map_err(|_| (404, format!("No job {job}")));
+	let response = match (method.as_str(), &segments[..]) {
+		("GET", ["events"]) => return events(stream.into_inner()).await,
+		("GET", ["jobs", number, "output"]) => {
+			match job(number).and_then(|job| output(&server, job)) {
+				Ok(output) => return download(stream.into_inner(), &output).await,
+				Err(err) => Err(err),
+			}
+		},
+		("GET", ["jobs"]) => call(&server, "status", Params::default()),
+		("GET", ["jobs", number]) => {
+			job(number).and_then(|job| {
+				           call(&server, "status", Params { job: Some(job),
+				                                            ..Default::default() })
+			           })
+		},
+		("DELETE", ["jobs", number]) => {
+			job(number).and_then(|job| {
+				           call(&server, "cancel", Params { job: Some(job),
+				                                            ..Default::default() })
+			           })
+		},
+		("POST", ["jobs"]) => {
+			match (length, json) {
+				(None, _) => Err((411, "Missing Content-Length".to_owned())),
+				(Some(length), true) => {
+					let mut body = Vec::new();
+					(&mut stream).take(length).read_to_end(&mut body).await?;
+					match serde_json::from_slice::<Params>(&body) {
+						Ok(params) => call(&server, "submit", params),
+						Err(err) => Err((400, format!("Invalid request: {err}"))),
+					}
+				},
+				(Some(length), false) => upload(&mut stream, &server, length, query).await,
+			}
+		},
+		_ => Err((404, format!("No {method} {path}"))),
+	};
+	respond(stream.get_mut(), response).await
+}
+
+
+fn call(server: &Arc<Server>, method: &str, params: Params) -> Response {
+	handle(server, method, params).map_err(|err| (400, err.to_string()))
+}
+
+
+/// Save the body as the archive named by the `name` parameter and submit it.
+async fn upload(stream: &mut BufReader<TcpStream>, server: &Arc<Server>, length: u64, query: &str) -> Response {
+	let name = query.split('&')
+	                .filter_map(|param| param.split_once('='))
+	                .find(|(key, _)| *key == "name")
+	                .map(|(_, name)| decode(name))
+	                .map(|name| crate::filename::sanitize(&name))
+	                .filter(|name| !name.is_empty())
+	                .ok_or_else(|| (400, "Missing name of the uploaded archive, e.g. `?name=Series v01.cbz`".to_owned()))?;
+	let internal = |err: std::io::Error| (500, err.to_string());
+
+	let dir = crate::spill::temp_path();
+	tokio::fs::create_dir_all(&dir).await.map_err(internal)?;
+	let path = dir.join(name);
+	let mut file = tokio::fs::File::create(&path).await.map_err(internal)?;
+	let copied = tokio::io::copy(&mut stream.take(length), &mut file).await;
+	drop(file);
+	match copied {
+		Ok(copied) if copied == length => {},
+		_ => {
+			remove_upload(&path).await;
+			return Err((400, "Incomplete upload".to_owned()));
+		},
+	}
+	match submit(server, path.clone(), true) {
+		Ok(job) => Ok(serde_json::json!({ "job": job })),
+		Err(err) => {
+			remove_upload(&path).await;
+			Err((400, err.to_string()))
+		},
+	}
+}
+
+
+/// Remove the uploaded archive with its folder.
+pub async fn remove_upload(path: &Path) {
+	if let Err(err) = tokio::fs::remove_file(path).await {
+		warn!("Unable to remove upload '{}': {err}", path.display());
+	}
+	if let Some(dir) = path.parent() {
+		tokio::fs::remove_dir(dir).await.ok();
+	}
+}
+
+
+/// Output of the finished job.
+fn output(server: &Server, job: usize) -> Result<PathBuf, (u16, String)> {
+	let jobs = server.jobs.lock().unwrap();
+	let job = find(&jobs, job).map_err(|err| (404, err.to_string()))?;
+	match &job.status.state {
+		State::Done { output, .. } => Ok(output.clone()),
+		_ => Err((409, format!("Job {} is not done", job.status.job))),
+	}
+}
+
+
+async fn download(mut stream: TcpStream, path: &Path) -> Result<(), Error> {
+	let mut file = match tokio::fs::File::open(path).await {
+		Ok(file) => file,
+		Err(err) => return respond(&mut stream, Err((404, err.to_string()))).await,
+	};
+	let length = file.metadata().await?.len();
+	let name = path.file_name().unwrap_or_default().to_string_lossy().replace('"', "'");
+	let head = format!(
+	                   "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {length}\r\n\
+	                    Content-Disposition: attachment; filename=\"{name}\"\r\nConnection: close\r\n\r\n"
+	);
+	stream.write_all(head.as_bytes()).await?;
+	tokio::io::copy(&mut file, &mut stream).await?;
+	Ok(())
+}
+
+
+/// Progress events until the client disconnects.
+async fn events(mut stream: TcpStream) -> Result<(), Error> {
+	let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+	stream.write_all(head.as_bytes()).await?;
+	let mut events = progress::subscribe();
+	while let Some(event) = events.next().await {
+		let data = serde_json::to_string(&event)?;
+		stream.write_all(format!("data: {data}\n\n").as_bytes()).await?;
+	}
+	Ok(())
+}
+
+
+async fn respond(stream: &mut TcpStream, response: Response) -> Result<(), Error> {
+	let (code, body) = match response {
+		Ok(value) => (200, value),
+		Err((code, error)) => (code, serde_json::json!({ "error": error })),
+	};
+	let reason = match code {
+		200 => "OK",
+		400 => "Bad Request",
+		404 => "Not Found",
+		409 => "Conflict",
+		411 => "Length Required",
+		_ => "Internal Server Error",
+	};
+	let body = serde_json::to_vec(&body)?;
+	let head = format!(
+	                   "HTTP/1.1 {code} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+	                    Connection: close\r\n\r\n",
+	                   body.len()
+	);
+	stream.write_all(head.as_bytes()).await?;
+	stream.write_all(&body).await?;
+	Ok(())
+}
+
+
+/// Decode `%XX` escapes and `+` of a query parameter.
+fn decode(s: &str) -> String {
+	let mut bytes = Vec::with_capacity(s.len());
+	let mut rest = s.as_bytes();
+	while let Some((&byte, tail)) = rest.split_first() {
+		rest = tail;
+		match byte {
+			b'+' => bytes.push(b' '),
+			b'%' if rest.len() >= 2 => {
+				match std::str::from_utf8(&rest[..2]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+					Some(byte) => {
+						bytes.push(byte);
+						rest = &rest[2..];
+					},
+					None => bytes.push(b'%'),
+				}
+			},
+			byte => bytes.push(byte),
+		}
+	}
+	String::from_utf8_lossy(&bytes).into_owned()
+}
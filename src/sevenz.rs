@@ -0,0 +1,144 @@
+//! 7z output on a dedicated thread.
+//!
+//! LZMA2 compression is synchronous and slow, so entries are sent through a bounded channel
+//! to the writer thread which compresses them while next pages are still being encoded.
+//! Entries are pushed in chunks, each chunk is a solid block.
+//! Large entries of a chunk wait for compression in `--temp-dir`, see [`crate::spill`].
+//! All blocking file I/O, including the header and the final sync, happens on that thread,
+//! so the async runtime is never stalled by the 7z output.
+
+use std::fs::{File, Metadata};
+use std::time::SystemTime;
+use sevenz_rust::*;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::cli::{SevenZipMethod, SevenZipOptions};
+use crate::error::Error;
+use crate::spill;
+
+
+/// How many entries can wait for the writer thread.
+const QUEUE: usize = 4;
+
+/// Entry name, data and modification time.
+type Entry = (String, Vec<u8>, Option<SystemTime>);
+
+/// Entry of the chunk waiting for compression.
+type Pending = (String, spill::Buffer, Option<SystemTime>);
+
+
+pub struct SevenZipWriter {
+	sender: mpsc::Sender<Entry>,
+	result: oneshot::Receiver<Result<Metadata, Error>>,
+}
+
+
+impl SevenZipWriter {
+	/// Start the writer thread.
+	pub fn new(file: File, options: &SevenZipOptions) -> Result<Self, Error> {
+		let mut lzma_options = lzma::LZMA2Options::with_preset(options.preset);
+		if let Some(dict_size) = options.dict_size {
+			lzma_options.dict_size = dict_size;
+		}
+		let chunk = options.chunk();
+		debug!(
+		       "7z: {:?}, preset: {}, dict: {}b, chunk: {chunk}",
+		       options.method, options.preset, lzma_options.dict_size
+		);
+
+		let config = match options.method {
+			// `lzma-rust` has a single type of options, of both encoders,
+			// and the LZMA encoder of `sevenz-rust` reads preset and dictionary from its `LZMA2` variant:
+			SevenZipMethod::Lzma => {
+				SevenZMethodConfiguration::new(SevenZMethod::LZMA).with_options(MethodOptions::LZMA2(lzma_options))
+			},
+			SevenZipMethod::Lzma2 => SevenZMethodConfiguration::from(lzma_options),
+		};
+
+		let (sender, receiver) = mpsc::channel(QUEUE);
+		let (result_sender, result) = oneshot::channel();
+		std::thread::Builder::new().name("7z-writer".to_owned())
+		                           .spawn(move || {
+			                           let res = Self::run(file, config, receiver, chunk);
+			                           result_sender.send(res).ok();
+		                           })?;
+		Ok(Self { sender, result })
+	}
+
+
+	fn run(file: File,
+	       config: SevenZMethodConfiguration,
+	       mut receiver: mpsc::Receiver<Entry>,
+	       chunk: usize)
+	       -> Result<Metadata, Error> {
+		let mut writer = SevenZWriter::new(file)?;
+		writer.set_content_methods(vec![config]);
+
+		let mut pending = Vec::with_capacity(chunk.min(1024));
+		while let Some((name, data, modified)) = receiver.blocking_recv() {
+			pending.push((name, spill::Buffer::new(data)?, modified));
+			if pending.len() >= chunk {
+				Self::push(&mut writer, &mut pending)?;
+			}
+		}
+		Self::push(&mut writer, &mut pending)?;
+		let file = writer.finish()?;
+		let meta = file.metadata()?;
+		file.sync_data()?;
+		Ok(meta)
+	}
+
+
+	fn push(writer: &mut SevenZWriter<File>, pending: &mut Vec<Pending>) -> Result<(), Error> {
+		match pending.len() {
+			0 => {},
+			1 => {
+				let (name, data, modified) = pending.pop().unwrap();
+				trace!("7z: compressing '{name}'");
+				let entry = Self::entry(name, modified);
+				writer.push_archive_entry(entry, Some(data.into_reader()))?;
+			},
+			n => {
+				trace!("7z: compressing chunk of {n} entries");
+				let (entries, readers): (Vec<_>, Vec<_>) = pending.drain(..)
+				                                                  .map(|(name, data, modified)| {
+					                                                  let mut entry = Self::entry(name, modified);
+					                                                  entry.has_stream = true;
+					                                                  (entry, SourceReader::new(data.into_reader()))
+				                                                  })
+				                                                  .unzip();
+				writer.push_archive_entries(entries, SeqReader::new(readers))?;
+			},
+		}
+		Ok(())
+	}
+
+
+	fn entry(name: String, modified: Option<SystemTime>) -> SevenZArchiveEntry {
+		let mut entry = SevenZArchiveEntry::default();
+		entry.name = name;
+		if let Some(Ok(time)) = modified.map(nt_time::FileTime::try_from) {
+			entry.last_modified_date = time;
+			entry.has_last_modified_date = true;
+		}
+		entry
+	}
+
+
+	pub async fn write_all(&mut self, name: &str, data: &[u8], modified: Option<SystemTime>) -> Result<(), Error> {
+		if self.sender.send((name.to_owned(), data.to_vec(), modified)).await.is_err() {
+			// writer thread is gone, the reason will be returned by `close`:
+			return Err(format!("7z writer stopped, can't write '{name}'").into());
+		}
+		Ok(())
+	}
+
+
+	/// Wait for the writer thread to compress remaining entries, write the header and sync the file.
+	pub async fn close(self) -> Result<Metadata, Error> {
+		drop(self.sender);
+		self.result
+		    .await
+		    .map_err(|_| Error::from("7z writer thread panicked".to_owned()))?
+	}
+}
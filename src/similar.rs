@@ -0,0 +1,133 @@
+//! Reuse of encoded pages across archives for `--reuse-similar`.
+//!
+//! The same chapter from different rips differs in compression artifacts,
+//! so pages are matched by a perceptual hash of the decoded image rather than by content.
+//! Encoded outputs are kept for the whole run and reused for pages with the same resolution,
+//! the same encode settings and a close hash, confirmed by comparing thumbnails pixel by pixel,
+//! since blank pages or pages with the same panel layout often have close hashes.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use image::DynamicImage;
+use image::imageops::FilterType;
+use serde::Serialize;
+
+use crate::cli::Config;
+
+
+/// Pages with hashes differing in no more than this many bits are the same.
+const MAX_DISTANCE: u32 = 3;
+/// Side of the grayscale thumbnail compared to confirm a match of hashes.
+const THUMBNAIL: u32 = 128;
+/// Least PSNR of thumbnails of the same page, artifacts of different rips stay well above it.
+const MIN_PSNR: f64 = 36.0;
+/// Stop caching new encodes when cached ones take this many bytes.
+const CACHE_LIMIT: usize = 1 << 30;
+
+static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+
+/// Page which was encoded first.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+	pub archive: PathBuf,
+	pub page: String,
+}
+
+
+#[derive(Default)]
+struct Cache {
+	entries: Vec<Cached>,
+	size: usize,
+}
+
+struct Cached {
+	fingerprint: Fingerprint,
+	settings: String,
+	data: Arc<Vec<u8>>,
+	origin: Origin,
+}
+
+
+/// What pages are matched by.
+pub struct Fingerprint {
+	hash: u64,
+	dimensions: (u32, u32),
+	thumbnail: Vec<u8>,
+}
+
+
+impl Fingerprint {
+	pub fn new(image: &DynamicImage) -> Self {
+		Self { hash: perceptual_hash(image),
+		       dimensions: (image.width(), image.height()),
+		       thumbnail: image.resize_exact(THUMBNAIL, THUMBNAIL, FilterType::Triangle)
+		                       .to_luma8()
+		                       .into_raw() }
+	}
+
+
+	fn matches(&self, other: &Fingerprint) -> bool {
+		if self.dimensions != other.dimensions || (self.hash ^ other.hash).count_ones() > MAX_DISTANCE {
+			return false;
+		}
+		let squared: u64 = self.thumbnail
+		                       .iter()
+		                       .zip(&other.thumbnail)
+		                       .map(|(a, b)| (a.abs_diff(*b) as u64).pow(2))
+		                       .sum();
+		let mse = squared as f64 / self.thumbnail.len() as f64;
+		mse == 0.0 || 10.0 * (255.0 * 255.0 / mse).log10() >= MIN_PSNR
+	}
+}
+
+
+/// Difference hash: each bit tells whether a pixel is brighter than its right neighbour
+/// on a 9×8 grayscale thumbnail.
+fn perceptual_hash(image: &DynamicImage) -> u64 {
+	let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+	let mut hash = 0;
+	for y in 0..8 {
+		for x in 0..8 {
+			let bit = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+			hash = hash << 1 | bit as u64;
+		}
+	}
+	hash
+}
+
+
+/// Settings the encode depends on, so a page never gets an output of another format or quality,
+/// e.g. of a `--cover-format` page or an `--adaptive` lossless one.
+pub fn settings(cfg: &Config) -> String {
+	format!(
+	        "{:?} {} {} {:?} {:?}",
+	        cfg.format, cfg.quality, cfg.lossless, cfg.encoder_cmd, cfg.target_page_size
+	)
+}
+
+
+/// Encoded output of a similar page seen before with the same settings.
+pub fn find(fingerprint: &Fingerprint, settings: &str) -> Option<(Arc<Vec<u8>>, Origin)> {
+	let cache = CACHE.get_or_init(Default::default).lock().unwrap();
+	cache.entries
+	     .iter()
+	     .filter(|cached| cached.settings == settings)
+	     .find(|cached| cached.fingerprint.matches(fingerprint))
+	     .map(|cached| (cached.data.clone(), cached.origin.clone()))
+}
+
+
+pub fn insert(fingerprint: Fingerprint, settings: String, data: &[u8], origin: Origin) {
+	let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+	let size = data.len() + fingerprint.thumbnail.len();
+	if cache.size + size > CACHE_LIMIT {
+		trace!("similar pages cache is full, not caching {}", origin.page);
+		return;
+	}
+	cache.size += size;
+	cache.entries.push(Cached { fingerprint,
+	                            settings,
+	                            data: Arc::new(data.to_vec()),
+	                            origin });
+}
@@ -0,0 +1,126 @@
+//! `slice` command: copy a subset of pages into a new archive without transcoding.
+
+use std::ops::RangeInclusive;
+use clap::ValueEnum;
+
+use crate::cli::{ArchiveType, EntryOrder, SliceArgs};
+use crate::error::Error;
+use crate::comic_info::is_comic_info;
+use crate::paths;
+use crate::ArchiveWriter;
+
+
+/// Page ranges such as `5-30`, `1,3,7-`, `-10`. Page numbers start from 1.
+#[derive(Debug, Clone)]
+pub struct PageRanges(Vec<RangeInclusive<usize>>);
+
+impl PageRanges {
+	pub fn contains(&self, page: usize) -> bool { self.0.iter().any(|range| range.contains(&page)) }
+}
+
+
+pub fn parse_page_ranges(s: &str) -> Result<PageRanges, String> {
+	let page = |s: &str| {
+		s.trim()
+		 .parse::<usize>()
+		 .ok()
+		 .filter(|n| *n > 0)
+		 .ok_or_else(|| format!("Invalid page number '{s}'"))
+	};
+
+	let ranges = s.split(',')
+	              .map(|part| {
+		              match part.split_once('-') {
+			              Some((from, to)) => {
+				              let from = if from.trim().is_empty() { 1 } else { page(from)? };
+				              let to = if to.trim().is_empty() { usize::MAX } else { page(to)? };
+				              if from > to {
+					              return Err(format!("Invalid page range '{part}'"));
+				              }
+				              Ok(from..=to)
+			              },
+			              None => page(part).map(|n| n..=n),
+		              }
+	              })
+	              .collect::<Result<Vec<_>, _>>()?;
+	Ok(PageRanges(ranges))
+}
+
+
+pub async fn run(args: &SliceArgs) -> Result<(), Error> {
+	let ext = args.output
+	              .extension()
+	              .map(|ext| ext.to_string_lossy().to_string())
+	              .unwrap_or_default();
+	let archive = ArchiveType::from_str(&ext, true).map_err(|_| {
+		                                               format!("Unknown output archive type '{}'", args.output.display())
+	                                               })?;
+
+	let (reader, mut entries, _) = crate::archive_reader(&args.input, None, &Default::default()).await?;
+	paths::sort_entries(&mut entries, EntryOrder::Natural);
+
+	// Non-image entries such as ComicInfo.xml are always kept:
+	let numbers = paths::page_numbers(&entries);
+	entries.retain(|entry| {
+		       let Some(page) = numbers.get(&entry.index).copied() else {
+			       return true;
+		       };
+		       let in_range = args.pages.as_ref().filter(|pages| !pages.contains(page)).is_none();
+		       let matches = args.name.as_ref().filter(|name| !name.matches(&entry.uri)).is_none();
+		       in_range && matches
+	       });
+
+	let numbers = paths::page_numbers(&entries);
+	if numbers.is_empty() {
+		return Err(format!("No pages selected in '{}'", args.input.display()).into());
+	}
+	info!("Slicing {} pages of {}", numbers.len(), args.input.display());
+
+	let mut writer = match archive {
+		ArchiveType::Cbz | ArchiveType::Zip | ArchiveType::Keep => {
+			ArchiveWriter::open_zip(&args.output, args.force, args.zip.clone()).await?
+		},
+		ArchiveType::Cb7 | ArchiveType::SevenZip => {
+			ArchiveWriter::open_7z(&args.output, args.force, args.sevenz.clone()).await?
+		},
+	};
+
+	for entry in entries {
+		let mut data = Vec::new();
+		reader.read_file(&entry.uri, &mut data)?;
+
+		let name = match numbers.get(&entry.index) {
+			Some(page) if !args.keep_names => paths::render_page_name(&args.rename_pages, *page, &entry.uri)?,
+			_ if is_comic_info(&entry.uri) => {
+				let xml = String::from_utf8_lossy(&data);
+				data = update_comic_info(&xml, numbers.len()).into_bytes();
+				entry.uri
+			},
+			_ => entry.uri,
+		};
+		writer.write_all(&name, &data, None, None).await?;
+	}
+
+	writer.close().await?;
+	info!("Written: {}", args.output.display());
+	Ok(())
+}
+
+
+/// Set `PageCount` and drop per-page `Pages` metadata which refers to the original page indices.
+fn update_comic_info(xml: &str, page_count: usize) -> String {
+	let mut xml = xml.to_owned();
+	if let (Some(start), Some(end)) = (xml.find("<PageCount>"), xml.find("</PageCount>")) {
+		if start < end {
+			xml.replace_range(start + "<PageCount>".len()..end, &page_count.to_string());
+		}
+	}
+	if let (Some(start), Some(end)) = (xml.find("<Pages>"), xml.find("</Pages>")) {
+		if start < end {
+			xml.replace_range(start..end + "</Pages>".len(), "");
+		}
+	} else if let Some(start) = xml.find("<Pages/>") {
+		xml.replace_range(start..start + "<Pages/>".len(), "");
+	}
+	xml
+}
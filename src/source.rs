@@ -0,0 +1,386 @@
+//! Input archive reader.
+//!
+//! Archives are read with libarchive, except of password-protected zip and 7z,
+//! which are read with `zip` and `sevenz-rust` when `--password` is given.
+//! Archive type is detected by magic bytes, the extension is often wrong (`.cbz` which is RAR).
+//! Those are opened once and shared by the pages read in parallel.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use archive_reader::Archive;
+
+use crate::error::Error;
+
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const ZIP_EMPTY_MAGIC: &[u8] = b"PK\x05\x06";
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+const RAR_MAGIC: &[u8] = b"Rar!\x1A\x07";
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+
+
+/// Archive type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+	Zip,
+	SevenZip,
+	Rar,
+	Tar,
+}
+
+impl Kind {
+	/// Detect by magic bytes at the start of the file.
+	pub fn from_magic(head: &[u8]) -> Option<Self> {
+		if head.starts_with(ZIP_MAGIC) || head.starts_with(ZIP_EMPTY_MAGIC) {
+			Some(Self::Zip)
+		} else if head.starts_with(SEVEN_ZIP_MAGIC) {
+			Some(Self::SevenZip)
+		} else if head.starts_with(RAR_MAGIC) {
+			Some(Self::Rar)
+		} else if head.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+			Some(Self::Tar)
+		} else {
+			None
+		}
+	}
+
+	/// Type which the extension claims.
+	pub fn from_extension(path: &Path) -> Option<Self> {
+		let ext = path.extension()?.to_string_lossy().to_lowercase();
+		match ext.as_str() {
+			"cbz" | "zip" => Some(Self::Zip),
+			"cb7" | "7z" => Some(Self::SevenZip),
+			"cbr" | "rar" => Some(Self::Rar),
+			"cbt" | "tar" => Some(Self::Tar),
+			_ => None,
+		}
+	}
+}
+
+
+pub enum Source {
+	Archive(Archive, Option<Kind>),
+	Zip {
+		path: PathBuf,
+		password: String,
+		archive: Mutex<zip::ZipArchive<File>>,
+	},
+	SevenZip {
+		path: PathBuf,
+		archive: Box<Mutex<SevenZip>>,
+	},
+}
+
+
+/// Password-protected 7z, which has no random access: entries of a solid block are decoded in order,
+/// so the rest of a block decoded to reach an entry is kept until that is read.
+pub struct SevenZip {
+	file: File,
+	archive: sevenz_rust::Archive,
+	password: Vec<u8>,
+	/// Entries decoded ahead of reading.
+	decoded: HashMap<String, Vec<u8>>,
+	/// Entries taken already, not kept when their block is decoded again.
+	taken: HashSet<String>,
+}
+
+
+impl Source {
+	pub fn open(path: impl AsRef<Path>, password: Option<&str>) -> Result<Self, Error> {
+		let path = path.as_ref();
+		let kind = Self::sniff(path)?;
+
+		let Some(password) = password else {
+			// libarchive detects the format by content itself:
+			let mut archive = Archive::open(path);
+			archive.block_size(1024 * 1024);
+			return Ok(Self::Archive(archive, kind));
+		};
+
+		let path = path.to_owned();
+		match kind {
+			Some(Kind::Zip) => {
+				let archive = zip::ZipArchive::new(File::open(&path)?).map_err(zip_error)?;
+				Ok(Self::Zip { path,
+				               password: password.to_owned(),
+				               archive: Mutex::new(archive) })
+			},
+			Some(Kind::SevenZip) => {
+				let archive = SevenZip::open(&path, password).map_err(|err| seven_zip_error(&path, err))?;
+				Ok(Self::SevenZip { path,
+				                    archive: Box::new(Mutex::new(archive)) })
+			},
+			_ => {
+				warn!("password is supported only for zip and 7z, ignoring it for '{}'", path.display());
+				Self::open(path, None)
+			},
+		}
+	}
+
+
+	/// Archive type by magic bytes, warns if the extension says otherwise.
+	fn sniff(path: &Path) -> Result<Option<Kind>, Error> {
+		let mut head = [0; TAR_MAGIC_OFFSET + 8];
+		let mut file = std::fs::File::open(path)?;
+		let mut len = 0;
+		// `read` may return less than asked:
+		while len < head.len() {
+			match file.read(&mut head[len..])? {
+				0 => break,
+				n => len += n,
+			}
+		}
+		let kind = Kind::from_magic(&head[..len]);
+		match (kind, Kind::from_extension(path)) {
+			(Some(kind), Some(claimed)) if kind != claimed => {
+				warn!("'{}' is actually {kind:?}, not {claimed:?}", path.display());
+			},
+			(None, _) => debug!("unknown archive type of '{}', leaving it to libarchive", path.display()),
+			_ => trace!("archive type of '{}': {kind:?}", path.display()),
+		}
+		Ok(kind)
+	}
+
+
+	pub fn path(&self) -> &Path {
+		match self {
+			Self::Archive(archive, _) => archive.path(),
+			Self::Zip { path, .. } | Self::SevenZip { path, .. } => path,
+		}
+	}
+
+
+	/// Archive type detected by magic bytes.
+	pub fn kind(&self) -> Option<Kind> {
+		match self {
+			Self::Archive(_, kind) => *kind,
+			Self::Zip { .. } => Some(Kind::Zip),
+			Self::SevenZip { .. } => Some(Kind::SevenZip),
+		}
+	}
+
+
+	pub fn list_file_names(&self) -> Result<Vec<String>, Error> {
+		match self {
+			Self::Archive(archive, _) => Ok(archive.list_file_names()?.filter_map(Result::ok).collect()),
+			Self::Zip { archive, .. } => Ok(archive.lock().unwrap().file_names().map(ToOwned::to_owned).collect()),
+			Self::SevenZip { archive, .. } => {
+				Ok(archive.lock()
+				          .unwrap()
+				          .archive
+				          .files
+				          .iter()
+				          .filter(|entry| !entry.is_directory)
+				          .map(|entry| entry.name.clone())
+				          .collect())
+			},
+		}
+	}
+
+
+	/// Modification times of entries by name, libarchive doesn't expose them,
+	/// so they are read from zip and 7z only.
+	pub fn modified_times(&self) -> Result<HashMap<String, SystemTime>, Error> {
+		let path = self.path();
+		match self.kind() {
+			Some(Kind::Zip) => {
+				let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?).map_err(zip_error)?;
+				let mut times = HashMap::with_capacity(archive.len());
+				for i in 0..archive.len() {
+					// raw entry doesn't need the password:
+					let file = archive.by_index_raw(i).map_err(zip_error)?;
+					if let Some(time) = zip_time(file.last_modified()) {
+						times.insert(file.name().to_owned(), time);
+					}
+				}
+				Ok(times)
+			},
+			Some(Kind::SevenZip) => {
+				self.seven_zip_entries(|entry| {
+					    entry.has_last_modified_date
+					         .then(|| (entry.name.clone(), entry.last_modified_date.into()))
+				    })
+			},
+			kind => {
+				debug!("modification times of {kind:?} '{}' are not supported", path.display());
+				Ok(HashMap::new())
+			},
+		}
+	}
+
+
+	/// Uncompressed sizes of entries by name, read from zip and 7z only, like modification times.
+	pub fn entry_sizes(&self) -> Result<HashMap<String, u64>, Error> {
+		let path = self.path();
+		match self.kind() {
+			Some(Kind::Zip) => {
+				let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?).map_err(zip_error)?;
+				let mut sizes = HashMap::with_capacity(archive.len());
+				for i in 0..archive.len() {
+					let file = archive.by_index_raw(i).map_err(zip_error)?;
+					sizes.insert(file.name().to_owned(), file.size());
+				}
+				Ok(sizes)
+			},
+			Some(Kind::SevenZip) => self.seven_zip_entries(|entry| Some((entry.name.clone(), entry.size))),
+			_ => Ok(HashMap::new()),
+		}
+	}
+
+
+	/// Headers of 7z entries, read without the password unless opened with one.
+	fn seven_zip_entries<T, C: FromIterator<T>>(&self,
+	                                            map: impl FnMut(&sevenz_rust::SevenZArchiveEntry) -> Option<T>)
+	                                            -> Result<C, Error> {
+		if let Self::SevenZip { archive, .. } = self {
+			return Ok(archive.lock().unwrap().archive.files.iter().filter_map(map).collect());
+		}
+		let path = self.path();
+		let reader = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty()).map_err(|err| {
+			                                                                                 seven_zip_error(path, err)
+		                                                                                 })?;
+		Ok(reader.archive().files.iter().filter_map(map).collect())
+	}
+
+
+	/// Zip comment of the archive, if any.
+	pub fn comment(&self) -> Result<Option<String>, Error> {
+		if self.kind() != Some(Kind::Zip) {
+			return Ok(None);
+		}
+		let comment = match self {
+			Self::Zip { archive, .. } => String::from_utf8_lossy(archive.lock().unwrap().comment()).into_owned(),
+			_ => {
+				let archive = zip::ZipArchive::new(File::open(self.path())?).map_err(zip_error)?;
+				String::from_utf8_lossy(archive.comment()).into_owned()
+			},
+		};
+		Ok((!comment.trim().is_empty()).then_some(comment))
+	}
+
+
+	/// Like `read_file`, but data read before an error such as a CRC mismatch is kept,
+	/// returns whether the entry is damaged.
+	pub fn read_file_salvaged(&self, name: &str, output: &mut Vec<u8>) -> Result<bool, Error> {
+		match self.read_file(name, output) {
+			Ok(_) => Ok(false),
+			Err(err) if !output.is_empty() => {
+				warn!("'{name}' is damaged, salvaged {}b: {err}", output.len());
+				Ok(true)
+			},
+			Err(err) => Err(err),
+		}
+	}
+
+
+	/// Read every entry to check the archive, returns number of entries.
+	pub fn verify(&self) -> Result<usize, Error> {
+		let names = self.list_file_names()?;
+		let mut buffer = Vec::new();
+		for name in &names {
+			buffer.clear();
+			self.read_file(name, &mut buffer)
+			    .map_err(|err| format!("'{name}' of '{}' is unreadable: {err}", self.path().display()))?;
+		}
+		Ok(names.len())
+	}
+
+
+	/// Read the whole entry, returns number of bytes read.
+	pub fn read_file(&self, name: &str, output: &mut Vec<u8>) -> Result<usize, Error> {
+		match self {
+			Self::Archive(archive, _) => Ok(archive.read_file(name, output)?),
+			Self::Zip { path, password, archive } => {
+				let mut archive = archive.lock().unwrap();
+				let mut file = archive.by_name_decrypt(name, password.as_bytes())
+				                      .map_err(zip_error)?
+				                      .map_err(|_| wrong_password(path))?;
+				Ok(file.read_to_end(output)?)
+			},
+			Self::SevenZip { path, archive } => {
+				let found = archive.lock()
+				                   .unwrap()
+				                   .read_file(name, output)
+				                   .map_err(|err| seven_zip_error(path, err))?;
+				found.ok_or_else(|| format!("'{name}' not found in '{}'", path.display()).into())
+			},
+		}
+	}
+}
+
+
+impl SevenZip {
+	fn open(path: &Path, password: &str) -> Result<Self, sevenz_rust::Error> {
+		let mut file = File::open(path).map_err(sevenz_rust::Error::io)?;
+		let len = file.metadata().map_err(sevenz_rust::Error::io)?.len();
+		let password = sevenz_rust::Password::from(password).to_vec();
+		let archive = sevenz_rust::Archive::read(&mut file, len, &password)?;
+		Ok(Self { file,
+		          archive,
+		          password,
+		          decoded: HashMap::new(),
+		          taken: HashSet::new() })
+	}
+
+
+	/// Decode the block of the entry, `None` if there is no such entry.
+	fn read_file(&mut self, name: &str, output: &mut Vec<u8>) -> Result<Option<usize>, sevenz_rust::Error> {
+		if let Some(data) = self.decoded.remove(name) {
+			self.taken.insert(name.to_owned());
+			output.extend_from_slice(&data);
+			return Ok(Some(data.len()));
+		}
+		let Some(index) = self.archive.files.iter().position(|entry| entry.name == name) else {
+			return Ok(None);
+		};
+		self.taken.insert(name.to_owned());
+		let Some(folder) = self.archive.stream_map.file_folder_index[index] else {
+			// empty entry:
+			return Ok(Some(0));
+		};
+		let Self { file,
+		           archive,
+		           password,
+		           decoded,
+		           taken, } = self;
+		let mut found = 0;
+		let decoder = sevenz_rust::FolderDecoder::new(folder, archive, password, file);
+		decoder.for_each_entries(&mut |entry, data| {
+			       if entry.name == name {
+				       found = data.read_to_end(output)?;
+			       } else if taken.contains(&entry.name) {
+				       // entries of a block follow one another, so skipped ones are still decoded:
+				       std::io::copy(data, &mut std::io::sink())?;
+			       } else {
+				       let mut buffer = Vec::with_capacity(entry.size as usize);
+				       data.read_to_end(&mut buffer)?;
+				       decoded.insert(entry.name.clone(), buffer);
+			       }
+			       Ok(true)
+		       })?;
+		Ok(Some(found))
+	}
+}
+
+
+fn wrong_password(path: &Path) -> Error { format!("Wrong password for '{}'", path.display()).into() }
+
+fn zip_error(err: zip::result::ZipError) -> Error { format!("Zip-read: {err}").into() }
+
+/// MS-DOS time has no time zone, it is taken as UTC both ways.
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+	let date = chrono::NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)?;
+	let time = date.and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32)?;
+	Some(time.and_utc().into())
+}
+
+fn seven_zip_error(path: &Path, err: sevenz_rust::Error) -> Error {
+	// decryption with a wrong key produces garbage which fails somewhere in decoding:
+	debug!("7z: {err}");
+	format!("Unable to read '{}', wrong password? ({err})", path.display()).into()
+}
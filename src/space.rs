@@ -0,0 +1,75 @@
+//! Free space check of the output directory before a batch, for `--disk-check`.
+//!
+//! Outputs are rarely bigger than sources, so the total size of sources is the space required.
+//! `--estimate` tells how much the outputs would really take.
+
+use std::path::{Path, PathBuf};
+use indicatif::HumanBytes;
+
+use crate::cli::DiskCheck;
+use crate::error::Error;
+
+
+/// Check that the outputs of `sources` fit into `outdir`, at most `cap` bytes of them.
+pub fn check(sources: &[PathBuf], outdir: &Path, cap: Option<u64>, mode: DiskCheck) -> Result<(), Error> {
+	if mode == DiskCheck::Off || sources.is_empty() {
+		return Ok(());
+	}
+	let available = match available(outdir) {
+		Ok(available) => available,
+		Err(err) => {
+			warn!("Unable to check free space of '{}': {err}", outdir.display());
+			return Ok(());
+		},
+	};
+	let total: u64 = sources.iter()
+	                        .filter_map(|path| std::fs::metadata(path).ok())
+	                        .map(|meta| meta.len())
+	                        .sum();
+	let required = cap.map_or(total, |cap| total.min(cap));
+	debug!("space required: {required}b, available: {available}b");
+	if required <= available {
+		return Ok(());
+	}
+
+	let message = format!(
+	                      "Output directory '{}' has {} free, sources take {}",
+	                      outdir.display(),
+	                      HumanBytes(available),
+	                      HumanBytes(required)
+	);
+	match mode {
+		DiskCheck::Refuse => {
+			Err(format!(
+			            "{message}. Check the projected size with `--estimate`, start anyway with `--disk-check warn` \
+			             or limit outputs with `--max-total-output`"
+			).into())
+		},
+		_ => {
+			warn!("{message}, outputs may not fit");
+			Ok(())
+		},
+	}
+}
+
+
+/// Bytes available to the user on the filesystem of the path.
+#[cfg(unix)]
+fn available(path: &Path) -> std::io::Result<u64> {
+	use std::os::unix::ffi::OsStrExt;
+
+	let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+	// SAFETY: `statvfs` only writes into the zeroed struct, the path is NUL-terminated.
+	let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+	if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+		return Err(std::io::Error::last_os_error());
+	}
+	#[allow(clippy::unnecessary_cast)]
+	Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+
+#[cfg(not(unix))]
+fn available(_: &Path) -> std::io::Result<u64> {
+	Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "not supported on this platform"))
+}
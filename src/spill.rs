@@ -0,0 +1,131 @@
+//! Large buffers kept in files of `--temp-dir` instead of memory:
+//! converted pages waiting to be written in order and entries of a 7z chunk waiting for compression.
+//! Buffers are spilled only when `--temp-dir` is set and they are larger than `--spill-above`.
+
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+
+static SPILL: OnceLock<Spill> = OnceLock::new();
+
+/// Counter of temp files, so names are unique within the process.
+static FILES: AtomicU64 = AtomicU64::new(0);
+
+
+struct Spill {
+	dir: PathBuf,
+	threshold: usize,
+}
+
+
+/// Data either in memory or in a temp file.
+pub enum Buffer {
+	Memory(Vec<u8>),
+	Spilled(Spilled),
+}
+
+
+/// Temp file removed when dropped.
+pub struct Spilled {
+	path: PathBuf,
+	file: File,
+}
+
+
+/// Spill buffers larger than `threshold` bytes to files in `dir`.
+pub fn init(dir: PathBuf, threshold: u64) -> std::io::Result<()> {
+	std::fs::create_dir_all(&dir)?;
+	debug!("spilling buffers above {threshold}b to '{}'", dir.display());
+	SPILL.set(Spill { dir,
+	                  threshold: threshold.try_into().unwrap_or(usize::MAX) })
+	     .ok();
+	Ok(())
+}
+
+
+/// Directory for temporary files, `--temp-dir` or the one of the system.
+pub fn temp_dir() -> PathBuf {
+	SPILL.get().map_or_else(std::env::temp_dir, |spill| spill.dir.clone())
+}
+
+
+/// Unique path of a new temporary file or directory.
+pub fn temp_path() -> PathBuf {
+	temp_dir().join(format!(
+	                        "comic-repack-{}-{}",
+	                        std::process::id(),
+	                        FILES.fetch_add(1, Ordering::SeqCst)
+	))
+}
+
+
+impl Buffer {
+	/// Keep the data in memory, or write it to a temp file if it is large.
+	pub fn new(data: Vec<u8>) -> std::io::Result<Self> {
+		match SPILL.get() {
+			Some(spill) if data.len() > spill.threshold => {
+				let path = temp_path();
+				trace!("spilling {}b to '{}'", data.len(), path.display());
+				let mut file = File::options().read(true)
+				                              .write(true)
+				                              .create_new(true)
+				                              .open(&path)?;
+				// removed on error too:
+				let mut spilled = Spilled { path, file: file.try_clone()? };
+				file.write_all(&data)?;
+				spilled.file.rewind()?;
+				Ok(Self::Spilled(spilled))
+			},
+			_ => Ok(Self::Memory(data)),
+		}
+	}
+
+
+	/// [`Buffer::new`] without blocking the async runtime.
+	pub async fn spill(data: Vec<u8>) -> std::io::Result<Self> {
+		match SPILL.get() {
+			Some(spill) if data.len() > spill.threshold => tokio::task::spawn_blocking(move || Self::new(data)).await?,
+			_ => Ok(Self::Memory(data)),
+		}
+	}
+
+
+	/// Read the data back into memory.
+	pub async fn load(self) -> std::io::Result<Vec<u8>> {
+		match self {
+			Self::Memory(data) => Ok(data),
+			Self::Spilled(mut spilled) => {
+				tokio::task::spawn_blocking(move || {
+					let mut data = Vec::new();
+					spilled.read_to_end(&mut data)?;
+					Ok(data)
+				}).await?
+			},
+		}
+	}
+
+
+	pub fn into_reader(self) -> Box<dyn Read + Send> {
+		match self {
+			Self::Memory(data) => Box::new(std::io::Cursor::new(data)),
+			Self::Spilled(spilled) => Box::new(spilled),
+		}
+	}
+}
+
+
+impl Read for Spilled {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.file.read(buf) }
+}
+
+
+impl Drop for Spilled {
+	fn drop(&mut self) {
+		if let Err(err) = std::fs::remove_file(&self.path) {
+			warn!("Unable to remove temp file '{}': {err}", self.path.display());
+		}
+	}
+}
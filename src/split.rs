@@ -0,0 +1,120 @@
+//! `split` command: break one archive into several outputs,
+//! by top-level folders or at page numbers.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use indicatif::MultiProgress;
+
+use crate::cli::{Config, EntryOrder, SplitArgs};
+use crate::error::Error;
+use crate::paths::{self, StringEntry};
+use crate::volumes::VolumeWriter;
+
+
+pub async fn run(args: &SplitArgs, multibar: MultiProgress) -> Result<(), Error> {
+	let cfg = &args.config;
+	let (reader, mut entries, _) = crate::archive_reader(&args.input, cfg.password.as_deref(), &cfg.junk_filter()).await?;
+	let cfg = &Config { archive: cfg.archive.for_input(&args.input, reader.kind()),
+	                    ..cfg.clone() };
+	crate::filter_working_files(&mut entries, cfg);
+	paths::sort_entries(&mut entries, EntryOrder::Natural);
+	let reader = Arc::new(reader);
+
+	// Non-image entries such as ComicInfo.xml go to every part:
+	let numbers = paths::page_numbers(&entries);
+	let (pages, others): (Vec<_>, Vec<_>) = entries.into_iter()
+	                                               .partition(|entry| numbers.contains_key(&entry.index));
+
+	let parts = if args.at.is_empty() {
+		by_folder(pages)
+	} else {
+		at_pages(pages, &numbers, &args.at)
+	};
+	if parts.len() < 2 {
+		warn!("Nothing to split in '{}'", args.input.display());
+	}
+
+	let outdir = match &args.output {
+		Some(outdir) => outdir.to_owned(),
+		None => std::env::current_dir()?,
+	};
+	tokio::fs::create_dir_all(&outdir).await?;
+
+	let stem = args.input.file_stem().unwrap_or_default().to_string_lossy();
+	let width = parts.len().to_string().len().max(2);
+	let mut failed = 0;
+	for (i, (label, mut part)) in parts.into_iter().enumerate() {
+		let label = label.unwrap_or_else(|| format!("{:0width$}", i + 1));
+		let output = outdir.join(format!("{stem} - {label}.{}", cfg.output_ext()));
+		part.extend(others.iter().cloned());
+
+		let mut writer = VolumeWriter::open(&output, cfg.force, cfg).await?;
+		let bar = crate::source_progress_bar(&multibar, &reader, &part);
+		let (stats, failures) =
+			crate::convert_entries(&reader, part, &mut writer, None, None, cfg, &Default::default(), Some(bar)).await?;
+		writer.close().await?;
+		info!("Written: {}, {} pages", output.display(), stats.len());
+		failed += failures.len();
+	}
+	if failed > 0 {
+		return Err(format!("{failed} pages failed, outputs are incomplete").into());
+	}
+	Ok(())
+}
+
+
+/// Group pages by the first folder below the common root.
+/// Pages outside of any folder make their own part.
+fn by_folder(pages: Vec<StringEntry>) -> Vec<(Option<String>, Vec<StringEntry>)> {
+	let dirs: Vec<PathBuf> = pages.iter()
+	                              .map(|entry| Path::new(&entry.uri).parent().unwrap_or(Path::new("")).to_owned())
+	                              .collect();
+	let root = dirs.iter()
+	               .skip(1)
+	               .fold(dirs.first().cloned().unwrap_or_default(), |root, dir| {
+		               root.components()
+		                   .zip(dir.components())
+		                   .take_while(|(a, b)| a == b)
+		                   .map(|(a, _)| a)
+		                   .collect()
+	               });
+
+	let mut parts: Vec<(Option<String>, Vec<StringEntry>)> = Vec::new();
+	for (entry, dir) in pages.into_iter().zip(dirs) {
+		let folder = dir.strip_prefix(&root)
+		                .ok()
+		                .and_then(|dir| dir.components().next())
+		                .and_then(|c| match c {
+			                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+			                _ => None,
+		                });
+		match parts.iter_mut().find(|(label, _)| *label == folder) {
+			Some((_, part)) => part.push(entry),
+			None => parts.push((folder, vec![entry])),
+		}
+	}
+	parts
+}
+
+
+/// Split after each of given page numbers.
+fn at_pages(pages: Vec<StringEntry>,
+            numbers: &std::collections::HashMap<usize, usize>,
+            at: &[u32])
+            -> Vec<(Option<String>, Vec<StringEntry>)> {
+	let mut at = at.to_vec();
+	at.sort_unstable();
+	at.dedup();
+
+	let mut parts: Vec<(Option<String>, Vec<StringEntry>)> = Vec::new();
+	for entry in pages {
+		let page = numbers[&entry.index];
+		let part = at.iter().filter(|at| page > **at as usize).count();
+		while parts.len() <= part {
+			parts.push((None, Vec::new()));
+		}
+		parts[part].1.push(entry);
+	}
+	parts.retain(|(_, part)| !part.is_empty());
+	parts
+}
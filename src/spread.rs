@@ -0,0 +1,97 @@
+//! Splitting of two-page spreads.
+//!
+//! Photographed open books have a dark shadow band along the spine,
+//! which is usually not exactly in the middle. The band is located by column brightness
+//! and cut out, so each half gets no shadow.
+
+use image::DynamicImage;
+use image::imageops::FilterType;
+
+use crate::cli::SplitSpreads;
+
+
+/// Gutter is searched only in this fraction of the width around the center.
+const SEARCH_BAND: f64 = 0.15;
+/// Gutter column must be darker than this fraction of the median column brightness.
+const GUTTER_DARKNESS: f64 = 0.85;
+/// Gutter wider than this fraction of the width is rather a dark picture.
+const MAX_GUTTER_WIDTH: f64 = 0.2;
+
+
+/// Split spread into pages in reading order.
+pub fn split(image: &DynamicImage, mode: SplitSpreads, rtl: bool) -> Vec<DynamicImage> {
+	let (width, height) = (image.width(), image.height());
+	let center = (width / 2, width / 2);
+	let (left_end, right_start) = match mode {
+		SplitSpreads::Center => center,
+		SplitSpreads::Gutter => {
+			find_gutter(image).unwrap_or_else(|| {
+				                  debug!("no gutter found, splitting at center");
+				                  center
+			                  })
+		},
+	};
+	trace!("splitting {width}x{height}: left ..{left_end}, right {right_start}..");
+
+	let left = image.crop_imm(0, 0, left_end, height);
+	let right = image.crop_imm(right_start, 0, width - right_start, height);
+	if rtl {
+		vec![right, left]
+	} else {
+		vec![left, right]
+	}
+}
+
+
+/// Dark vertical band near the center, returns its `[start, end)` columns.
+pub fn find_gutter(image: &DynamicImage) -> Option<(u32, u32)> {
+	let small = image.resize_exact(image.width().min(512), image.height().min(256), FilterType::Triangle)
+	                 .to_luma8();
+	let (w, h) = small.dimensions();
+	if w < 8 || h < 4 {
+		return None;
+	}
+
+	// skip top and bottom margins, page edges are often dark there too:
+	let rows = h / 10..h - h / 10;
+	let columns: Vec<f64> = (0..w).map(|x| {
+		                              let sum: u64 = rows.clone().map(|y| small.get_pixel(x, y)[0] as u64).sum();
+		                              sum as f64 / rows.len() as f64
+	                              })
+	                              .collect();
+
+	let mut sorted = columns.clone();
+	sorted.sort_by(f64::total_cmp);
+	let median = sorted[sorted.len() / 2];
+
+	let from = ((0.5 - SEARCH_BAND) * w as f64) as usize;
+	let to = ((0.5 + SEARCH_BAND) * w as f64) as usize;
+	let (darkest, value) = columns[from..to].iter()
+	                                        .copied()
+	                                        .enumerate()
+	                                        .min_by(|a, b| a.1.total_cmp(&b.1))
+	                                        .map(|(i, v)| (from + i, v))?;
+	if value > median * GUTTER_DARKNESS {
+		return None;
+	}
+
+	// grow the band while columns are closer to the darkest one than to the median:
+	let threshold = (value + median) / 2.0;
+	let mut start = darkest;
+	while start > 0 && columns[start - 1] < threshold {
+		start -= 1;
+	}
+	let mut end = darkest + 1;
+	while end < columns.len() && columns[end] < threshold {
+		end += 1;
+	}
+	if (end - start) as f64 > w as f64 * MAX_GUTTER_WIDTH {
+		return None;
+	}
+
+	let scale = image.width() as f64 / w as f64;
+	let start = (start as f64 * scale).floor() as u32;
+	let end = ((end as f64 * scale).ceil() as u32).min(image.width());
+	debug!("gutter found at {start}..{end} of {}", image.width());
+	Some((start.max(1), end.min(image.width() - 1)))
+}
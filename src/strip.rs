@@ -0,0 +1,43 @@
+//! Webtoon strips: splitting of very tall pages and stitching of short ones.
+
+
+use image::DynamicImage;
+
+
+/// Split tall page into pieces of equal height not taller than `max_height`,
+/// neighbor pieces share `overlap` rows.
+pub fn split(image: &DynamicImage, max_height: u32, overlap: u32) -> Vec<DynamicImage> {
+	let (width, height) = (image.width(), image.height());
+	// overlap can't eat the whole piece:
+	let overlap = overlap.min(max_height / 2);
+	let step = max_height - overlap;
+	let count = height.saturating_sub(overlap).div_ceil(step).max(1);
+	let piece = (height + (count - 1) * overlap).div_ceil(count);
+	trace!("splitting {width}x{height} into {count} pieces of {piece}px, overlap {overlap}px");
+
+	(0..count).map(|i| {
+		          let top = (i * (piece - overlap)).min(height - piece);
+		          image.crop_imm(0, top, width, piece)
+	          })
+	          .collect()
+}
+
+
+/// Join pages top to bottom, narrower pages are aligned to the left.
+pub fn stitch(pages: &[DynamicImage]) -> DynamicImage {
+	let width = pages.iter().map(DynamicImage::width).max().unwrap_or(0);
+	let height = pages.iter().map(DynamicImage::height).sum();
+	let mut strip = if pages.iter().any(|page| page.color().has_alpha()) {
+		DynamicImage::new_rgba8(width, height)
+	} else {
+		DynamicImage::new_rgb8(width, height)
+	};
+	trace!("stitching {} pages into {width}x{height}", pages.len());
+
+	let mut top = 0;
+	for page in pages {
+		image::imageops::replace(&mut strip, page, 0, top as i64);
+		top += page.height();
+	}
+	strip
+}
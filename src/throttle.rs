@@ -0,0 +1,92 @@
+//! Pages transcoded at once across all archives, adjustable while running:
+//! `SIGUSR1` lowers the limit by one and `SIGUSR2` raises it, the limit of 0 pauses encoding.
+//! Pages already transcoding are finished.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+
+struct Throttle {
+	semaphore: Semaphore,
+	/// Current limit, permits taken away by [`lower`] are not in the semaphore.
+	limit: AtomicUsize,
+}
+
+
+/// Page being transcoded until dropped.
+pub struct Slot(#[allow(dead_code)] SemaphorePermit<'static>);
+
+
+/// Limit pages transcoded at once to `jobs` and listen to signals changing it.
+pub fn init(jobs: usize) {
+	let jobs = jobs.max(1);
+	let throttle = Throttle { semaphore: Semaphore::new(jobs),
+	                          limit: AtomicUsize::new(jobs) };
+	if THROTTLE.set(throttle).is_err() {
+		return;
+	}
+	#[cfg(unix)]
+	tokio::spawn(listen());
+}
+
+
+/// Wait for a free slot, `None` if there is no limit.
+pub async fn slot() -> Option<Slot> {
+	let permit = THROTTLE.get()?.semaphore.acquire().await.ok()?;
+	Some(Slot(permit))
+}
+
+
+/// Allow one more page at once, resuming if paused.
+pub fn raise() {
+	let Some(throttle) = THROTTLE.get() else {
+		return;
+	};
+	let limit = throttle.limit.fetch_add(1, Ordering::SeqCst) + 1;
+	throttle.semaphore.add_permits(1);
+	match limit {
+		1 => warn!("Resumed with 1 job"),
+		limit => warn!("Jobs raised to {limit}"),
+	}
+}
+
+
+/// Allow one page less at once, pausing at 0. The slot is taken away when a page releases it.
+pub fn lower() {
+	let Some(throttle) = THROTTLE.get() else {
+		return;
+	};
+	let lowered = throttle.limit.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |limit| limit.checked_sub(1));
+	match lowered {
+		Ok(1) => warn!("Paused after pages in progress, raise jobs to resume"),
+		Ok(limit) => warn!("Jobs lowered to {}", limit - 1),
+		Err(_) => return warn!("Already paused"),
+	}
+	tokio::spawn(async move {
+		if let Ok(permit) = throttle.semaphore.acquire().await {
+			permit.forget();
+		}
+	});
+}
+
+
+#[cfg(unix)]
+async fn listen() {
+	use tokio::signal::unix::{signal, SignalKind};
+
+	let (mut lower_signal, mut raise_signal) = match (signal(SignalKind::user_defined1()), signal(SignalKind::user_defined2())) {
+		(Ok(lower), Ok(raise)) => (lower, raise),
+		(Err(err), _) | (_, Err(err)) => return warn!("Unable to listen to signals adjusting jobs: {err}"),
+	};
+	loop {
+		tokio::select! {
+			Some(_) = lower_signal.recv() => lower(),
+			Some(_) = raise_signal.recv() => raise(),
+			else => return,
+		}
+	}
+}
@@ -0,0 +1,118 @@
+//! Archives converted at once, adjusted while running within `--jobs-fs`.
+//!
+//! Time spent decoding and encoding on the encode threads, reading sources and writing outputs is measured,
+//! and every [`PERIOD`] the bottleneck decides: idle encode threads while pages are read and written
+//! start one more archive, saturated encode threads let one archive less run, which saves memory.
+//! Pages decoded and encoded at once are limited by `--max-cpu` separately, see [`crate::throttle`].
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+
+/// How often the bottleneck is checked.
+pub const PERIOD: Duration = Duration::from_secs(2);
+
+/// Encode threads are idle below this share of their time.
+const IDLE: f64 = 0.75;
+
+/// Encode threads are saturated above this share of their time.
+const SATURATED: f64 = 0.95;
+
+static TUNER: OnceLock<Tuner> = OnceLock::new();
+
+
+#[derive(Copy, Clone, Debug)]
+pub enum Stage {
+	/// Reading entries of sources.
+	Read,
+	/// Decoding and encoding pages on the encode threads.
+	Transcode,
+	/// Writing pages to outputs.
+	Write,
+}
+
+
+struct Tuner {
+	archives: Semaphore,
+	/// Archives allowed at once, permits taken away when lowering are not in the semaphore.
+	limit: AtomicUsize,
+	max: usize,
+	/// Encode threads available to pages, all of them busy is 100%.
+	threads: usize,
+	/// Microseconds spent in each [`Stage`] since the last check.
+	busy: [AtomicU64; 3],
+}
+
+
+/// Archive being converted until dropped.
+pub struct Slot(#[allow(dead_code)] SemaphorePermit<'static>);
+
+
+/// Start with one archive at once, adjusted up to `max` by the usage of `threads` encode threads.
+pub fn init(max: usize, threads: usize) {
+	let max = max.max(1);
+	let tuner = Tuner { archives: Semaphore::new(1),
+	                    limit: AtomicUsize::new(1),
+	                    max,
+	                    threads: threads.max(1),
+	                    busy: Default::default() };
+	if TUNER.set(tuner).is_err() || max == 1 {
+		return;
+	}
+	debug!("archives at once: 1 up to {max}");
+	tokio::spawn(run());
+}
+
+
+/// Wait for the archive's turn, `None` if archives are not limited.
+pub async fn slot() -> Option<Slot> {
+	let permit = TUNER.get()?.archives.acquire().await.ok()?;
+	Some(Slot(permit))
+}
+
+
+/// Count time spent in the stage.
+pub fn record(stage: Stage, elapsed: Duration) {
+	if let Some(tuner) = TUNER.get() {
+		tuner.busy[stage as usize].fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+	}
+}
+
+
+async fn run() {
+	let Some(tuner) = TUNER.get() else {
+		return;
+	};
+	let mut interval = tokio::time::interval(PERIOD);
+	interval.tick().await;
+	loop {
+		interval.tick().await;
+		let [read, transcode, write] = tuner.busy.each_ref().map(|busy| busy.swap(0, Ordering::Relaxed) as f64);
+		let period = PERIOD.as_micros() as f64;
+		let usage = transcode / (period * tuner.threads as f64);
+		let limit = tuner.limit.load(Ordering::SeqCst);
+		trace!(
+		       "usage of encode threads: {:.0}%, reading: {:.1}s, writing: {:.1}s, archives: {limit}",
+		       usage * 100.0,
+		       read / 1e6,
+		       write / 1e6
+		);
+		if usage < IDLE && read + write > 0.0 && limit < tuner.max {
+			let bottleneck = if read > write { "reading" } else { "writing" };
+			tuner.limit.fetch_add(1, Ordering::SeqCst);
+			tuner.archives.add_permits(1);
+			debug!("bottleneck is {bottleneck}, archives at once raised to {}", limit + 1);
+		} else if usage > SATURATED && limit > 1 {
+			tuner.limit.fetch_sub(1, Ordering::SeqCst);
+			// the slot is taken away when an archive releases it:
+			tokio::spawn(async move {
+				if let Ok(permit) = tuner.archives.acquire().await {
+					permit.forget();
+				}
+			});
+			debug!("bottleneck is encoding, archives at once lowered to {}", limit - 1);
+		}
+	}
+}
@@ -0,0 +1,117 @@
+//! Multi-volume output for `--split-size`.
+//!
+//! Output rolls over to the next volume (`name.part2.cbz`, ...) on page boundaries,
+//! so every volume is a complete archive which can be opened on its own.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::cap;
+use crate::cli::Config;
+use crate::error::Error;
+use crate::paths;
+use crate::ArchiveWriter;
+
+
+/// Estimated size of headers of an entry in addition to its data and name.
+const ENTRY_OVERHEAD: u64 = 128;
+
+
+pub struct VolumeWriter {
+	writer: ArchiveWriter,
+	path: PathBuf,
+	force: bool,
+	cfg: Config,
+	/// Archive comment, repeated in every volume
+	comment: Option<String>,
+
+	/// Current volume number, from 1
+	volume: usize,
+	/// Estimated size of the current volume
+	written: u64,
+	/// Total size of closed volumes
+	closed: u64,
+	/// Written bytes counted for `--max-total-output`
+	charge: cap::Charge,
+}
+
+
+impl VolumeWriter {
+	pub async fn open(path: impl AsRef<Path>, force: bool, cfg: &Config) -> Result<Self, Error> {
+		let path = path.as_ref().to_owned();
+		let writer = ArchiveWriter::open(&path, force, cfg).await?;
+		Ok(Self { writer,
+		          path,
+		          force,
+		          cfg: cfg.clone(),
+		          comment: None,
+		          volume: 1,
+		          written: 0,
+		          closed: 0,
+		          charge: Default::default() })
+	}
+
+
+	pub async fn write_all(&mut self,
+	                       name: &str,
+	                       data: &[u8],
+	                       source_hash: Option<&str>,
+	                       modified: Option<SystemTime>)
+	                       -> Result<(), Error> {
+		let size = data.len() as u64 + name.len() as u64 + ENTRY_OVERHEAD;
+		if let Some(limit) = self.cfg.split_size {
+			if self.written > 0 && self.written + size > limit {
+				self.next_volume().await?;
+			}
+		}
+		self.charge.add(size)?;
+		self.writer.write_all(name, data, source_hash, modified).await?;
+		self.written += size;
+		Ok(())
+	}
+
+
+	async fn next_volume(&mut self) -> Result<(), Error> {
+		self.volume += 1;
+		let path = paths::volume_path(&self.path, self.volume);
+		info!("Starting volume {}: '{}'", self.volume, path.display());
+		let mut next = ArchiveWriter::open(&path, self.force, &self.cfg).await?;
+		if let Some(comment) = self.comment.clone() {
+			next.set_comment(comment);
+		}
+		let previous = std::mem::replace(&mut self.writer, next);
+		self.closed += previous.close().await?.len();
+		self.written = 0;
+		Ok(())
+	}
+
+
+	pub fn set_comment(&mut self, comment: String) {
+		self.writer.set_comment(comment.clone());
+		self.comment = Some(comment);
+	}
+
+
+	/// Remove closed volumes, the current one is removed on drop as incomplete.
+	/// Their bytes are not counted for `--max-total-output` anymore.
+	pub async fn discard(self) {
+		for volume in 1..self.volume {
+			let path = match volume {
+				1 => self.path.clone(),
+				n => paths::volume_path(&self.path, n),
+			};
+			debug!("removing volume {volume}: '{}'", path.display());
+			if let Err(err) = tokio::fs::remove_file(&path).await {
+				warn!("Unable to remove '{}': {err}", path.display());
+			}
+		}
+	}
+
+
+	/// Close the last volume, returns total size of all volumes.
+	pub async fn close(self) -> Result<u64, Error> {
+		let size = self.closed + self.writer.close().await?.len();
+		self.charge.keep();
+		Ok(size)
+	}
+}
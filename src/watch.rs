@@ -0,0 +1,111 @@
+//! `watch` command: convert archives dropped into folders, e.g. a downloads folder.
+//!
+//! Folders are scanned every `--interval` seconds instead of subscribing to file system events,
+//! which also works for network shares, and a scan is needed anyway to know
+//! when a copied archive is complete.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::cli::WatchArgs;
+use crate::converter::Converter;
+use crate::error::Error;
+use crate::paths;
+
+
+/// Size and modification time of an archive, it's complete when they stop changing.
+type Stamp = (u64, Option<SystemTime>);
+
+
+pub async fn run(args: &WatchArgs) -> Result<(), Error> {
+	let output_dir = match &args.output {
+		Some(dir) => dir.clone(),
+		None => std::env::current_dir()?,
+	};
+	tokio::fs::create_dir_all(&output_dir).await?;
+	// absolute, to leave out outputs and their working files from scans of folders around them:
+	let output_dir = tokio::fs::canonicalize(&output_dir).await?;
+	let mut dirs = Vec::with_capacity(args.dirs.len());
+	for dir in &args.dirs {
+		match tokio::fs::canonicalize(dir).await {
+			Ok(path) if path.is_dir() => dirs.push(path),
+			_ => return Err(format!("'{}' is not a directory", dir.display()).into()),
+		}
+	}
+
+	let config = args.config.clone();
+	let converter = Converter::builder().output_dir(output_dir.clone())
+	                                    .configure(|cfg| *cfg = config)
+	                                    .build()?;
+	info!("Watching {} folders, press Ctrl-C to stop", dirs.len());
+
+	// archives of the previous scan:
+	let mut seen = HashMap::new();
+	// converted or failed archives, tried again only when they change:
+	let mut done: HashMap<PathBuf, Stamp> = HashMap::new();
+	let mut ticks = tokio::time::interval(Duration::from_secs(args.interval));
+	loop {
+		ticks.tick().await;
+		let found = scan(&dirs, &output_dir, &args.input_ext).await;
+		let mut settled: Vec<_> = found.iter()
+		                               .filter(|(path, stamp)| {
+			                               seen.get(*path) == Some(*stamp) && done.get(*path) != Some(*stamp)
+		                               })
+		                               .map(|(path, stamp)| (path.clone(), *stamp))
+		                               .collect();
+		settled.sort();
+		// removed archives are converted again when they come back:
+		done.retain(|path, _| found.contains_key(path));
+		seen = found;
+
+		for (path, stamp) in settled {
+			convert(&converter, &path).await;
+			done.insert(path, stamp);
+		}
+	}
+}
+
+
+/// Archives in the folders with their stamps, outside of the output directory.
+async fn scan(dirs: &[PathBuf], output_dir: &Path, exts: &[String]) -> HashMap<PathBuf, Stamp> {
+	let mut found = HashMap::new();
+	for dir in dirs {
+		let paths = match paths::walk_dir(dir, exts).await {
+			Ok(paths) => paths,
+			Err(err) => {
+				warn!("Unable to scan '{}': {err}", dir.display());
+				continue;
+			},
+		};
+		for path in paths.into_iter().filter(|path| !path.starts_with(output_dir)) {
+			// removed since listed:
+			if let Ok(meta) = tokio::fs::metadata(&path).await {
+				found.insert(path, (meta.len(), meta.modified().ok()));
+			}
+		}
+	}
+	found
+}
+
+
+async fn convert(converter: &Converter, path: &Path) {
+	info!("Converting: {}", path.display());
+	match converter.convert(path).await {
+		Ok(report) => {
+			info!(
+			      "Finished: {}, new size: {}b vs. {}b",
+			      report.output.display(),
+			      report.output_size,
+			      report.source_size
+			);
+			if !report.failed_pages.is_empty() {
+				warn!("{} pages failed in {}, the output is incomplete", report.failed_pages.len(), path.display());
+			}
+		},
+		Err(Error::Exists(output)) => {
+			info!("Skipped: {}, output {} already exists", path.display(), output.display());
+		},
+		Err(err) => error!("{}: {err}", path.display()),
+	}
+}
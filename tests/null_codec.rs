@@ -0,0 +1,63 @@
+//! Conversion of a whole archive by the binary with `-f null`, which writes raw pixels,
+//! so the pipeline is tested without codec costs: `cargo test --features null-codec`.
+
+#![cfg(feature = "null-codec")]
+
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+
+/// Archive with two pages of different sizes and a text file.
+fn fixture(path: &Path) {
+	let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+	let options = zip::write::FileOptions::default();
+	for (name, width, height) in [("p01.png", 4, 6), ("p02.png", 6, 4)] {
+		let mut png = Vec::new();
+		image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]))
+			.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+			.unwrap();
+		zip.start_file(name, options).unwrap();
+		zip.write_all(&png).unwrap();
+	}
+	zip.start_file("info.txt", options).unwrap();
+	zip.write_all(b"fixture").unwrap();
+	zip.finish().unwrap();
+}
+
+
+#[test]
+fn convert_with_null_codec() {
+	let dir = std::env::temp_dir().join(format!("comic-repack-null-codec-{}", std::process::id()));
+	let (input, output) = (dir.join("input.cbz"), dir.join("out"));
+	std::fs::create_dir_all(&output).unwrap();
+	fixture(&input);
+
+	// the failures file and the journal are written to the working directory:
+	let status = Command::new(env!("CARGO_BIN_EXE_comic-repack")).current_dir(&dir)
+	                                                            .arg(&input)
+	                                                            .args(["-f", "null", "--progress", "none", "--"])
+	                                                            .arg(&output)
+	                                                            .status()
+	                                                            .unwrap();
+	assert!(status.success());
+
+	let mut archive = zip::ZipArchive::new(File::open(output.join("input.cbz")).unwrap()).unwrap();
+	let mut names: Vec<_> = archive.file_names().map(ToOwned::to_owned).collect();
+	names.sort();
+	assert_eq!(names, ["info.txt", "p01.null", "p02.null"]);
+
+	for (name, header, pixels) in [("p01.null", "NULL\n4 6 Rgb8\n", 4 * 6), ("p02.null", "NULL\n6 4 Rgb8\n", 6 * 4)] {
+		let mut page = Vec::new();
+		archive.by_name(name).unwrap().read_to_end(&mut page).unwrap();
+		assert!(page.starts_with(header.as_bytes()), "{name}: {:?}", String::from_utf8_lossy(&page[..page.len().min(16)]));
+		assert_eq!(page.len(), header.len() + pixels * 3);
+		assert!(page[header.len()..].chunks(3).all(|pixel| pixel == [200, 100, 50]));
+	}
+	let mut text = String::new();
+	archive.by_name("info.txt").unwrap().read_to_string(&mut text).unwrap();
+	assert_eq!(text, "fixture");
+
+	std::fs::remove_dir_all(&dir).ok();
+}